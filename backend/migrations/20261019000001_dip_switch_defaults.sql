@@ -0,0 +1,13 @@
+-- One row per default column of the service manual (destination / sub-model variants)
+CREATE TABLE IF NOT EXISTS dip_switch_defaults (
+    dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+    variant TEXT NOT NULL,
+    value TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (dip_switch_id, variant)
+);
+
+-- Carry over the single default stored so far
+INSERT INTO dip_switch_defaults (dip_switch_id, variant, value)
+SELECT id, 'default', default_val FROM dip_switches WHERE default_val IS NOT NULL
+ON CONFLICT (dip_switch_id, variant) DO NOTHING;
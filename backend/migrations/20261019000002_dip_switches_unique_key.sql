@@ -0,0 +1,10 @@
+-- Keep one row per (model, switch, bit) so imports can upsert instead of delete + insert
+DELETE FROM dip_switches a
+USING dip_switches b
+WHERE a.model_name = b.model_name
+  AND a.switch_number = b.switch_number
+  AND a.bit_number = b.bit_number
+  AND a.created_at < b.created_at;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_dip_switches_model_switch_bit
+    ON dip_switches (model_name, switch_number, bit_number);
@@ -0,0 +1,10 @@
+-- Multi-bit fields: bit_number is the first bit, end_bit the last one (NULL for single-bit switches)
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS end_bit INTEGER;
+
+-- Value table of a multi-bit field; value reads the bit range with bit_number as the lowest bit
+CREATE TABLE IF NOT EXISTS dip_switch_values (
+    dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+    value INTEGER NOT NULL,
+    meaning TEXT,
+    PRIMARY KEY (dip_switch_id, value)
+);
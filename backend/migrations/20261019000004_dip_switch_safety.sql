@@ -0,0 +1,28 @@
+-- Safety annotations
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS risk_level TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS temporary_only BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS risk_note TEXT;
+
+-- Dependency rules: while the switch is at when_value, the target must (requires) / must not (conflicts) be at target_value
+CREATE TABLE IF NOT EXISTS dip_switch_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+    when_value INTEGER NOT NULL DEFAULT 1,
+    kind TEXT NOT NULL DEFAULT 'requires' CHECK (kind IN ('requires', 'conflicts')),
+    target_switch INTEGER NOT NULL,
+    target_bit INTEGER NOT NULL,
+    target_value INTEGER NOT NULL,
+    message TEXT
+);
+
+-- Saved DIP switch settings per model
+CREATE TABLE IF NOT EXISTS dip_switch_profiles (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    model_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    variant TEXT,
+    settings JSONB NOT NULL DEFAULT '[]',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (model_name, name)
+);
@@ -0,0 +1,103 @@
+-- Change tracking for delta sync: every row remembers the transaction that last changed it,
+-- deletes leave a tombstone. Sync tokens are transaction ids (see /api/sync/changes).
+
+CREATE TABLE IF NOT EXISTS tombstones (
+    id BIGSERIAL PRIMARY KEY,
+    entity TEXT NOT NULL,
+    entity_key JSONB NOT NULL,
+    deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    change_xid BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_tombstones_change_xid ON tombstones (change_xid);
+
+-- Stamps inserts and real updates; an upsert that changes nothing keeps the old stamp
+CREATE OR REPLACE FUNCTION track_change() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE'
+       AND (to_jsonb(NEW) - 'updated_at' - 'change_xid') = (to_jsonb(OLD) - 'updated_at' - 'change_xid') THEN
+        RETURN NEW;
+    END IF;
+    NEW.updated_at := NOW();
+    NEW.change_xid := pg_current_xact_id()::text::bigint;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+-- Trigger arguments are the key columns stored in the tombstone
+CREATE OR REPLACE FUNCTION record_tombstone() RETURNS trigger AS $$
+DECLARE
+    key JSONB := '{}';
+    col TEXT;
+BEGIN
+    FOREACH col IN ARRAY TG_ARGV LOOP
+        key := key || jsonb_build_object(col, to_jsonb(OLD) -> col);
+    END LOOP;
+    INSERT INTO tombstones (entity, entity_key, change_xid)
+    VALUES (TG_TABLE_NAME, key, pg_current_xact_id()::text::bigint);
+    RETURN OLD;
+END
+$$ LANGUAGE plpgsql;
+
+-- Defaults, value tables and rules are part of their switch
+CREATE OR REPLACE FUNCTION touch_dip_switch() RETURNS trigger AS $$
+DECLARE
+    switch_id UUID;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        switch_id := OLD.dip_switch_id;
+    ELSE
+        switch_id := NEW.dip_switch_id;
+    END IF;
+    UPDATE dip_switches
+    SET change_xid = pg_current_xact_id()::text::bigint, updated_at = NOW()
+    WHERE id = switch_id AND change_xid <> pg_current_xact_id()::text::bigint;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE printers ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE printers ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_printers_change_xid ON printers (change_xid);
+DROP TRIGGER IF EXISTS printers_track_change ON printers;
+CREATE TRIGGER printers_track_change BEFORE INSERT OR UPDATE ON printers FOR EACH ROW EXECUTE FUNCTION track_change();
+DROP TRIGGER IF EXISTS printers_tombstone ON printers;
+CREATE TRIGGER printers_tombstone AFTER DELETE ON printers FOR EACH ROW EXECUTE FUNCTION record_tombstone('id');
+
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_error_codes_change_xid ON error_codes (change_xid);
+DROP TRIGGER IF EXISTS error_codes_track_change ON error_codes;
+CREATE TRIGGER error_codes_track_change BEFORE INSERT OR UPDATE ON error_codes FOR EACH ROW EXECUTE FUNCTION track_change();
+DROP TRIGGER IF EXISTS error_codes_tombstone ON error_codes;
+CREATE TRIGGER error_codes_tombstone AFTER DELETE ON error_codes FOR EACH ROW EXECUTE FUNCTION record_tombstone('id');
+
+ALTER TABLE spare_parts ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE spare_parts ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_spare_parts_change_xid ON spare_parts (change_xid);
+DROP TRIGGER IF EXISTS spare_parts_track_change ON spare_parts;
+CREATE TRIGGER spare_parts_track_change BEFORE INSERT OR UPDATE ON spare_parts FOR EACH ROW EXECUTE FUNCTION track_change();
+DROP TRIGGER IF EXISTS spare_parts_tombstone ON spare_parts;
+CREATE TRIGGER spare_parts_tombstone AFTER DELETE ON spare_parts FOR EACH ROW EXECUTE FUNCTION record_tombstone('id');
+
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_error_parts_change_xid ON error_parts (change_xid);
+DROP TRIGGER IF EXISTS error_parts_track_change ON error_parts;
+CREATE TRIGGER error_parts_track_change BEFORE INSERT OR UPDATE ON error_parts FOR EACH ROW EXECUTE FUNCTION track_change();
+DROP TRIGGER IF EXISTS error_parts_tombstone ON error_parts;
+CREATE TRIGGER error_parts_tombstone AFTER DELETE ON error_parts FOR EACH ROW EXECUTE FUNCTION record_tombstone('error_id', 'part_id');
+
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_dip_switches_change_xid ON dip_switches (change_xid);
+DROP TRIGGER IF EXISTS dip_switches_track_change ON dip_switches;
+CREATE TRIGGER dip_switches_track_change BEFORE INSERT OR UPDATE ON dip_switches FOR EACH ROW EXECUTE FUNCTION track_change();
+DROP TRIGGER IF EXISTS dip_switches_tombstone ON dip_switches;
+CREATE TRIGGER dip_switches_tombstone AFTER DELETE ON dip_switches FOR EACH ROW EXECUTE FUNCTION record_tombstone('id');
+
+DROP TRIGGER IF EXISTS dip_switch_defaults_touch ON dip_switch_defaults;
+CREATE TRIGGER dip_switch_defaults_touch AFTER INSERT OR UPDATE OR DELETE ON dip_switch_defaults FOR EACH ROW EXECUTE FUNCTION touch_dip_switch();
+DROP TRIGGER IF EXISTS dip_switch_values_touch ON dip_switch_values;
+CREATE TRIGGER dip_switch_values_touch AFTER INSERT OR UPDATE OR DELETE ON dip_switch_values FOR EACH ROW EXECUTE FUNCTION touch_dip_switch();
+DROP TRIGGER IF EXISTS dip_switch_rules_touch ON dip_switch_rules;
+CREATE TRIGGER dip_switch_rules_touch AFTER INSERT OR UPDATE OR DELETE ON dip_switch_rules FOR EACH ROW EXECUTE FUNCTION touch_dip_switch();
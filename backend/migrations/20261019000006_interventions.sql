@@ -0,0 +1,40 @@
+-- Service interventions: what a technician did on a machine visit. Linked codes and parts keep their
+-- text, so a record stays readable when the catalog entry is removed (the link is set to NULL).
+
+CREATE TABLE IF NOT EXISTS interventions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    serial_number TEXT NOT NULL,
+    model_name TEXT NOT NULL,
+    performed_on DATE NOT NULL,
+    technician TEXT NOT NULL,
+    minutes_spent INTEGER CHECK (minutes_spent >= 0),
+    outcome TEXT NOT NULL CHECK (outcome IN ('resolved', 'partially_resolved', 'unresolved', 'follow_up')),
+    notes TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_interventions_serial ON interventions (serial_number, performed_on);
+CREATE INDEX IF NOT EXISTS idx_interventions_performed_on ON interventions (performed_on);
+
+CREATE TABLE IF NOT EXISTS intervention_error_codes (
+    intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+    error_id UUID REFERENCES error_codes(id) ON DELETE SET NULL,
+    code TEXT NOT NULL,
+    PRIMARY KEY (intervention_id, code)
+);
+
+CREATE TABLE IF NOT EXISTS intervention_parts (
+    intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+    part_id UUID REFERENCES spare_parts(id) ON DELETE SET NULL,
+    oem_code TEXT NOT NULL,
+    quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+    PRIMARY KEY (intervention_id, oem_code)
+);
+
+CREATE TABLE IF NOT EXISTS intervention_dip_switches (
+    intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+    switch_number INTEGER NOT NULL,
+    bit_number INTEGER NOT NULL,
+    previous_value INTEGER,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (intervention_id, switch_number, bit_number)
+);
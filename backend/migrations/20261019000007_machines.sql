@@ -0,0 +1,16 @@
+-- Installed base: the physical machines we maintain, each of a catalog model (printers).
+
+CREATE TABLE IF NOT EXISTS machines (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    serial_number TEXT NOT NULL UNIQUE,
+    printer_id UUID NOT NULL REFERENCES printers(id),
+    customer TEXT,
+    site TEXT,
+    installed_on DATE,
+    firmware TEXT,
+    options JSONB NOT NULL DEFAULT '[]',
+    notes TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_machines_printer ON machines (printer_id);
@@ -0,0 +1,9 @@
+-- Learned part rankings: each error-code/part link keeps its catalog ranking, an optional hand-set
+-- override and the results of the recorded interventions that replaced the part (see /api/rankings/recalculate).
+
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS catalog_ranking INTEGER;
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS manual_ranking INTEGER CHECK (manual_ranking >= 1 AND manual_ranking <= 5);
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS ranking_source TEXT NOT NULL DEFAULT 'catalog';
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS fix_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS fix_successes INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS success_rate DOUBLE PRECISION;
@@ -0,0 +1,12 @@
+-- Technician feedback: "this fixed it" (or not) on an error code's solution, or on one part for it.
+
+CREATE TABLE IF NOT EXISTS error_feedback (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    error_id UUID NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+    part_id UUID REFERENCES spare_parts(id) ON DELETE CASCADE,
+    worked BOOLEAN NOT NULL,
+    technician TEXT NOT NULL,
+    comment TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_error_feedback_error ON error_feedback (error_id, created_at);
@@ -0,0 +1,18 @@
+-- Audit trail: one row per changed field, with who changed it and the source file.
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    user_name TEXT NOT NULL,
+    action TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    entity_id UUID,
+    model_name TEXT,
+    entity_key TEXT NOT NULL,
+    field TEXT,
+    old_value TEXT,
+    new_value TEXT,
+    source_file TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log (entity, model_name, entity_key, changed_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity_id ON audit_log (entity_id);
@@ -0,0 +1,26 @@
+-- Every revision of an error code's texts. Existing rows get a baseline revision.
+
+CREATE TABLE IF NOT EXISTS error_code_revisions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    error_id UUID NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    user_name TEXT,
+    source_file TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    code TEXT NOT NULL,
+    classification TEXT,
+    cause TEXT,
+    measures TEXT,
+    solution TEXT,
+    estimated_abnormal_parts TEXT,
+    correction TEXT,
+    faulty_part_isolation TEXT,
+    note TEXT,
+    UNIQUE (error_id, revision)
+);
+
+INSERT INTO error_code_revisions (error_id, revision, action, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note)
+SELECT e.id, 1, 'baseline', e.code, e.classification, e.cause, e.measures, e.solution, e.estimated_abnormal_parts, e.correction, e.faulty_part_isolation, e.note
+FROM error_codes e
+WHERE NOT EXISTS (SELECT 1 FROM error_code_revisions r WHERE r.error_id = e.id);
@@ -0,0 +1,20 @@
+-- One row per error code import; rows and revisions point at the batch that wrote them, so an import can be rolled back.
+
+CREATE TABLE IF NOT EXISTS import_batches (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    source_file TEXT,
+    content_hash TEXT NOT NULL,
+    user_name TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    total_rows INTEGER NOT NULL DEFAULT 0,
+    created_count INTEGER NOT NULL DEFAULT 0,
+    updated_count INTEGER NOT NULL DEFAULT 0,
+    unchanged_count INTEGER NOT NULL DEFAULT 0,
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    rolled_back_at TIMESTAMP WITH TIME ZONE,
+    rolled_back_by TEXT
+);
+
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS import_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_error_code_revisions_batch ON error_code_revisions (batch_id);
@@ -0,0 +1,9 @@
+-- Saved header mappings for error code imports: per import field, the source column names that hold it.
+
+CREATE TABLE IF NOT EXISTS column_mappings (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    columns JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- Per text field of an error code, whether an import or a manual edit wrote it last ({"note": "manual", ...}).
+
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS field_sources JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- Error codes an import no longer lists can be marked obsolete (soft-deleted) by that import; rolling the import back brings them back.
+
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS obsolete_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS obsolete_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
+ALTER TABLE import_batches ADD COLUMN IF NOT EXISTS obsolete_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+-- Where each error code comes from: the document, sheet and row or page of the import that last wrote it,
+-- and whether it was edited by hand since. Codes imported before are traced back to their batch.
+
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_kind TEXT;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_document TEXT;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_sheet TEXT;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_row INTEGER;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_page INTEGER;
+ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS imported_at TIMESTAMP WITH TIME ZONE;
+
+UPDATE error_codes e SET source_kind = 'sheet', source_document = b.source_file, imported_at = b.created_at
+FROM import_batches b
+WHERE b.id = e.import_batch_id AND e.source_kind IS NULL;
@@ -0,0 +1,16 @@
+-- The carry-over of 20261019000001 was re-run on every start by the inline migration and gave switches
+-- whose variants had been imported since an extra 'default' row. Drop those, and only carry over the
+-- single default of switches that have no variant yet.
+
+DELETE FROM dip_switch_defaults x
+WHERE x.variant = 'default'
+AND EXISTS (
+    SELECT 1 FROM dip_switch_defaults o
+    WHERE o.dip_switch_id = x.dip_switch_id AND o.variant <> 'default' AND o.position = x.position
+);
+
+INSERT INTO dip_switch_defaults (dip_switch_id, variant, value)
+SELECT d.id, 'default', d.default_val FROM dip_switches d
+WHERE d.default_val IS NOT NULL
+AND NOT EXISTS (SELECT 1 FROM dip_switch_defaults x WHERE x.dip_switch_id = d.id)
+ON CONFLICT (dip_switch_id, variant) DO NOTHING;
@@ -0,0 +1,5 @@
+-- The revision of an import that brought an obsolete error code back keeps since when, and by which
+-- import, the code was obsolete, so rolling the import back retires it again.
+
+ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS previous_obsolete_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS previous_obsolete_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL;
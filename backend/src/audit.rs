@@ -0,0 +1,239 @@
+//! Field-level audit trail of data changes: who changed what, from which value to which, and from
+//! which uploaded file. Write handlers compare the stored row with what they are about to write and
+//! record one entry per changed field.
+
+use crate::auth::Caller;
+use crate::models::{AuditEntry, DipSwitch, DipSwitchImport, ErrorCode, ErrorCodeRevision, Machine};
+use crate::repository::ErrorCodeFields;
+use uuid::Uuid;
+
+pub const ENTITY_ERROR_CODE: &str = "error_code";
+pub const ENTITY_DIPSWITCH: &str = "dip_switch";
+pub const ENTITY_MACHINE: &str = "machine";
+/// A part link of an error code (manual ranking)
+pub const ENTITY_ERROR_PART: &str = "error_part";
+
+pub const ACTION_CREATE: &str = "create";
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_DELETE: &str = "delete";
+/// Revision written by restoring an earlier one
+pub const ACTION_RESTORE: &str = "restore";
+/// Revision written by rolling back an import batch
+pub const ACTION_ROLLBACK: &str = "rollback";
+/// Error code marked obsolete by an import that no longer lists it
+pub const ACTION_OBSOLETE: &str = "obsolete";
+
+/// (field, old value, new value)
+pub type FieldChange = (&'static str, Option<String>, Option<String>);
+
+/// The row a set of changes belongs to.
+pub struct AuditTarget<'a> {
+    pub entity: &'static str,
+    pub entity_id: Option<Uuid>,
+    pub model_name: Option<&'a str>,
+    /// Readable key within the model: the error code, "switch-bit", the serial number
+    pub entity_key: &'a str,
+}
+
+/// Who is writing, and from which file. One per request.
+pub struct AuditContext {
+    pub user: String,
+    pub source_file: Option<String>,
+    /// Import batch the writes belong to
+    pub batch_id: Option<Uuid>,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl AuditContext {
+    pub fn new(caller: &Caller, source_file: Option<&str>) -> AuditContext {
+        AuditContext {
+            user: caller.name.clone(),
+            source_file: source_file.map(|f| f.to_string()),
+            batch_id: None,
+            changed_at: chrono::Utc::now(),
+        }
+    }
+
+    /// A new revision of an error code with the texts just written.
+    pub fn revision(&self, error_id: Uuid, action: &str, fields: &ErrorCodeFields) -> ErrorCodeRevision {
+        ErrorCodeRevision {
+            id: Uuid::new_v4(),
+            error_id,
+            revision: 0,
+            action: action.to_string(),
+            user_name: Some(self.user.clone()),
+            source_file: self.source_file.clone(),
+            batch_id: self.batch_id,
+            created_at: self.changed_at,
+            code: fields.code.to_string(),
+            classification: owned(fields.classification),
+            cause: owned(fields.cause),
+            measures: owned(fields.measures),
+            solution: owned(fields.solution),
+            estimated_abnormal_parts: owned(fields.estimated_abnormal_parts),
+            correction: owned(fields.correction),
+            faulty_part_isolation: owned(fields.faulty_part_isolation),
+            note: owned(fields.note),
+            previous_obsolete_at: None,
+            previous_obsolete_batch_id: None,
+        }
+    }
+
+    /// One entry per field whose value differs.
+    pub fn entries(&self, target: &AuditTarget, action: &str, fields: Vec<FieldChange>) -> Vec<AuditEntry> {
+        fields
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(field, old_value, new_value)| AuditEntry {
+                id: Uuid::new_v4(),
+                changed_at: self.changed_at,
+                user_name: self.user.clone(),
+                action: action.to_string(),
+                entity: target.entity.to_string(),
+                entity_id: target.entity_id,
+                model_name: target.model_name.map(|m| m.to_string()),
+                entity_key: target.entity_key.to_string(),
+                field: Some(field.to_string()),
+                old_value,
+                new_value,
+                source_file: self.source_file.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Column values of one row, in a fixed order per entity.
+type Values<const N: usize> = [(&'static str, Option<String>); N];
+
+/// Pairs up the columns of the stored and the written row (`None` for a row that doesn't exist on that side).
+fn changes<const N: usize>(old: Option<Values<N>>, new: Option<Values<N>>) -> Vec<FieldChange> {
+    let mut old = old.map(|v| v.into_iter());
+    let mut new = new.map(|v| v.into_iter());
+    (0..N)
+        .filter_map(|_| {
+            let old_column = old.as_mut().and_then(Iterator::next);
+            let new_column = new.as_mut().and_then(Iterator::next);
+            let field = old_column.as_ref().or(new_column.as_ref())?.0;
+            Some((field, old_column.and_then(|(_, v)| v), new_column.and_then(|(_, v)| v)))
+        })
+        .collect()
+}
+
+fn owned(value: Option<&str>) -> Option<String> {
+    value.map(|v| v.to_string())
+}
+
+fn error_code_values(error: &ErrorCode) -> Values<8> {
+    [
+        ("classification", error.classification.clone()),
+        ("cause", error.cause.clone()),
+        ("measures", error.measures.clone()),
+        ("solution", error.solution.clone()),
+        ("estimated_abnormal_parts", error.estimated_abnormal_parts.clone()),
+        ("correction", error.correction.clone()),
+        ("faulty_part_isolation", error.faulty_part_isolation.clone()),
+        ("note", error.note.clone()),
+    ]
+}
+
+/// Stored values of an error code against an import row (which replaces every column).
+pub fn error_code_changes(old: Option<&ErrorCode>, new: &ErrorCodeFields) -> Vec<FieldChange> {
+    let new = [
+        ("classification", owned(new.classification)),
+        ("cause", owned(new.cause)),
+        ("measures", owned(new.measures)),
+        ("solution", owned(new.solution)),
+        ("estimated_abnormal_parts", owned(new.estimated_abnormal_parts)),
+        ("correction", owned(new.correction)),
+        ("faulty_part_isolation", owned(new.faulty_part_isolation)),
+        ("note", owned(new.note)),
+    ];
+    changes(old.map(error_code_values), Some(new))
+}
+
+fn revision_values(revision: &ErrorCodeRevision) -> Values<8> {
+    [
+        ("classification", revision.classification.clone()),
+        ("cause", revision.cause.clone()),
+        ("measures", revision.measures.clone()),
+        ("solution", revision.solution.clone()),
+        ("estimated_abnormal_parts", revision.estimated_abnormal_parts.clone()),
+        ("correction", revision.correction.clone()),
+        ("faulty_part_isolation", revision.faulty_part_isolation.clone()),
+        ("note", revision.note.clone()),
+    ]
+}
+
+/// Every text column of two revisions of the same error code.
+pub fn revision_changes(from: &ErrorCodeRevision, to: &ErrorCodeRevision) -> Vec<FieldChange> {
+    changes(Some(revision_values(from)), Some(revision_values(to)))
+}
+
+/// The stored row against a revision about to be restored.
+pub fn restore_changes(current: &ErrorCode, revision: &ErrorCodeRevision) -> Vec<FieldChange> {
+    changes(Some(error_code_values(current)), Some(revision_values(revision)))
+}
+
+/// The texts of a revision, to write them back.
+pub fn revision_fields(revision: &ErrorCodeRevision) -> ErrorCodeFields<'_> {
+    ErrorCodeFields {
+        code: &revision.code,
+        classification: revision.classification.as_deref(),
+        cause: revision.cause.as_deref(),
+        measures: revision.measures.as_deref(),
+        solution: revision.solution.as_deref(),
+        estimated_abnormal_parts: revision.estimated_abnormal_parts.as_deref(),
+        correction: revision.correction.as_deref(),
+        faulty_part_isolation: revision.faulty_part_isolation.as_deref(),
+        note: revision.note.as_deref(),
+    }
+}
+
+fn dipswitch_values(switch: &DipSwitch) -> Values<8> {
+    [
+        ("function_name", switch.function_name.clone()),
+        ("setting_0", switch.setting_0.clone()),
+        ("setting_1", switch.setting_1.clone()),
+        ("default_val", switch.default_val.clone()),
+        ("end_bit", switch.end_bit.map(|b| b.to_string())),
+        ("risk_level", Some(switch.risk_level.clone())),
+        ("temporary_only", Some(switch.temporary_only.to_string())),
+        ("risk_note", switch.risk_note.clone()),
+    ]
+}
+
+/// Main columns of a DIP switch entry (default variants, value tables and rules are not tracked).
+/// `new` is `None` for entries removed by a pruning import.
+pub fn dipswitch_changes(old: Option<&DipSwitch>, new: Option<&DipSwitchImport>) -> Vec<FieldChange> {
+    let new = new.map(|item| {
+        [
+            ("function_name", item.function_name.clone()),
+            ("setting_0", item.setting_0.clone()),
+            ("setting_1", item.setting_1.clone()),
+            ("default_val", item.primary_default()),
+            ("end_bit", item.end_bit.map(|b| b.to_string())),
+            ("risk_level", Some(item.risk_level.clone().unwrap_or_else(|| "none".to_string()))),
+            ("temporary_only", Some(item.temporary_only.to_string())),
+            ("risk_note", item.risk_note.clone()),
+        ]
+    });
+    changes(old.map(dipswitch_values), new)
+}
+
+fn machine_values(machine: &Machine) -> Values<8> {
+    [
+        ("serial_number", Some(machine.serial_number.clone())),
+        ("model_name", Some(machine.model_name.clone())),
+        ("customer", machine.customer.clone()),
+        ("site", machine.site.clone()),
+        ("installed_on", machine.installed_on.map(|d| d.to_string())),
+        ("firmware", machine.firmware.clone()),
+        ("options", serde_json::to_string(&machine.options.0).ok()),
+        ("notes", machine.notes.clone()),
+    ]
+}
+
+/// Machine columns, `None` on either side for created or deleted machines.
+pub fn machine_changes(old: Option<&Machine>, new: Option<&Machine>) -> Vec<FieldChange> {
+    changes(old.map(machine_values), new.map(machine_values))
+}
@@ -0,0 +1,396 @@
+//! API keys and signed tokens, and the role checks the router applies per route group.
+//!
+//! Callers send `Authorization: Bearer <key or token>` (or `X-API-Key: <key>`). Keys are stored hashed
+//! and never expire until revoked; tokens are issued for a key by `/api/auth/token`, carry their role
+//! and expire after `TOKEN_TTL_HOURS`, or as soon as their key is revoked (or, for the `ADMIN_API_KEY`
+//! bootstrap key, replaced).
+
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Prefix of generated API keys, so tokens and keys can be told apart.
+pub const KEY_PREFIX: &str = "atk_";
+const DEFAULT_TOKEN_TTL_HOURS: i64 = 12;
+
+/// Access levels, each including the ones before it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Catalog reads
+    ReadOnly,
+    /// Plus interventions, machines, feedback and DIP switch profiles
+    Technician,
+    /// Plus imports, rankings and machine records
+    Editor,
+    /// Plus API key management
+    Admin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim() {
+            "read-only" => Some(Role::ReadOnly),
+            "technician" => Some(Role::Technician),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read-only",
+            Role::Technician => "technician",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Who is making the request, added to the request extensions by [`authenticate`].
+#[derive(Serialize, Debug, Clone)]
+pub struct Caller {
+    /// Key name, or the key name a token was issued for
+    pub name: String,
+    pub role: Role,
+    /// None for the `ADMIN_API_KEY` bootstrap key
+    pub key_id: Option<Uuid>,
+}
+
+pub struct AuthConfig {
+    /// Catalog reads (printers, errors, DIP switches, exports) without credentials
+    pub public_catalog: bool,
+    token_secret: Vec<u8>,
+    /// Hash of `ADMIN_API_KEY`, for creating the first keys
+    admin_key_hash: Option<String>,
+    pub token_ttl_hours: i64,
+}
+
+impl AuthConfig {
+    /// `PUBLIC_CATALOG` (default on), `AUTH_TOKEN_SECRET`, `ADMIN_API_KEY`, `TOKEN_TTL_HOURS`.
+    pub fn from_env() -> AuthConfig {
+        let public_catalog = std::env::var("PUBLIC_CATALOG")
+            .map(|v| !matches!(v.trim(), "0" | "false"))
+            .unwrap_or(true);
+        let token_secret = match std::env::var("AUTH_TOKEN_SECRET") {
+            Ok(secret) if !secret.trim().is_empty() => secret.trim().as_bytes().to_vec(),
+            _ => {
+                tracing::warn!("AUTH_TOKEN_SECRET not set: issued tokens stop working on restart");
+                [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat()
+            }
+        };
+        let admin_key_hash = std::env::var("ADMIN_API_KEY")
+            .ok()
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty())
+            .map(|k| hash_key(&k));
+        let token_ttl_hours = std::env::var("TOKEN_TTL_HOURS")
+            .ok()
+            .and_then(|h| h.trim().parse().ok())
+            .filter(|h| *h > 0)
+            .unwrap_or(DEFAULT_TOKEN_TTL_HOURS);
+        AuthConfig { public_catalog, token_secret, admin_key_hash, token_ttl_hours }
+    }
+
+    /// Short digest of the configured `ADMIN_API_KEY`, carried by the tokens issued for it.
+    fn admin_key_fingerprint(&self) -> Option<String> {
+        self.admin_key_hash.as_deref().map(|hash| hash_key(hash)[..16].to_string())
+    }
+}
+
+/// A new random API key (shown once, stored only as its hash).
+pub fn generate_key() -> String {
+    format!("{}{}{}", KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenClaims {
+    sub: String,
+    role: Role,
+    key_id: Option<Uuid>,
+    /// For tokens of the `ADMIN_API_KEY` bootstrap key: fingerprint of that key, so rotating it ends them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    admin_key: Option<String>,
+    /// Unix seconds
+    exp: i64,
+}
+
+fn signature(secret: &[u8], payload: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `<claims>.<signature>`, both base64url. Returns the token and its expiry.
+pub fn issue_token(config: &AuthConfig, caller: &Caller, role: Role) -> (String, chrono::DateTime<chrono::Utc>) {
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(config.token_ttl_hours);
+    let claims = TokenClaims {
+        sub: caller.name.clone(),
+        role,
+        key_id: caller.key_id,
+        admin_key: caller.key_id.map_or_else(|| config.admin_key_fingerprint(), |_| None),
+        exp: expires_at.timestamp(),
+    };
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap_or_default());
+    let signature = URL_SAFE_NO_PAD.encode(signature(&config.token_secret, &payload));
+    (format!("{}.{}", payload, signature), expires_at)
+}
+
+fn verify_token(config: &AuthConfig, token: &str) -> Option<Caller> {
+    let (payload, signature) = token.split_once('.')?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&config.token_secret).ok()?;
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+    let claims: TokenClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    // Keys are checked against the database by the caller; the bootstrap key must still be the configured one
+    if claims.key_id.is_none() && (claims.admin_key.is_none() || claims.admin_key != config.admin_key_fingerprint()) {
+        return None;
+    }
+    (claims.exp > chrono::Utc::now().timestamp()).then_some(Caller {
+        name: claims.sub,
+        role: claims.role,
+        key_id: claims.key_id,
+    })
+}
+
+fn credentials(req: &Request) -> Option<String> {
+    let headers = req.headers();
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn reject(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "success": false, "message": message }))).into_response()
+}
+
+async fn key_active(state: &AppState, id: Uuid) -> bool {
+    state.db.api_key(id).await
+        .map_err(|e| {
+            tracing::error!("Failed to look up API key: {:?}", e);
+            e
+        })
+        .is_ok_and(|key| key.is_some())
+}
+
+/// Resolves the credentials, if any, to a [`Caller`]. Requests without credentials pass through
+/// anonymous; wrong credentials are rejected here so a typo doesn't look like missing access.
+pub async fn authenticate(State(state): State<Arc<AppState>>, mut req: Request, next: Next) -> Response {
+    let Some(credential) = credentials(&req) else {
+        return next.run(req).await;
+    };
+
+    let caller = if credential.starts_with(KEY_PREFIX) {
+        let hash = hash_key(&credential);
+        if state.auth.admin_key_hash.as_deref() == Some(hash.as_str()) {
+            Some(Caller { name: "admin".to_string(), role: Role::Admin, key_id: None })
+        } else {
+            match state.db.api_key_by_hash(&hash).await {
+                Ok(key) => key.and_then(|k| {
+                    Role::parse(&k.role).map(|role| Caller { name: k.name, role, key_id: Some(k.id) })
+                }),
+                Err(e) => {
+                    tracing::error!("Failed to look up API key: {:?}", e);
+                    return reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check credentials".to_string());
+                }
+            }
+        }
+    } else {
+        match verify_token(&state.auth, &credential) {
+            Some(Caller { key_id: Some(id), .. }) if !key_active(&state, id).await => None,
+            caller => caller,
+        }
+    };
+
+    match caller {
+        Some(caller) => {
+            req.extensions_mut().insert(caller);
+            next.run(req).await
+        }
+        None => reject(StatusCode::UNAUTHORIZED, "Invalid or expired API key or token".to_string()),
+    }
+}
+
+/// Lets the request through only for callers with at least `role`.
+pub async fn require(role: Role, req: Request, next: Next) -> Response {
+    match req.extensions().get::<Caller>() {
+        None => reject(StatusCode::UNAUTHORIZED, "Authentication required".to_string()),
+        Some(caller) if caller.role < role => {
+            reject(StatusCode::FORBIDDEN, format!("Requires the {} role", role.as_str()))
+        }
+        Some(_) => next.run(req).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn config(admin_key: Option<&str>) -> AuthConfig {
+        AuthConfig {
+            public_catalog: true,
+            token_secret: b"test secret".to_vec(),
+            admin_key_hash: admin_key.map(hash_key),
+            token_ttl_hours: 1,
+        }
+    }
+
+    fn key_caller(role: Role) -> Caller {
+        Caller { name: "workshop".to_string(), role, key_id: Some(Uuid::new_v4()) }
+    }
+
+    fn bootstrap_caller() -> Caller {
+        Caller { name: "admin".to_string(), role: Role::Admin, key_id: None }
+    }
+
+    #[test]
+    fn roles_include_the_ones_before() {
+        assert!(Role::ReadOnly < Role::Technician);
+        assert!(Role::Technician < Role::Editor);
+        assert!(Role::Editor < Role::Admin);
+        for role in [Role::ReadOnly, Role::Technician, Role::Editor, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse(" editor "), Some(Role::Editor));
+        assert_eq!(Role::parse("root"), None);
+    }
+
+    #[test]
+    fn issued_token_verifies_with_its_role() {
+        let config = config(None);
+        let caller = key_caller(Role::Editor);
+        let (token, expires_at) = issue_token(&config, &caller, Role::Technician);
+        let verified = verify_token(&config, &token).unwrap();
+        assert_eq!(verified.name, "workshop");
+        assert_eq!(verified.role, Role::Technician);
+        assert_eq!(verified.key_id, caller.key_id);
+        assert!(expires_at > chrono::Utc::now());
+    }
+
+    #[test]
+    fn token_with_bad_signature_is_rejected() {
+        let config = config(None);
+        let (token, _) = issue_token(&config, &key_caller(Role::Technician), Role::Technician);
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        // Claims raised to admin under the original signature
+        let mut claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        claims["role"] = serde_json::json!("admin");
+        let forged = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        assert!(verify_token(&config, &format!("{}.{}", forged, signature)).is_none());
+
+        // Signed with another secret
+        let other = AuthConfig { token_secret: b"other secret".to_vec(), ..self::config(None) };
+        let (foreign, _) = issue_token(&other, &key_caller(Role::Technician), Role::Technician);
+        assert!(verify_token(&config, &foreign).is_none());
+
+        assert!(verify_token(&config, payload).is_none());
+        assert!(verify_token(&config, &format!("{}.not-base64!", payload)).is_none());
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let config = AuthConfig { token_ttl_hours: -1, ..config(None) };
+        let (token, _) = issue_token(&config, &key_caller(Role::Editor), Role::Editor);
+        assert!(verify_token(&config, &token).is_none());
+    }
+
+    #[test]
+    fn bootstrap_token_ends_when_admin_key_changes() {
+        let (token, _) = issue_token(&config(Some("atk_first")), &bootstrap_caller(), Role::Admin);
+        assert_eq!(verify_token(&config(Some("atk_first")), &token).map(|c| c.role), Some(Role::Admin));
+        assert!(verify_token(&config(Some("atk_second")), &token).is_none());
+        assert!(verify_token(&config(None), &token).is_none());
+    }
+
+    /// `GET /` for callers with at least `role`, `caller` put in the request as `authenticate` would.
+    fn guarded(role: Role, caller: Option<Caller>) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(move |req, next| require(role, req, next)))
+            .layer(middleware::from_fn(move |mut req: Request, next: Next| {
+                if let Some(caller) = caller.clone() {
+                    req.extensions_mut().insert(caller);
+                }
+                next.run(req)
+            }))
+    }
+
+    async fn status(router: Router, request: axum::http::Request<Body>) -> StatusCode {
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    fn get_root() -> axum::http::Request<Body> {
+        axum::http::Request::get("/").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn require_checks_the_role() {
+        assert_eq!(status(guarded(Role::Editor, None), get_root()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(guarded(Role::Editor, Some(key_caller(Role::Technician))), get_root()).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(guarded(Role::Editor, Some(key_caller(Role::Editor))), get_root()).await, StatusCode::OK);
+        assert_eq!(status(guarded(Role::Editor, Some(key_caller(Role::Admin))), get_root()).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn revoked_key_and_its_tokens_are_rejected() {
+        let path = std::env::temp_dir().join(format!("auth-{}.db", Uuid::new_v4()));
+        let db = crate::repository::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        let raw_key = generate_key();
+        let key = crate::models::ApiKey {
+            id: Uuid::new_v4(),
+            name: "workshop".to_string(),
+            key_prefix: String::new(),
+            key_hash: hash_key(&raw_key),
+            role: Role::Technician.as_str().to_string(),
+            created_at: chrono::Utc::now(),
+            revoked_at: None,
+        };
+        db.insert_api_key(&key).await.unwrap();
+        let state = Arc::new(AppState {
+            db: db.clone(),
+            auth: config(None),
+            images: Arc::new(crate::bundle::ImageFetcher::from_env()),
+        });
+        let caller = Caller { name: key.name.clone(), role: Role::Technician, key_id: Some(key.id) };
+        let (token, _) = issue_token(&state.auth, &caller, Role::Technician);
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(|req, next| require(Role::Technician, req, next)))
+            .layer(middleware::from_fn_with_state(state.clone(), authenticate));
+        let request = |credential: &str| {
+            axum::http::Request::get("/")
+                .header(header::AUTHORIZATION, format!("Bearer {}", credential))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(status(router.clone(), request(&raw_key)).await, StatusCode::OK);
+        assert_eq!(status(router.clone(), request(&token)).await, StatusCode::OK);
+        assert!(db.revoke_api_key(key.id).await.unwrap());
+        assert_eq!(status(router.clone(), request(&raw_key)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(router, request(&token)).await, StatusCode::UNAUTHORIZED);
+    }
+}
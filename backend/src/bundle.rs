@@ -0,0 +1,333 @@
+use crate::models::{DipSwitch, ErrorCode, Printer, SparePart, Tombstone};
+use base64::Engine;
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Layout version of the bundle JSON. Bump it when fields are renamed or removed so old clients refuse it.
+pub const BUNDLE_FORMAT: u32 = 1;
+
+/// Images larger than this are listed but not embedded.
+const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+const IMAGE_FETCH_CONCURRENCY: usize = 8;
+const IMAGE_FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+/// Cached images are served without asking the origin for this long
+const IMAGE_CACHE_FRESH: Duration = Duration::from_secs(60 * 60);
+const IMAGE_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Everything an offline client searches. Error codes carry their parts exactly like `/api/errors`,
+/// so the same screens can render both.
+#[derive(Serialize, Debug)]
+pub struct BundleData {
+    pub printers: Vec<Printer>,
+    pub error_codes: Vec<ErrorCode>,
+    /// Every part referenced by the error codes, once, with its general ranking
+    pub parts: Vec<SparePart>,
+    pub dip_switches: Vec<DipSwitch>,
+}
+
+/// A part image embedded as base64. `data` is `None` when the image could not be fetched (`error` says why).
+#[derive(Serialize, Debug)]
+pub struct PartImage {
+    pub part_id: Uuid,
+    pub url: String,
+    pub content_type: Option<String>,
+    pub data: Option<String>,
+    pub sha256: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Bundle {
+    pub format: u32,
+    pub version: String,
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    /// Pass to `/api/sync/changes` to fetch what changed after this bundle
+    pub sync_token: String,
+    pub models: Vec<String>,
+    #[serde(flatten)]
+    pub data: BundleData,
+    pub part_images: Vec<PartImage>,
+}
+
+/// Answer of `/api/sync/changes`: rows changed since `since`, to upsert, and rows deleted since then.
+/// Error codes retired by an import are upserted with `obsolete_at` set (and again with it cleared when revived).
+#[derive(Serialize, Debug)]
+pub struct ChangeSet {
+    pub since: String,
+    /// Token for the next call
+    pub token: String,
+    pub models: Vec<String>,
+    #[serde(flatten)]
+    pub upserts: BundleData,
+    pub deleted: Vec<Tombstone>,
+}
+
+/// The compressed bundle and its checksum, as sent to the client.
+pub struct EncodedBundle {
+    pub version: String,
+    pub sha256: String,
+    pub body: Vec<u8>,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Content version of the data: the same models and data always give the same version, so clients
+/// can skip the download (`If-None-Match`) when nothing changed. Image bytes are not part of it.
+pub fn version_of(models: &[String], data: &BundleData, with_images: bool) -> Result<String, String> {
+    let json = serde_json::to_vec(&(BUNDLE_FORMAT, models, data, with_images)).map_err(|e| e.to_string())?;
+    Ok(sha256_hex(&json)[..16].to_string())
+}
+
+/// Serializes and gzips the bundle; `sha256` is the checksum of the compressed body.
+pub fn encode(bundle: &Bundle) -> Result<EncodedBundle, String> {
+    let json = serde_json::to_vec(bundle).map_err(|e| e.to_string())?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&json).map_err(|e| e.to_string())?;
+    let body = encoder.finish().map_err(|e| e.to_string())?;
+    Ok(EncodedBundle { version: bundle.version.clone(), sha256: sha256_hex(&body), body })
+}
+
+/// Fetches the part images embedded in bundles. Only http(s) URLs on `IMAGE_HOSTS` (all hosts when unset)
+/// that resolve to public addresses are fetched, without following redirects. Images are cached by URL and
+/// revalidated with their ETag once they are older than `IMAGE_CACHE_FRESH`.
+pub struct ImageFetcher {
+    /// Host names (and their subdomains) images may come from; empty allows any public host
+    allowed_hosts: Vec<String>,
+    cache: tokio::sync::Mutex<HashMap<String, CachedImage>>,
+}
+
+#[derive(Clone)]
+struct CachedImage {
+    content_type: Option<String>,
+    bytes: Arc<Vec<u8>>,
+    etag: Option<String>,
+    fetched_at: Instant,
+}
+
+/// Answer of the origin to a (conditional) image request.
+enum Fetched {
+    Image(CachedImage),
+    NotModified,
+}
+
+impl ImageFetcher {
+    /// `IMAGE_HOSTS`: comma-separated host names part images may be fetched from.
+    pub fn from_env() -> ImageFetcher {
+        let allowed_hosts = std::env::var("IMAGE_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|h| h.trim().trim_start_matches('.').to_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        ImageFetcher { allowed_hosts, cache: tokio::sync::Mutex::new(HashMap::new()) }
+    }
+
+    fn host_allowed(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.allowed_hosts.is_empty()
+            || self.allowed_hosts.iter().any(|h| host == *h || host.ends_with(&format!(".{}", h)))
+    }
+
+    /// The public address to connect to for `url`, or why the URL may not be fetched.
+    async fn resolve(&self, url: &reqwest::Url) -> Result<SocketAddr, String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("not an absolute http(s) URL".to_string());
+        }
+        let host = url.host_str().ok_or("URL has no host")?;
+        if !self.host_allowed(host) {
+            return Err(format!("host {} is not in IMAGE_HOSTS", host));
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+                .collect(),
+        };
+        // Every address must be public, so a name can't mix an internal one in
+        match addrs.first() {
+            None => Err(format!("{} has no address", host)),
+            Some(_) if addrs.iter().any(|a| !is_public(a.ip())) => Err(format!("{} resolves to a non-public address", host)),
+            Some(addr) => Ok(*addr),
+        }
+    }
+
+    async fn fetch(&self, url: &str, etag: Option<&str>) -> Result<Fetched, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|_| "not an absolute http(s) URL".to_string())?;
+        let addr = self.resolve(&parsed).await?;
+        // Connect to the checked address only: no second lookup, no proxy, no redirect elsewhere
+        let mut client = reqwest::Client::builder()
+            .timeout(IMAGE_FETCH_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy();
+        if let Some(domain) = parsed.domain() {
+            client = client.resolve(domain, addr);
+        }
+        let client = client.build().map_err(|e| e.to_string())?;
+
+        let mut request = client.get(parsed);
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED && etag.is_some() {
+            return Ok(Fetched::NotModified);
+        }
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        if response.content_length().is_some_and(|len| len as usize > MAX_IMAGE_BYTES) {
+            return Err(format!("larger than {} bytes", MAX_IMAGE_BYTES));
+        }
+        let header = |name| response.headers().get(name).and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok()).map(str::to_string);
+        let content_type = header(reqwest::header::CONTENT_TYPE);
+        let etag = header(reqwest::header::ETAG);
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(format!("larger than {} bytes", MAX_IMAGE_BYTES));
+        }
+        Ok(Fetched::Image(CachedImage { content_type, bytes: Arc::new(bytes.to_vec()), etag, fetched_at: Instant::now() }))
+    }
+
+    /// The image at `url`, from the cache while fresh, otherwise fetched (or revalidated) and cached.
+    async fn image(&self, url: &str) -> Result<CachedImage, String> {
+        let cached = self.cache.lock().await.get(url).cloned();
+        if let Some(cached) = &cached {
+            if cached.fetched_at.elapsed() < IMAGE_CACHE_FRESH {
+                return Ok(cached.clone());
+            }
+        }
+        let image = match (self.fetch(url, cached.as_ref().and_then(|c| c.etag.as_deref())).await?, cached) {
+            (Fetched::Image(image), _) => image,
+            (Fetched::NotModified, Some(cached)) => CachedImage { fetched_at: Instant::now(), ..cached },
+            (Fetched::NotModified, None) => return Err("HTTP 304 without a cached copy".to_string()),
+        };
+
+        let mut cache = self.cache.lock().await;
+        cache.insert(url.to_string(), image.clone());
+        // Drop the least recently fetched images beyond the size budget
+        let mut total: usize = cache.values().map(|c| c.bytes.len()).sum();
+        while total > IMAGE_CACHE_MAX_BYTES {
+            let Some(oldest) = cache.iter().min_by_key(|(_, c)| c.fetched_at).map(|(u, _)| u.clone()) else { break };
+            if let Some(removed) = cache.remove(&oldest) {
+                total -= removed.bytes.len();
+            }
+        }
+        Ok(image)
+    }
+
+    /// Gets the images of the given parts, a few at a time. Failures are reported per image and never
+    /// fail the bundle; `data:` URLs are already self-contained and are passed through without data.
+    pub async fn fetch_part_images(self: &Arc<Self>, parts: &[SparePart]) -> Vec<PartImage> {
+        let permits = Arc::new(tokio::sync::Semaphore::new(IMAGE_FETCH_CONCURRENCY));
+        let mut tasks = tokio::task::JoinSet::new();
+        for part in parts {
+            let Some(url) = part.image_url.as_deref().map(str::trim).filter(|u| !u.is_empty()) else {
+                continue;
+            };
+            let (part_id, url) = (part.id, url.to_string());
+            let (fetcher, permits) = (self.clone(), permits.clone());
+            tasks.spawn(async move {
+                let mut image = PartImage { part_id, url, content_type: None, data: None, sha256: None, error: None };
+                if image.url.starts_with("data:") {
+                    return image;
+                }
+                let _permit = permits.acquire().await;
+                match fetcher.image(&image.url).await {
+                    Ok(cached) => {
+                        image.content_type = cached.content_type;
+                        image.sha256 = Some(sha256_hex(&cached.bytes));
+                        image.data = Some(base64::engine::general_purpose::STANDARD.encode(cached.bytes.as_slice()));
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to fetch part image {}: {}", image.url, e);
+                        image.error = Some(e);
+                    }
+                }
+                image
+            });
+        }
+
+        let mut images = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(image) => images.push(image),
+                Err(e) => tracing::error!("Image fetch task failed: {:?}", e),
+            }
+        }
+        images.sort_by_key(|i| i.part_id);
+        images
+    }
+}
+
+/// Whether the address is on the public internet (not loopback, private, link-local, shared or reserved).
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public(v4.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses() {
+        for ip in ["8.8.8.8", "151.101.1.69", "2a00:1450:4001:80e::200e"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_urls_outside_the_allowed_hosts() {
+        let fetcher = ImageFetcher { allowed_hosts: vec!["images.example.com".to_string()], cache: Default::default() };
+        assert!(fetcher.host_allowed("images.example.com"));
+        assert!(fetcher.host_allowed("cdn.images.example.com"));
+        assert!(!fetcher.host_allowed("evilimages.example.com"));
+
+        let open = ImageFetcher { allowed_hosts: Vec::new(), cache: Default::default() };
+        for url in ["http://127.0.0.1/a.png", "http://[::1]:8080/a.png", "http://169.254.169.254/latest", "ftp://example.com/a.png"] {
+            assert!(open.resolve(&reqwest::Url::parse(url).unwrap()).await.is_err(), "{}", url);
+        }
+        assert!(fetcher.resolve(&reqwest::Url::parse("http://8.8.8.8/a.png").unwrap()).await.is_err());
+    }
+}
@@ -0,0 +1,496 @@
+use crate::models::{DipSwitch, DipSwitchRule, DipSwitchSetting};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Allowed, but the technician should know what they are doing
+    Warning,
+    /// The combination is refused
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SafetyWarning {
+    pub switch_number: i32,
+    pub bit_number: i32,
+    pub severity: Severity,
+    /// Machine-readable reason: unknown_switch, invalid_value, undefined_value, unreadable_default, invalid_field,
+    /// overlapping_fields, unknown_target, risk, temporary_only, requires, conflicts
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// One switch entry after applying the requested settings on top of the defaults.
+#[derive(Serialize, Debug)]
+pub struct FieldState {
+    pub switch_number: i32,
+    pub bit_number: i32,
+    pub end_bit: Option<i32>,
+    pub function_name: Option<String>,
+    pub value: i32,
+    pub meaning: Option<String>,
+    pub is_default: bool,
+}
+
+/// The byte to set on one physical switch (bit 0 is the lowest bit).
+#[derive(Serialize, Debug)]
+pub struct SwitchByte {
+    pub switch_number: i32,
+    pub value: u8,
+    pub hex: String,
+    /// Bit 7 first, as shown on the service screen
+    pub bits: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Calculation {
+    pub allowed: bool,
+    pub switches: Vec<SwitchByte>,
+    pub fields: Vec<FieldState>,
+    pub warnings: Vec<SafetyWarning>,
+}
+
+/// Reads a stored default ("1", "0", "010", "5") as the value of a field `width` bits wide. No default
+/// (empty, or "-" as printed in the manual) counts as 0; `None` when the text is not a value of the field
+/// (a note, a number too large).
+pub fn parse_setting_value(text: Option<&str>, width: i32) -> Option<i32> {
+    let text = text.unwrap_or("").trim();
+    if !(1..=8).contains(&width) {
+        return None;
+    }
+    if text.is_empty() || text == "-" {
+        return Some(0);
+    }
+    if width > 1 && text.len() == width as usize && text.chars().all(|c| c == '0' || c == '1') {
+        return i32::from_str_radix(text, 2).ok();
+    }
+    text.parse().ok().filter(|value| (0..1 << width).contains(value))
+}
+
+/// Whether a rule of the entry at `switch_number`, bits `bit_number..=last_bit`, targets one of those
+/// bits: the rule would depend on its own switch.
+pub fn rule_targets_itself(switch_number: i32, bit_number: i32, last_bit: i32, rule: &DipSwitchRule) -> bool {
+    rule.target_switch == switch_number && (bit_number..=last_bit).contains(&rule.target_bit)
+}
+
+/// Whether the entry fits in one switch byte.
+fn fits_byte(sw: &DipSwitch) -> bool {
+    (0..=7).contains(&sw.bit_number) && (sw.bit_number..=7).contains(&sw.end_bit.unwrap_or(sw.bit_number))
+}
+
+/// The bits of its switch byte the entry covers.
+fn field_mask(sw: &DipSwitch) -> u8 {
+    (sw.bit_number..=sw.end_bit.unwrap_or(sw.bit_number)).fold(0, |mask, bit| mask | 1 << bit)
+}
+
+fn meaning_of(sw: &DipSwitch, value: i32) -> Option<String> {
+    if sw.width() > 1 {
+        return sw.values.iter().find(|v| v.value == value).and_then(|v| v.meaning.clone());
+    }
+    match value {
+        0 => sw.setting_0.clone(),
+        _ => sw.setting_1.clone(),
+    }
+}
+
+/// Applies `settings` on top of the model's defaults, encodes every switch as a byte and checks the
+/// risk annotations and dependency rules. Any `Severity::Error` makes the combination not allowed.
+pub fn calculate(switches: &[DipSwitch], settings: &[DipSwitchSetting]) -> Calculation {
+    let mut warnings = Vec::new();
+    let mut used: BTreeMap<i32, u8> = BTreeMap::new();
+    for sw in switches {
+        if !fits_byte(sw) {
+            warnings.push(SafetyWarning {
+                switch_number: sw.switch_number,
+                bit_number: sw.bit_number,
+                severity: Severity::Error,
+                code: "invalid_field",
+                message: format!(
+                    "SW {}-{} covers bits {} to {}, outside the 8 bits of the switch",
+                    sw.switch_number, sw.bit_number, sw.bit_number, sw.end_bit.unwrap_or(sw.bit_number)
+                ),
+            });
+            continue;
+        }
+        let bits = used.entry(sw.switch_number).or_default();
+        if *bits & field_mask(sw) != 0 {
+            warnings.push(SafetyWarning {
+                switch_number: sw.switch_number,
+                bit_number: sw.bit_number,
+                severity: Severity::Error,
+                code: "overlapping_fields",
+                message: format!("SW {}-{} shares bits with another entry of the same switch", sw.switch_number, sw.bit_number),
+            });
+        }
+        *bits |= field_mask(sw);
+    }
+
+    // `None` for a default that cannot be read: refused unless the setting replaces it
+    let mut state: BTreeMap<(i32, i32), Option<i32>> = switches
+        .iter()
+        .map(|sw| ((sw.switch_number, sw.bit_number), parse_setting_value(sw.default_val.as_deref(), sw.width())))
+        .collect();
+    let defaults = state.clone();
+
+    for setting in settings {
+        let Some(sw) = switches
+            .iter()
+            .find(|sw| sw.switch_number == setting.switch_number && sw.bit_number == setting.bit_number)
+        else {
+            warnings.push(SafetyWarning {
+                switch_number: setting.switch_number,
+                bit_number: setting.bit_number,
+                severity: Severity::Error,
+                code: "unknown_switch",
+                message: format!("SW {}-{} does not exist on this model", setting.switch_number, setting.bit_number),
+            });
+            continue;
+        };
+        if !fits_byte(sw) {
+            continue;
+        }
+        let max = (1 << sw.width()) - 1;
+        if !(0..=max).contains(&setting.value) {
+            warnings.push(SafetyWarning {
+                switch_number: sw.switch_number,
+                bit_number: sw.bit_number,
+                severity: Severity::Error,
+                code: "invalid_value",
+                message: format!("SW {}-{} accepts values 0 to {}, got {}", sw.switch_number, sw.bit_number, max, setting.value),
+            });
+            continue;
+        }
+        if sw.width() > 1 && !sw.values.iter().any(|v| v.value == setting.value) {
+            // Without a value table nothing is known about any value: warn. A value missing from the table is refused
+            let (severity, message) = if sw.values.is_empty() {
+                (Severity::Warning, format!("SW {}-{} has no value table: the meaning of {} is unknown", sw.switch_number, sw.bit_number, setting.value))
+            } else {
+                (Severity::Error, format!("SW {}-{} has no value {} in its value table", sw.switch_number, sw.bit_number, setting.value))
+            };
+            warnings.push(SafetyWarning {
+                switch_number: sw.switch_number,
+                bit_number: sw.bit_number,
+                severity,
+                code: "undefined_value",
+                message,
+            });
+        }
+        state.insert((sw.switch_number, sw.bit_number), Some(setting.value));
+    }
+
+    let mut fields = Vec::new();
+    let mut bytes: BTreeMap<i32, u8> = BTreeMap::new();
+    for sw in switches.iter().filter(|sw| fits_byte(sw)) {
+        let key = (sw.switch_number, sw.bit_number);
+        let is_default = state[&key] == defaults[&key];
+        let Some(value) = state[&key] else {
+            warnings.push(SafetyWarning {
+                switch_number: sw.switch_number,
+                bit_number: sw.bit_number,
+                severity: Severity::Error,
+                code: "unreadable_default",
+                message: format!(
+                    "SW {}-{} has no usable default ({}): set it explicitly",
+                    sw.switch_number,
+                    sw.bit_number,
+                    sw.default_val.as_deref().unwrap_or("").trim()
+                ),
+            });
+            continue;
+        };
+
+        if !is_default {
+            let name = sw.function_name.as_deref().and_then(|f| f.lines().next()).unwrap_or("");
+            if matches!(sw.risk_level.as_str(), "medium" | "high") {
+                warnings.push(SafetyWarning {
+                    switch_number: sw.switch_number,
+                    bit_number: sw.bit_number,
+                    severity: Severity::Warning,
+                    code: "risk",
+                    message: sw.risk_note.clone().unwrap_or_else(|| {
+                        format!("SW {}-{} ({}) is marked {} risk", sw.switch_number, sw.bit_number, name, sw.risk_level)
+                    }),
+                });
+            }
+            if sw.temporary_only {
+                warnings.push(SafetyWarning {
+                    switch_number: sw.switch_number,
+                    bit_number: sw.bit_number,
+                    severity: Severity::Warning,
+                    code: "temporary_only",
+                    message: format!("SW {}-{} ({}) is for temporary use only: restore the default after service", sw.switch_number, sw.bit_number, name),
+                });
+            }
+        }
+
+        for rule in sw.rules.iter().filter(|r| r.when_value == value) {
+            let Some(target) = state.get(&(rule.target_switch, rule.target_bit)) else {
+                warnings.push(SafetyWarning {
+                    switch_number: sw.switch_number,
+                    bit_number: sw.bit_number,
+                    severity: Severity::Error,
+                    code: "unknown_target",
+                    message: format!(
+                        "SW {}-{} has a rule on SW {}-{}, which does not exist on this model",
+                        sw.switch_number, sw.bit_number, rule.target_switch, rule.target_bit
+                    ),
+                });
+                continue;
+            };
+            // An unreadable target is reported on its own entry and satisfies nothing
+            let violated = match (rule.kind.as_str(), target) {
+                (_, None) => true,
+                ("conflicts", Some(target)) => *target == rule.target_value,
+                (_, Some(target)) => *target != rule.target_value,
+            };
+            if violated {
+                let default_message = match rule.kind.as_str() {
+                    "conflicts" => format!(
+                        "SW {}-{} = {} cannot be combined with SW {}-{} = {}",
+                        sw.switch_number, sw.bit_number, value, rule.target_switch, rule.target_bit, rule.target_value
+                    ),
+                    _ => format!(
+                        "SW {}-{} = {} needs SW {}-{} = {}",
+                        sw.switch_number, sw.bit_number, value, rule.target_switch, rule.target_bit, rule.target_value
+                    ),
+                };
+                warnings.push(SafetyWarning {
+                    switch_number: sw.switch_number,
+                    bit_number: sw.bit_number,
+                    severity: Severity::Error,
+                    code: if rule.kind == "conflicts" { "conflicts" } else { "requires" },
+                    message: rule.message.clone().unwrap_or(default_message),
+                });
+            }
+        }
+
+        *bytes.entry(sw.switch_number).or_default() |= ((value as u8) << sw.bit_number) & field_mask(sw);
+        fields.push(FieldState {
+            switch_number: sw.switch_number,
+            bit_number: sw.bit_number,
+            end_bit: sw.end_bit,
+            function_name: sw.function_name.clone(),
+            value,
+            meaning: meaning_of(sw, value),
+            is_default,
+        });
+    }
+
+    let switches = bytes
+        .into_iter()
+        .map(|(switch_number, value)| SwitchByte {
+            switch_number,
+            value,
+            hex: format!("{:02X}", value),
+            bits: format!("{:08b}", value),
+        })
+        .collect();
+
+    Calculation {
+        allowed: !warnings.iter().any(|w| w.severity == Severity::Error),
+        switches,
+        fields,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{DipSwitchRule, DipSwitchValue};
+    use uuid::Uuid;
+
+    fn bit(switch_number: i32, bit_number: i32, default_val: &str) -> DipSwitch {
+        DipSwitch {
+            id: Uuid::new_v4(),
+            model_name: "TEST".to_string(),
+            switch_number,
+            bit_number,
+            function_name: Some(format!("SW {}-{}", switch_number, bit_number)),
+            setting_0: Some("Off".to_string()),
+            setting_1: Some("On".to_string()),
+            default_val: Some(default_val.to_string()),
+            end_bit: None,
+            risk_level: "none".to_string(),
+            temporary_only: false,
+            risk_note: None,
+            defaults: Vec::new(),
+            values: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    fn field(switch_number: i32, bit_number: i32, end_bit: i32, default_val: &str, values: &[i32]) -> DipSwitch {
+        let mut sw = bit(switch_number, bit_number, default_val);
+        sw.end_bit = Some(end_bit);
+        sw.values = values
+            .iter()
+            .map(|&value| DipSwitchValue { value, meaning: Some(format!("Mode {}", value)), bits: String::new() })
+            .collect();
+        sw
+    }
+
+    fn rule(kind: &str, when_value: i32, target: (i32, i32), target_value: i32) -> DipSwitchRule {
+        DipSwitchRule {
+            when_value,
+            kind: kind.to_string(),
+            target_switch: target.0,
+            target_bit: target.1,
+            target_value,
+            message: None,
+        }
+    }
+
+    fn set(switch_number: i32, bit_number: i32, value: i32) -> DipSwitchSetting {
+        DipSwitchSetting { switch_number, bit_number, value }
+    }
+
+    fn codes(calculation: &Calculation) -> Vec<&'static str> {
+        calculation.warnings.iter().map(|w| w.code).collect()
+    }
+
+    #[test]
+    fn parses_defaults() {
+        assert_eq!(parse_setting_value(Some("1"), 1), Some(1));
+        assert_eq!(parse_setting_value(Some(" 0 "), 1), Some(0));
+        assert_eq!(parse_setting_value(Some("010"), 3), Some(2));
+        assert_eq!(parse_setting_value(Some("5"), 3), Some(5));
+        assert_eq!(parse_setting_value(None, 2), Some(0));
+        assert_eq!(parse_setting_value(Some("-"), 1), Some(0));
+        assert_eq!(parse_setting_value(Some("2"), 1), None);
+        assert_eq!(parse_setting_value(Some("8"), 3), None);
+        assert_eq!(parse_setting_value(Some("see note"), 1), None);
+        assert_eq!(parse_setting_value(Some("0"), 9), None);
+    }
+
+    #[test]
+    fn packs_multi_bit_fields() {
+        let switches = vec![
+            bit(1, 0, "1"),
+            field(1, 2, 4, "000", &[0, 1, 5, 7]),
+            bit(1, 7, "0"),
+            field(2, 6, 7, "10", &[0, 1, 2, 3]),
+        ];
+        let calculation = calculate(&switches, &[set(1, 2, 5), set(1, 7, 1)]);
+        assert!(calculation.allowed, "{:?}", calculation.warnings);
+        assert_eq!(calculation.switches[0].value, 0b1001_0101);
+        assert_eq!(calculation.switches[0].bits, "10010101");
+        assert_eq!(calculation.switches[1].value, 0b1000_0000);
+        assert_eq!(calculation.switches[1].hex, "80");
+
+        let mode = calculation.fields.iter().find(|f| f.bit_number == 2).unwrap();
+        assert_eq!(mode.value, 5);
+        assert_eq!(mode.meaning.as_deref(), Some("Mode 5"));
+        assert!(!mode.is_default);
+    }
+
+    #[test]
+    fn refuses_fields_outside_the_byte() {
+        let switches = vec![bit(1, 0, "1"), field(1, 6, 8, "0", &[0, 1])];
+        let calculation = calculate(&switches, &[]);
+        assert!(!calculation.allowed);
+        assert_eq!(codes(&calculation), vec!["invalid_field"]);
+        assert_eq!(calculation.switches[0].value, 0b0000_0001);
+    }
+
+    #[test]
+    fn refuses_overlapping_fields() {
+        let switches = vec![field(1, 0, 2, "111", &[0, 7]), bit(1, 2, "0")];
+        let calculation = calculate(&switches, &[]);
+        assert!(!calculation.allowed);
+        assert_eq!(codes(&calculation), vec!["overlapping_fields"]);
+    }
+
+    #[test]
+    fn checks_values() {
+        let switches = vec![field(1, 0, 1, "0", &[0, 1, 2]), field(1, 2, 3, "0", &[]), bit(1, 4, "0")];
+
+        let undefined = calculate(&switches, &[set(1, 0, 3)]);
+        assert!(!undefined.allowed);
+        assert_eq!(codes(&undefined), vec!["undefined_value"]);
+
+        // Without a value table the value is only flagged
+        let no_table = calculate(&switches, &[set(1, 2, 3)]);
+        assert!(no_table.allowed);
+        assert_eq!(no_table.warnings[0].severity, Severity::Warning);
+        assert_eq!(codes(&no_table), vec!["undefined_value"]);
+
+        let too_large = calculate(&switches, &[set(1, 4, 2)]);
+        assert!(!too_large.allowed);
+        assert_eq!(codes(&too_large), vec!["invalid_value"]);
+
+        let unknown = calculate(&switches, &[set(3, 0, 1)]);
+        assert!(!unknown.allowed);
+        assert_eq!(codes(&unknown), vec!["unknown_switch"]);
+    }
+
+    #[test]
+    fn refuses_unreadable_defaults_unless_set() {
+        let switches = vec![bit(1, 0, "see note"), bit(1, 1, "0")];
+        let calculation = calculate(&switches, &[]);
+        assert!(!calculation.allowed);
+        assert_eq!(codes(&calculation), vec!["unreadable_default"]);
+
+        let calculation = calculate(&switches, &[set(1, 0, 1)]);
+        assert!(calculation.allowed, "{:?}", calculation.warnings);
+        assert_eq!(calculation.switches[0].value, 0b0000_0001);
+    }
+
+    #[test]
+    fn checks_rules() {
+        let mut duplex = bit(1, 0, "0");
+        duplex.rules.push(rule("requires", 1, (2, 0), 1));
+        let mut eco = bit(1, 1, "0");
+        eco.rules.push(rule("conflicts", 1, (2, 2), 3));
+        let switches = vec![duplex, eco, bit(2, 0, "0"), field(2, 2, 3, "00", &[0, 1, 2, 3])];
+
+        let missing = calculate(&switches, &[set(1, 0, 1)]);
+        assert!(!missing.allowed);
+        assert_eq!(codes(&missing), vec!["requires"]);
+
+        let satisfied = calculate(&switches, &[set(1, 0, 1), set(2, 0, 1)]);
+        assert!(satisfied.allowed, "{:?}", satisfied.warnings);
+
+        let conflict = calculate(&switches, &[set(1, 1, 1), set(2, 2, 3)]);
+        assert!(!conflict.allowed);
+        assert_eq!(codes(&conflict), vec!["conflicts"]);
+
+        // Rules only apply at their `when_value`
+        let inactive = calculate(&switches, &[set(2, 2, 3)]);
+        assert!(inactive.allowed, "{:?}", inactive.warnings);
+    }
+
+    #[test]
+    fn finds_rules_on_their_own_bits() {
+        assert!(rule_targets_itself(1, 0, 0, &rule("requires", 1, (1, 0), 1)));
+        // Any bit of a multi-bit field, not only its first one
+        assert!(rule_targets_itself(2, 2, 4, &rule("requires", 1, (2, 3), 1)));
+        assert!(rule_targets_itself(2, 2, 4, &rule("conflicts", 1, (2, 4), 1)));
+        assert!(!rule_targets_itself(2, 2, 4, &rule("requires", 1, (2, 5), 1)));
+        assert!(!rule_targets_itself(2, 2, 4, &rule("requires", 1, (3, 3), 1)));
+    }
+
+    #[test]
+    fn refuses_rules_on_missing_switches() {
+        let mut sw = bit(1, 0, "1");
+        sw.rules.push(rule("requires", 1, (5, 0), 1));
+        let calculation = calculate(&[sw], &[]);
+        assert!(!calculation.allowed);
+        assert_eq!(codes(&calculation), vec!["unknown_target"]);
+    }
+
+    #[test]
+    fn warns_about_risky_changes() {
+        let mut risky = bit(1, 0, "0");
+        risky.risk_level = "high".to_string();
+        risky.temporary_only = true;
+        let switches = vec![risky];
+
+        assert!(calculate(&switches, &[]).warnings.is_empty());
+        let calculation = calculate(&switches, &[set(1, 0, 1)]);
+        assert!(calculation.allowed);
+        assert_eq!(codes(&calculation), vec!["risk", "temporary_only"]);
+    }
+}
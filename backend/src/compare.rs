@@ -0,0 +1,285 @@
+use crate::models::DipSwitch;
+use serde::Serialize;
+
+/// Function-text similarity (Sørensen–Dice on character bigrams) above which two switches are the same function.
+pub const DEFAULT_THRESHOLD: f64 = 0.6;
+
+#[derive(Serialize, Debug)]
+pub struct SwitchMatch {
+    /// Similarity of the two function texts (0..1)
+    pub similarity: f64,
+    /// Similarity of the setting texts / value tables (0..1)
+    pub settings_similarity: f64,
+    /// The function sits on a different switch, bit or bit range
+    pub moved: bool,
+    /// The settings no longer mean the same thing
+    pub meaning_changed: bool,
+    pub default_changed: bool,
+    pub from: DipSwitch,
+    pub to: DipSwitch,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ComparisonSummary {
+    pub matched: usize,
+    pub moved: usize,
+    pub meaning_changed: usize,
+    pub only_in_from: usize,
+    pub only_in_to: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModelComparison {
+    pub from_model: String,
+    pub to_model: String,
+    pub threshold: f64,
+    pub summary: ComparisonSummary,
+    pub matches: Vec<SwitchMatch>,
+    pub only_in_from: Vec<DipSwitch>,
+    pub only_in_to: Vec<DipSwitch>,
+}
+
+/// Lowercased alphanumeric words of the function title (its first line), so layout and punctuation don't count.
+fn function_key(sw: &DipSwitch) -> String {
+    let title = sw.function_name.as_deref().unwrap_or("").lines().next().unwrap_or("");
+    normalize(title)
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything a technician reads to know what 0/1 (or each value) does.
+fn settings_key(sw: &DipSwitch) -> String {
+    let mut parts = vec![
+        sw.setting_0.as_deref().unwrap_or(""),
+        sw.setting_1.as_deref().unwrap_or(""),
+    ];
+    parts.extend(sw.values.iter().map(|v| v.meaning.as_deref().unwrap_or("")));
+    normalize(&parts.join(" "))
+}
+
+fn similarity(a: &str, b: &str) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    strsim::sorensen_dice(a, b)
+}
+
+fn same_position(a: &DipSwitch, b: &DipSwitch) -> bool {
+    a.switch_number == b.switch_number && a.bit_number == b.bit_number && a.width() == b.width()
+}
+
+/// Pairs up the switches of two models by function text, best matches first, each switch used at most once.
+/// Reserved bits (function "-" or empty) are left out entirely.
+pub fn compare_models(
+    from_model: String,
+    from: Vec<DipSwitch>,
+    to_model: String,
+    to: Vec<DipSwitch>,
+    threshold: f64,
+) -> ModelComparison {
+    let mut from: Vec<Option<DipSwitch>> = from.into_iter().filter(|s| !function_key(s).is_empty()).map(Some).collect();
+    let mut to: Vec<Option<DipSwitch>> = to.into_iter().filter(|s| !function_key(s).is_empty()).map(Some).collect();
+
+    let from_keys: Vec<String> = from.iter().flatten().map(function_key).collect();
+    let to_keys: Vec<String> = to.iter().flatten().map(function_key).collect();
+
+    let mut candidates: Vec<(f64, bool, usize, usize)> = Vec::new();
+    for (i, fk) in from_keys.iter().enumerate() {
+        for (j, tk) in to_keys.iter().enumerate() {
+            let score = similarity(fk, tk);
+            if score >= threshold {
+                let same_pos = match (&from[i], &to[j]) {
+                    (Some(a), Some(b)) => same_position(a, b),
+                    _ => false,
+                };
+                candidates.push((score, same_pos, i, j));
+            }
+        }
+    }
+    // Highest similarity first; on ties keep a function where it was
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+
+    let mut summary = ComparisonSummary::default();
+    let mut matches = Vec::new();
+    for (score, same_pos, i, j) in candidates {
+        if from[i].is_none() || to[j].is_none() {
+            continue;
+        }
+        let (Some(a), Some(b)) = (from[i].take(), to[j].take()) else { continue };
+
+        let settings_similarity = similarity(&settings_key(&a), &settings_key(&b));
+        let meaning_changed = a.width() != b.width() || settings_similarity < threshold;
+        let default_changed = a.default_val.as_deref().map(str::trim) != b.default_val.as_deref().map(str::trim);
+
+        summary.matched += 1;
+        if !same_pos {
+            summary.moved += 1;
+        }
+        if meaning_changed {
+            summary.meaning_changed += 1;
+        }
+        matches.push(SwitchMatch {
+            similarity: score,
+            settings_similarity,
+            moved: !same_pos,
+            meaning_changed,
+            default_changed,
+            from: a,
+            to: b,
+        });
+    }
+    matches.sort_by_key(|m| (m.from.switch_number, m.from.bit_number));
+
+    let only_in_from: Vec<DipSwitch> = from.into_iter().flatten().collect();
+    let only_in_to: Vec<DipSwitch> = to.into_iter().flatten().collect();
+    summary.only_in_from = only_in_from.len();
+    summary.only_in_to = only_in_to.len();
+
+    ModelComparison { from_model, to_model, threshold, summary, matches, only_in_from, only_in_to }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DipSwitchValue;
+    use uuid::Uuid;
+
+    fn sw(switch_number: i32, bit_number: i32, function: &str, settings: (&str, &str), default_val: &str) -> DipSwitch {
+        DipSwitch {
+            id: Uuid::new_v4(),
+            model_name: String::new(),
+            switch_number,
+            bit_number,
+            function_name: Some(function.to_string()),
+            setting_0: Some(settings.0.to_string()),
+            setting_1: Some(settings.1.to_string()),
+            default_val: Some(default_val.to_string()),
+            end_bit: None,
+            risk_level: "none".to_string(),
+            temporary_only: false,
+            risk_note: None,
+            defaults: Vec::new(),
+            values: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    fn field(switch_number: i32, bit_number: i32, end_bit: i32, function: &str, meanings: &[&str]) -> DipSwitch {
+        let mut sw = sw(switch_number, bit_number, function, ("", ""), "0");
+        sw.end_bit = Some(end_bit);
+        sw.values = meanings
+            .iter()
+            .enumerate()
+            .map(|(value, meaning)| DipSwitchValue { value: value as i32, meaning: Some(meaning.to_string()), bits: String::new() })
+            .collect();
+        sw
+    }
+
+    fn toner() -> DipSwitch {
+        sw(1, 0, "Toner end detection", ("Stop printing", "Continue printing"), "0")
+    }
+
+    fn fuser() -> DipSwitch {
+        sw(2, 3, "Fusing temperature correction", ("Normal", "Low"), "0")
+    }
+
+    struct Case {
+        name: &'static str,
+        from: Vec<DipSwitch>,
+        to: Vec<DipSwitch>,
+        matched: usize,
+        moved: usize,
+        meaning_changed: usize,
+        default_changed: usize,
+        only_in_from: usize,
+        only_in_to: usize,
+    }
+
+    #[test]
+    fn classifies_differences() {
+        let cases = vec![
+            Case {
+                name: "identical",
+                from: vec![toner(), fuser()],
+                to: vec![toner(), fuser()],
+                matched: 2, moved: 0, meaning_changed: 0, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "added",
+                from: vec![toner()],
+                to: vec![toner(), fuser()],
+                matched: 1, moved: 0, meaning_changed: 0, default_changed: 0, only_in_from: 0, only_in_to: 1,
+            },
+            Case {
+                name: "removed",
+                from: vec![toner(), fuser()],
+                to: vec![fuser()],
+                matched: 1, moved: 0, meaning_changed: 0, default_changed: 0, only_in_from: 1, only_in_to: 0,
+            },
+            Case {
+                name: "moved to another bit",
+                from: vec![toner()],
+                to: vec![sw(4, 7, "Toner end detection", ("Stop printing", "Continue printing"), "0")],
+                matched: 1, moved: 1, meaning_changed: 0, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "settings mean something else",
+                from: vec![toner()],
+                to: vec![sw(1, 0, "Toner end detection", ("Display warning", "Send e-mail"), "0")],
+                matched: 1, moved: 0, meaning_changed: 1, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "default only",
+                from: vec![toner()],
+                to: vec![sw(1, 0, "Toner end detection", ("Stop printing", "Continue printing"), "1")],
+                matched: 1, moved: 0, meaning_changed: 0, default_changed: 1, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "punctuation and case do not count",
+                from: vec![toner()],
+                to: vec![sw(1, 0, "TONER END - detection.", ("stop printing", "continue printing"), " 0 ")],
+                matched: 1, moved: 0, meaning_changed: 0, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "single bit widened to a field",
+                from: vec![toner()],
+                to: vec![field(1, 0, 1, "Toner end detection", &["Stop printing", "Continue printing", "Warn", "Ignore"])],
+                matched: 1, moved: 1, meaning_changed: 1, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+            Case {
+                name: "reserved bits are left out",
+                from: vec![toner(), sw(3, 1, "-", ("", ""), "0")],
+                to: vec![toner(), sw(3, 2, "", ("", ""), "0")],
+                matched: 1, moved: 0, meaning_changed: 0, default_changed: 0, only_in_from: 0, only_in_to: 0,
+            },
+        ];
+
+        for case in cases {
+            let comparison = compare_models("A".to_string(), case.from, "B".to_string(), case.to, DEFAULT_THRESHOLD);
+            let summary = &comparison.summary;
+            let default_changed = comparison.matches.iter().filter(|m| m.default_changed).count();
+            assert_eq!(
+                (summary.matched, summary.moved, summary.meaning_changed, default_changed, summary.only_in_from, summary.only_in_to),
+                (case.matched, case.moved, case.meaning_changed, case.default_changed, case.only_in_from, case.only_in_to),
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn each_switch_is_matched_once() {
+        let to = vec![toner(), sw(5, 0, "Toner end detection", ("Stop printing", "Continue printing"), "0")];
+        let comparison = compare_models("A".to_string(), vec![toner()], "B".to_string(), to, DEFAULT_THRESHOLD);
+        assert_eq!(comparison.summary.matched, 1);
+        // On a tie the function stays where it was
+        assert!(!comparison.matches[0].moved);
+        assert_eq!(comparison.only_in_to[0].switch_number, 5);
+    }
+}
@@ -1,14 +1,44 @@
 use axum::{
-    extract::{Query, State, Multipart},
+    extract::{Path, Query, State, Multipart},
+    http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
 use std::sync::Arc;
-use crate::{AppState, models::{Printer, ErrorCode, SparePart}};
-use serde::Deserialize;
+use crate::{AppState, audit::{self, AuditContext, AuditTarget}, auth::{self, Caller, Role}, bundle, calculator, compare, merge::{self, MergePolicies}, ranking, worksheet, models::{Printer, ErrorCode, SparePart, DipSwitch, DipSwitchImport, DipSwitchSearchHit, DipSwitchProfile, DipSwitchSetting, RISK_LEVELS, Intervention, InterventionDipSwitch, InterventionErrorCode, InterventionPart, INTERVENTION_OUTCOMES, Machine, PartRanking, ErrorFeedback, FeedbackSummary, PartFeedback, ApiKey, AuditEntry, ErrorCodeRevision, ImportBatch, ColumnMapping, Provenance, SOURCE_KIND_MANUAL, SOURCE_KIND_PDF, SOURCE_KIND_SHEET}};
+use crate::repository::{AuditFilter, BatchRollback, CodeQuery, DipSwitchFilter, ErrorCodeFields, ErrorCodeImportRow, ErrorCodeRestore, InterventionFilter, MachineFilter, ObsoleteCode, Repository};
+use crate::spreadsheet::{
+    self, ColumnMatch, CsvOptions, Record, SheetFormat, COL_CAUSE, COL_CLASSIFICATION, COL_CODE, COL_CORRECTION, COL_ESTIMATED_PARTS,
+    COL_FAULTY_PART_ISOLATION, COL_MEASURES, COL_MODEL, COL_NOTE, COL_PAGE, COL_SOLUTION, DIPSW_COLUMNS, ERROR_CODE_COLUMNS,
+};
+use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
-use std::io::Cursor;
-use calamine::{Reader, Xlsx};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Strips the "Konica Minolta" prefix variants so every model is stored under its short name.
+pub fn normalize_model_name(model: &str) -> String {
+    ["Konica Minolta ", "KonicaMinolta ", "Konica Minolta", "KonicaMinolta"]
+        .iter()
+        .fold(model.to_string(), |acc, prefix| acc.replace(prefix, ""))
+        .trim()
+        .to_string()
+}
+
+/// Splits a comma-separated model list ("C4080,C4070"), normalized and without duplicates.
+fn parse_model_list(list: &str) -> Vec<String> {
+    let mut models: Vec<String> = Vec::new();
+    for m in list.split(',').map(normalize_model_name) {
+        if !m.is_empty() && !models.contains(&m) {
+            models.push(m);
+        }
+    }
+    models
+}
+
+/// Interprets a query-string switch such as `summary=1` or `prune=true`.
+fn flag(value: &Option<String>) -> bool {
+    value.as_deref().map(|s| s == "1" || s == "true").unwrap_or(false)
+}
 
 #[derive(Deserialize)]
 pub struct SearchParams {
@@ -16,11 +46,12 @@ pub struct SearchParams {
     code: Option<String>,
     limit: Option<i32>,
     summary: Option<String>,
+    /// Also list codes marked obsolete by an import ("1" or "true")
+    include_obsolete: Option<String>,
 }
 
 pub async fn get_printers(State(state): State<Arc<AppState>>) -> Json<Vec<Printer>> {
-    let printers = sqlx::query_as::<_, Printer>("SELECT * FROM printers ORDER BY model_name")
-        .fetch_all(&state.db)
+    let printers = state.db.printers()
         .await
         .map_err(|e| {
             tracing::error!("Failed to fetch printers: {:?}", e);
@@ -35,56 +66,16 @@ pub async fn search_errors(
     State(state): State<Arc<AppState>>,
     Query(params): Query<SearchParams>,
 ) -> Json<Vec<ErrorCode>> {
-    let mut sql = String::from(r#"
-        SELECT e.* FROM error_codes e
-        JOIN printers p ON e.printer_id = p.id
-        WHERE p.model_name = $1
-    "#);
-
-    let is_numeric_search = if let Some(ref c) = params.code {
-        c.chars().all(|x| x.is_numeric() || x.is_whitespace() || x == '-')
-    } else {
-        false
-    };
+    // Smart Starts With: digits only ("24") or alphanumeric without separators ("C2")
+    let code = params.code.as_deref().map(CodeQuery::parse);
 
-    if let Some(ref _c) = params.code {
-        if is_numeric_search {
-            // Smart Starts With (Numeric)
-            sql.push_str(" AND regexp_replace(e.code, '[^0-9]', '', 'g') LIKE $2");
-        } else {
-             // Smart Starts With (Alphanumeric)
-             sql.push_str(" AND REPLACE(REPLACE(e.code, '-', ''), ' ', '') ILIKE $2");
-        }
-    }
-    
     // Check if summary mode is requested (string "1" or "true")
-    let is_summary = params.summary.as_deref().map(|s| s == "1" || s == "true").unwrap_or(false);
+    let is_summary = flag(&params.summary);
 
     // Only apply limit if NOT fetching summary (or explicitly set)
-    if let Some(l) = params.limit {
-        sql.push_str(&format!(" LIMIT {}", l));
-    } else if !is_summary {
-         sql.push_str(" LIMIT 50");
-    }
-
-    let query = sqlx::query_as::<_, ErrorCode>(&sql)
-        .bind(&params.model);
-    
-    let query = if let Some(ref c) = params.code {
-        if is_numeric_search {
-            // Bind: "24%"
-            let clean_nums: String = c.chars().filter(|x| x.is_numeric()).collect();
-            query.bind(format!("{}%", clean_nums))
-        } else {
-             // Bind: "C2%" (Implicitly starts with)
-             let clean_code = c.replace('-', "").replace(' ', "");
-             query.bind(format!("{}%", clean_code))
-        }
-    } else {
-        query
-    };
+    let limit = params.limit.or(if is_summary { None } else { Some(50) });
 
-    let mut errors = query.fetch_all(&state.db)
+    let mut errors = state.db.search_error_codes(&params.model, code.as_ref(), limit, flag(&params.include_obsolete))
         .await
         .map_err(|e| {
             tracing::error!("Failed to search errors: {:?}", e);
@@ -94,196 +85,992 @@ pub async fn search_errors(
 
     // Skip parts population if summary is requested
     if !is_summary {
-        // Population of parts (N+1 query, simple for now)
-        for error in &mut errors {
-            let parts = sqlx::query_as::<_, SparePart>(r#"
-                SELECT sp.*, ep.ranking as ranking FROM spare_parts sp
-                JOIN error_parts ep ON sp.id = ep.part_id
-                WHERE ep.error_id = $1
-                ORDER BY ep.ranking ASC
-            "#)
-            .bind(error.id)
-            .fetch_all(&state.db)
-            .await
-            .unwrap_or_default();
-            
-            error.parts = parts;
+        if let Err(e) = attach_error_parts(state.db.as_ref(), &mut errors).await {
+            tracing::error!("Failed to load error parts: {:?}", e);
+        }
+        if let Err(e) = attach_feedback(state.db.as_ref(), &mut errors).await {
+            tracing::error!("Failed to load error feedback: {:?}", e);
         }
     }
 
     Json(errors)
 }
 
+/// A row `import_data` did not write: its position in the file (first data row is 1) and why.
+#[derive(Serialize)]
+pub struct ImportRowError {
+    pub row: usize,
+    pub code: String,
+    pub message: String,
+}
+
+/// A stored error code of an imported model that the file doesn't list.
+#[derive(Serialize, Debug)]
+pub struct MissingCode {
+    pub model: String,
+    pub code: String,
+    pub error_id: Uuid,
+}
+
+/// A field edited by hand that an imported row has another value for.
+#[derive(Serialize, Debug)]
+pub struct ImportConflict {
+    pub row: usize,
+    pub model: String,
+    pub code: String,
+    #[serde(flatten)]
+    pub conflict: merge::MergeConflict,
+}
+
+/// 400 for a multipart body that can't be read (truncated or malformed upload).
+fn invalid_upload(e: axum::extract::multipart::MultipartError) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "success": false, "message": format!("Invalid upload: {}", e) })),
+    )
+        .into_response()
+}
+
+/// Imports error codes from a CSV, XLSX or JSON sheet in one transaction. `mode` is `atomic` (default:
+/// any bad row and nothing is written) or `keep-valid` (bad rows are reported, the others written);
+/// `mapping` names the column mapping to read the file with, otherwise the best fitting one is used.
+/// `delimiter`, `quote` and `encoding` override what is detected in CSV files; `merge` sets which stored
+/// fields the file may replace (see `MergePolicies::parse`, by default fields edited by hand are kept).
+/// Stored codes of the imported models that the file doesn't list are reported, and marked obsolete
+/// with `missing=obsolete`. Each row written records its provenance: `source` names the original
+/// document (the uploaded file by default), a `Page` column the manual page of PDF extractions.
 pub async fn import_data(
     State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
     mut multipart: Multipart,
-) -> Json<serde_json::Value> {
+) -> axum::response::Response {
     let mut model = String::new();
-    let mut success_count = 0;
-    
+    let mut mode = String::new();
+    let mut mapping = String::new();
+    let mut merge = String::new();
+    let mut missing_mode = String::new();
+    let mut source = String::new();
+    let mut csv_fields: HashMap<String, String> = HashMap::new();
+
     // We need to buffer the file data because we process fields in order
     let mut file_data: Option<Vec<u8>> = None;
+    let mut file_name: Option<String> = None;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return invalid_upload(e),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        let read = match name.as_str() {
+            "model" => field.text().await.map(|value| model = value),
+            "mode" => field.text().await.map(|value| mode = value),
+            "mapping" => field.text().await.map(|value| mapping = value),
+            "merge" => field.text().await.map(|value| merge = value),
+            "missing" => field.text().await.map(|value| missing_mode = value),
+            "source" => field.text().await.map(|value| source = value),
+            name if spreadsheet::CSV_OPTION_FIELDS.contains(&name) => {
+                field.text().await.map(|value| {
+                    csv_fields.insert(name.to_string(), value);
+                })
+            }
+            "file" => {
+                file_name = field.file_name().map(|f| f.to_string());
+                field.bytes().await.map(|bytes| file_data = Some(bytes.to_vec()))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = read {
+            return invalid_upload(e);
+        }
+    }
+
+    let atomic = match mode.trim() {
+        "" | "atomic" => true,
+        "keep-valid" => false,
+        _ => return Json(serde_json::json!({ "success": false, "message": "mode must be atomic or keep-valid" })).into_response(),
+    };
+    let csv_options = match CsvOptions::from_fields(&csv_fields) {
+        Ok(options) => options,
+        Err(message) => return Json(serde_json::json!({ "success": false, "message": message })).into_response(),
+    };
+    let retire_missing = match missing_mode.trim() {
+        "" | "report" => false,
+        "obsolete" => true,
+        _ => return Json(serde_json::json!({ "success": false, "message": "missing must be report or obsolete" })).into_response(),
+    };
+    let policies = match MergePolicies::parse(&merge) {
+        Ok(policies) => policies,
+        Err(message) => return Json(serde_json::json!({ "success": false, "message": message })).into_response(),
+    };
+    let Some(bytes) = file_data else {
+        return Json(serde_json::json!({ "success": false, "message": "File is required" })).into_response();
+    };
+    let started = std::time::Instant::now();
+
+    // 1. Parse the sheet (CSV, XLSX or JSON)
+    let format = SheetFormat::detect(&bytes, file_name.as_deref());
+    let sheet = match spreadsheet::read_records(&bytes, format, &csv_options) {
+        Ok(sheet) => sheet,
+        Err(e) => return Json(serde_json::json!({ "success": false, "message": e })).into_response(),
+    };
+    let records = sheet.records;
+    if records.is_empty() {
+        return Json(serde_json::json!({ "success": false, "message": "The file has no rows" })).into_response();
+    }
+
+    // 2. Read the file's columns as the import fields
+    let headers = spreadsheet::record_headers(&records);
+    tracing::info!("CSV Record Keys: {:?}", headers);
+    let mappings = match column_mappings(state.db.as_ref()).await {
+        Ok(mappings) => mappings,
+        Err(e) => {
+            tracing::error!("Failed to load column mappings: {:?}", e);
+            return Json(serde_json::json!({ "success": false, "message": "Failed to load column mappings" })).into_response();
+        }
+    };
+    let (mapping, columns) = match choose_mapping(&mappings, mapping.trim(), &headers) {
+        Ok(chosen) => chosen,
+        Err(message) => return Json(serde_json::json!({ "success": false, "message": message })).into_response(),
+    };
+    let records: Vec<Record> = records.iter().map(|r| columns.apply(r)).collect();
+
+    // A "Model" column (multi-model exports) wins over the model sent with the upload
+    let has_model_column = records.iter().all(|r| r.get(COL_MODEL).is_some_and(|m| !m.trim().is_empty()));
+    if model.trim().is_empty() && !has_model_column {
+        return Json(serde_json::json!({ "success": false, "message": "Model name is required" })).into_response();
+    }
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        
-        if name == "model" {
-            model = field.text().await.unwrap();
-        } else if name == "file" {
-            file_data = Some(field.bytes().await.unwrap().to_vec());
-        }
-    }
-
-    if model.is_empty() {
-         return Json(serde_json::json!({ "success": false, "message": "Model name is required" }));
-    }
-
-    if let Some(bytes) = file_data {
-        // 1. Get or Create Printer (Normalize name to remove Konica Minolta prefix variants)
-        let normalized_model = model
-            .replace("Konica Minolta ", "")
-            .replace("KonicaMinolta ", "")
-            .replace("Konica Minolta", "")
-            .replace("KonicaMinolta", "")
-            .trim()
-            .to_string();
-        let printer = sqlx::query_as::<_, Printer>("SELECT * FROM printers WHERE model_name = $1")
-            .bind(&normalized_model)
-            .fetch_optional(&state.db)
-            .await
-            .unwrap();
-
-        let printer_id = if let Some(p) = printer {
-            p.id
+    // 3. Check the rows. A later row of the same code replaces an earlier one (Normalize model names
+    // to remove Konica Minolta prefix variants)
+    let mut errors: Vec<ImportRowError> = Vec::new();
+    let mut conflicts: Vec<ImportConflict> = Vec::new();
+    let mut missing: Vec<MissingCode> = Vec::new();
+    let mut kept: Vec<(usize, String)> = Vec::new();
+    let mut positions: HashMap<(String, String), usize> = HashMap::new();
+    let mut duplicates = 0;
+    for (i, record) in records.iter().enumerate() {
+        let row_model = normalize_model_name(
+            record.get(COL_MODEL).filter(|m| !m.trim().is_empty()).unwrap_or(&model),
+        );
+        let code = record.get(COL_CODE).map(String::as_str).unwrap_or_default();
+        let problem = if code.trim().is_empty() {
+            Some("Code is empty")
+        } else if row_model.is_empty() {
+            Some("Model is empty")
+        } else if page_number(record).is_err() {
+            Some("Page is not a number")
         } else {
-             let row: (Uuid,) = sqlx::query_as("INSERT INTO printers (model_name) VALUES ($1) RETURNING id")
-                .bind(&model)
-                .fetch_one(&state.db)
-                .await
-                .unwrap();
-            row.0
+            None
         };
+        if let Some(message) = problem {
+            errors.push(ImportRowError { row: i + 1, code: code.to_string(), message: message.to_string() });
+            continue;
+        }
+        match positions.entry((row_model.clone(), code.to_string())) {
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                kept[*entry.get()] = (i, row_model);
+                duplicates += 1;
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(kept.len());
+                kept.push((i, row_model));
+            }
+        }
+    }
+    let mut imported_models: Vec<&str> = kept.iter().map(|(_, m)| m.as_str()).collect();
+    imported_models.sort();
+    imported_models.dedup();
 
-        // 2. Parse CSV
-        let cursor = Cursor::new(bytes);
-        let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(true)
-            .flexible(true) // Allow variable fields just in case
-            .from_reader(cursor);
+    // Every row written carries the batch, so the whole import can be rolled back
+    let mut batch = ImportBatch {
+        id: Uuid::new_v4(),
+        source_file: file_name.clone(),
+        content_hash: bundle::sha256_hex(&bytes),
+        user_name: caller.name.clone(),
+        created_at: chrono::Utc::now(),
+        total_rows: records.len() as i32,
+        created_count: 0,
+        updated_count: 0,
+        unchanged_count: 0,
+        failed_count: errors.len() as i32,
+        obsolete_count: 0,
+        rolled_back_at: None,
+        rolled_back_by: None,
+    };
 
-        for result in rdr.deserialize::<std::collections::HashMap<String, String>>() {
-            let record = match result {
-                Ok(r) => r,
+    let committed = if atomic && !errors.is_empty() {
+        false
+    } else {
+        // 4. Stored rows per model: their ids are written over, their texts audited against
+        let mut stored: HashMap<&str, HashMap<String, ErrorCode>> = HashMap::new();
+        for row_model in &imported_models {
+            match state.db.search_error_codes(row_model, None, None, true).await {
+                Ok(codes) => {
+                    stored.insert(row_model, codes.into_iter().map(|e| (e.code.clone(), e)).collect());
+                }
                 Err(e) => {
-                    tracing::error!("CSV Parse Error: {:?}", e);
-                    continue;
+                    tracing::error!("Failed to load error codes of {}: {:?}", row_model, e);
+                    return Json(serde_json::json!({ "success": false, "message": format!("Failed to load error codes of {}", row_model) })).into_response();
                 }
-            };
+            }
+        }
 
-            if success_count == 0 {
-                let keys: Vec<_> = record.keys().collect();
-                tracing::info!("CSV Record Keys: {:?}", keys);
+        let mut audit_context = AuditContext::new(&caller, file_name.as_deref());
+        audit_context.batch_id = Some(batch.id);
+        let document = Some(source.trim()).filter(|s| !s.is_empty()).map(str::to_string).or_else(|| file_name.clone());
+        let from_pdf = document.as_deref().is_some_and(|d| d.to_lowercase().ends_with(".pdf"));
+        let rows: Vec<ErrorCodeImportRow> = kept
+            .iter()
+            .map(|(i, row_model)| {
+                let record = &records[*i];
+                let code = record.get(COL_CODE).map(String::as_str).unwrap_or_default();
+                let fields = ErrorCodeFields {
+                    code,
+                    classification: cell(record, COL_CLASSIFICATION),
+                    cause: cell(record, COL_CAUSE),
+                    measures: cell(record, COL_MEASURES),
+                    solution: cell(record, COL_SOLUTION),
+                    estimated_abnormal_parts: cell(record, COL_ESTIMATED_PARTS),
+                    correction: cell(record, COL_CORRECTION),
+                    faulty_part_isolation: cell(record, COL_FAULTY_PART_ISOLATION),
+                    note: cell(record, COL_NOTE),
+                };
+                let old = stored.get(row_model.as_str()).and_then(|codes| codes.get(code));
+                let merged = merge::merge_import(old, fields, &policies);
+                let fields = merged.fields;
+                conflicts.extend(merged.conflicts.into_iter().map(|conflict| ImportConflict {
+                    row: i + 1,
+                    model: row_model.clone(),
+                    code: code.to_string(),
+                    conflict,
+                }));
+                let id = old.map(|e| e.id).unwrap_or_else(Uuid::new_v4);
+                let page = page_number(record).ok().flatten();
+                let kind = if merged.sources.values().any(|s| s == merge::SOURCE_MANUAL) {
+                    SOURCE_KIND_MANUAL
+                } else if page.is_some() || from_pdf {
+                    SOURCE_KIND_PDF
+                } else {
+                    SOURCE_KIND_SHEET
+                };
+                let provenance = Provenance {
+                    source_kind: Some(kind.to_string()),
+                    source_document: document.clone(),
+                    source_sheet: sheet.name.clone(),
+                    source_row: Some(*i as i32 + 1),
+                    source_page: page,
+                    import_batch_id: Some(batch.id),
+                    imported_at: Some(batch.created_at),
+                };
+                let target = AuditTarget {
+                    entity: audit::ENTITY_ERROR_CODE,
+                    entity_id: Some(id),
+                    model_name: Some(row_model),
+                    entity_key: code,
+                };
+                let action = if old.is_some() { audit::ACTION_UPDATE } else { audit::ACTION_CREATE };
+                let mut changes = audit::error_code_changes(old, &fields);
+                if let Some(obsolete_at) = old.and_then(|e| e.obsolete_at) {
+                    changes.push(("obsolete_at", Some(obsolete_at.to_rfc3339()), None));
+                }
+                let audit = audit_context.entries(&target, action, changes);
+                // Re-importing the same texts is not a new revision
+                let revision = (old.is_none() || !audit.is_empty()).then(|| {
+                    let mut revision = audit_context.revision(id, action, &fields);
+                    revision.previous_obsolete_at = old.and_then(|e| e.obsolete_at);
+                    revision.previous_obsolete_batch_id = old.and_then(|e| e.obsolete_batch_id);
+                    revision
+                });
+                ErrorCodeImportRow { id, model: row_model, fields, field_sources: merged.sources, provenance, revision, audit }
+            })
+            .collect();
+
+        // 5. Stored codes the file no longer lists (the codes of refused rows count as listed)
+        let listed: HashSet<(&str, &str)> = rows.iter().map(|row| (row.model, row.fields.code)).collect();
+        let refused: HashSet<&str> = errors.iter().map(|e| e.code.as_str()).collect();
+        for (row_model, codes) in &stored {
+            for error in codes.values() {
+                if error.obsolete_at.is_none() && !listed.contains(&(*row_model, error.code.as_str())) && !refused.contains(error.code.as_str()) {
+                    missing.push(MissingCode { model: row_model.to_string(), code: error.code.clone(), error_id: error.id });
+                }
             }
+        }
+        missing.sort_by(|a, b| (&a.model, &a.code).cmp(&(&b.model, &b.code)));
+        let obsolete: Vec<ObsoleteCode> = if retire_missing {
+            let change = vec![("obsolete_at", None, Some(batch.created_at.to_rfc3339()))];
+            missing
+                .iter()
+                .map(|m| {
+                    let target = AuditTarget {
+                        entity: audit::ENTITY_ERROR_CODE,
+                        entity_id: Some(m.error_id),
+                        model_name: Some(&m.model),
+                        entity_key: &m.code,
+                    };
+                    ObsoleteCode { id: m.error_id, audit: audit_context.entries(&target, audit::ACTION_OBSOLETE, change.clone()) }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-            let code = record.get("Code").cloned().unwrap_or_default();
-            // Try different variants just in case
-            let isolation = record.get("Faulty part isolation DIPSW")
-                .or_else(|| record.get("Faulty part isolation DIPSW ")) 
-                .or_else(|| record.get("Faulty part isolation"))
-                .cloned();
-
-            let query_res = sqlx::query(r#"
-                INSERT INTO error_codes (
-                    printer_id, code, classification, cause, measures, solution, 
-                    estimated_abnormal_parts, correction, faulty_part_isolation, note
-                ) 
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-                ON CONFLICT (printer_id, code) DO UPDATE SET
-                    classification = EXCLUDED.classification,
-                    cause = EXCLUDED.cause,
-                    measures = EXCLUDED.measures,
-                    solution = EXCLUDED.solution,
-                    estimated_abnormal_parts = EXCLUDED.estimated_abnormal_parts,
-                    correction = EXCLUDED.correction,
-                    faulty_part_isolation = EXCLUDED.faulty_part_isolation,
-                    note = EXCLUDED.note
-            "#)
-            .bind(printer_id)
-            .bind(&code)
-            .bind(record.get("Classification"))
-            .bind(record.get("Cause"))
-            .bind(record.get("Measures to take when an alert occurs"))
-            .bind(record.get("Solution"))
-            .bind(record.get("Estimated abnormal parts"))
-            .bind(record.get("Correction"))
-            .bind(&isolation)
-            .bind(record.get("Note"))
-            .execute(&state.db)
-            .await;
-
-            match query_res {
-                Ok(res) => {
-                    success_count += 1;
-                    if code == "C-0101" {
-                        tracing::info!("C-0101 DB Update Success. Rows affected: {}. Bound Isolation: {:?}", res.rows_affected(), isolation);
-                    }
-                },
-                Err(e) => tracing::error!("Database Error during import for {}: {:?}", code, e),
+        // 6. Write everything in one transaction
+        match state.db.import_error_codes(&mut batch, &rows, &obsolete, atomic).await {
+            Ok(failures) => {
+                let committed = !atomic || failures.is_empty();
+                for failure in failures {
+                    tracing::error!("Database Error during import for {}: {}", rows[failure.row].fields.code, failure.message);
+                    errors.push(ImportRowError {
+                        row: kept[failure.row].0 + 1,
+                        code: rows[failure.row].fields.code.to_string(),
+                        message: failure.message,
+                    });
+                }
+                committed
             }
+            Err(e) => {
+                tracing::error!("Import of {:?} failed: {:?}", file_name, e);
+                return Json(serde_json::json!({ "success": false, "message": "Import failed, nothing was written" })).into_response();
+            }
+        }
+    };
+    errors.sort_by_key(|e| e.row);
+    conflicts.retain(|c| !errors.iter().any(|e| e.row == c.row));
+
+    let elapsed = started.elapsed();
+    let rows_per_second = (records.len() as f64 / elapsed.as_secs_f64().max(0.001)).round();
+    tracing::info!("Import of {} rows took {} ms ({} rows/s)", records.len(), elapsed.as_millis(), rows_per_second);
+
+    let label = if model.trim().is_empty() { imported_models.join(", ") } else { model };
+    let written = batch.created_count + batch.updated_count + batch.unchanged_count;
+    let message = match (committed, errors.len()) {
+        (true, 0) => format!("Imported {} error codes for {}", written, label),
+        (true, failed) => format!("Imported {} error codes for {}, {} rows failed", written, label, failed),
+        (false, failed) => format!("Nothing imported for {}: {} rows failed", label, failed),
+    };
+    Json(serde_json::json!({
+        "success": committed,
+        "message": message,
+        "batch_id": committed.then_some(batch.id),
+        "mode": if atomic { "atomic" } else { "keep-valid" },
+        "mapping": mapping.name,
+        "columns": columns.fields,
+        "ignored_columns": columns.unmapped,
+        "csv": sheet.dialect,
+        "rows": records.len(),
+        "created": batch.created_count,
+        "updated": batch.updated_count,
+        "unchanged": batch.unchanged_count,
+        "duplicates": duplicates,
+        "failed": errors.len(),
+        "errors": errors,
+        "conflicts": if committed { conflicts } else { Vec::new() },
+        "missing": if committed { missing } else { Vec::new() },
+        "obsolete": batch.obsolete_count,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "rows_per_second": rows_per_second,
+    }))
+    .into_response()
+}
+
+/// The built-in column mapping followed by the saved ones.
+async fn column_mappings(db: &dyn Repository) -> Result<Vec<ColumnMapping>, sqlx::Error> {
+    let mut mappings = vec![ColumnMapping {
+        id: Uuid::nil(),
+        name: spreadsheet::DEFAULT_MAPPING.to_string(),
+        description: Some("Headers written by the export".to_string()),
+        columns: sqlx::types::Json(spreadsheet::default_mapping()),
+    }];
+    mappings.extend(db.column_mappings().await?);
+    Ok(mappings)
+}
+
+/// The mapping named with the upload, or else the one reading the most columns of the file among those
+/// that find every required field (the built-in one wins ties).
+fn choose_mapping<'a>(
+    mappings: &'a [ColumnMapping],
+    name: &str,
+    headers: &[String],
+) -> Result<(&'a ColumnMapping, ColumnMatch), String> {
+    let missing = |found: &ColumnMatch| {
+        format!(
+            "no column found for {}; columns in the file: {}",
+            found.missing.join(", "),
+            headers.join(", ")
+        )
+    };
+    if !name.is_empty() {
+        let mapping = mappings
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| format!("Unknown column mapping {}", name))?;
+        let found = ColumnMatch::new(&mapping.columns, headers);
+        if !found.missing.is_empty() {
+            return Err(format!("Mapping {}: {}", name, missing(&found)));
+        }
+        return Ok((mapping, found));
+    }
+
+    let mut best: Option<(&ColumnMapping, ColumnMatch)> = None;
+    for mapping in mappings {
+        let found = ColumnMatch::new(&mapping.columns, headers);
+        let better = match &best {
+            None => true,
+            Some((_, current)) => found.fields.len() > current.fields.len(),
+        };
+        if found.missing.is_empty() && better {
+            best = Some((mapping, found));
+        }
+    }
+    best.ok_or_else(|| {
+        format!(
+            "No column mapping fits the file: {}",
+            missing(&ColumnMatch { missing: spreadsheet::REQUIRED_FIELDS.to_vec(), ..Default::default() })
+        )
+    })
+}
+
+/// A non-empty cell of an import row. Empty cells are stored as NULL, the same way they are exported.
+fn cell<'a>(record: &'a Record, column: &str) -> Option<&'a str> {
+    record.get(column).map(|v| v.as_str()).filter(|v| !v.trim().is_empty())
+}
+
+/// The manual page of a row, if it has one (XLSX number cells read as "12.0").
+fn page_number(record: &Record) -> Result<Option<i32>, std::num::ParseIntError> {
+    cell(record, COL_PAGE)
+        .map(|page| {
+            let page = page.trim();
+            page.strip_suffix(".0").unwrap_or(page).parse()
+        })
+        .transpose()
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    /// One model or a comma-separated list ("C4080,C4070")
+    pub model: String,
+    /// csv (default), xlsx or json
+    pub format: Option<String>,
+}
+
+/// Exports error codes with the headers `import_data` accepts, so the file can be edited and imported again.
+/// Exports of several models get a leading "Model" column, which the import honours per row.
+pub async fn export_errors(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> impl IntoResponse {
+    let Some(format) = SheetFormat::from_param(params.format.as_deref()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "format must be csv, xlsx or json" })),
+        )
+            .into_response();
+    };
+    let models = parse_model_list(&params.model);
+    if models.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "Model name is required" })),
+        )
+            .into_response();
+    }
+
+    let rows = state.db.export_error_codes(&models).await;
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Failed to export errors: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to export error codes" })),
+            )
+                .into_response();
         }
+    };
+
+    let multi_model = models.len() > 1;
+    let mut headers: Vec<&str> = Vec::new();
+    if multi_model {
+        headers.push(COL_MODEL);
     }
+    headers.extend(ERROR_CODE_COLUMNS);
+
+    let cells: Vec<Vec<Option<String>>> = rows
+        .into_iter()
+        .map(|row| {
+            let e = row.error;
+            let mut cells = Vec::with_capacity(headers.len());
+            if multi_model {
+                cells.push(Some(row.model_name));
+            }
+            cells.extend([
+                Some(e.code),
+                e.classification,
+                e.cause,
+                e.measures,
+                e.solution,
+                e.estimated_abnormal_parts,
+                e.correction,
+                e.faulty_part_isolation,
+                e.note,
+            ]);
+            cells
+        })
+        .collect();
 
-    Json(serde_json::json!({ "success": true, "message": format!("Imported {} error codes for {}", success_count, model) }))
+    match spreadsheet::write_records(&headers, &cells, format) {
+        Ok(body) => {
+            let disposition = format!("attachment; filename=\"error_codes_{}.{}\"", models.join("_"), format.extension());
+            (
+                [(header::CONTENT_TYPE, format.content_type().to_string()), (header::CONTENT_DISPOSITION, disposition)],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to write export: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to write export file" })),
+            )
+                .into_response()
+        }
+    }
 }
 
 #[derive(Deserialize)]
-pub struct DipSwitchImport {
+pub struct ImportDipswParams {
+    /// Remove switches of the imported models that are not in the payload ("1" or "true")
+    pub prune: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportItemStatus {
+    Inserted,
+    Updated,
+    Rejected,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DipSwitchImportItemReport {
+    pub index: usize,
     pub model_name: String,
     pub switch_number: i32,
     pub bit_number: i32,
-    pub function_name: Option<String>,
-    pub setting_0: Option<String>,
-    pub setting_1: Option<String>,
-    pub default_val: Option<String>,
+    pub status: ImportItemStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DipSwitchModelReport {
+    pub model_name: String,
+    pub inserted: usize,
+    pub updated: usize,
+    pub rejected: usize,
+    pub failed: usize,
+    pub removed: u64,
+    /// Why nothing of the model was written; the models before it in the report are committed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DipSwitchImportReport {
+    pub success: bool,
+    pub models: Vec<DipSwitchModelReport>,
+    pub items: Vec<DipSwitchImportItemReport>,
+}
+
+/// Checks one payload item; `seen` maps every (switch, bit) already claimed in the same model to its item index.
+fn validate_dipsw_item(
+    item: &DipSwitchImport,
+    seen: &HashMap<(i32, i32), usize>,
+) -> Result<(), String> {
+    if item.switch_number < 1 {
+        return Err(format!("switch_number must be positive, got {}", item.switch_number));
+    }
+    if !(0..=7).contains(&item.bit_number) {
+        return Err(format!("bit_number must be between 0 and 7, got {}", item.bit_number));
+    }
+    if let Some(end) = item.end_bit {
+        if end < item.bit_number || end > 7 {
+            return Err(format!("end_bit must be between {} and 7, got {}", item.bit_number, end));
+        }
+    }
+    for bit in item.bit_number..=item.last_bit() {
+        if let Some(first) = seen.get(&(item.switch_number, bit)) {
+            return Err(format!("SW {}-{} is already defined by item {}", item.switch_number, bit, first));
+        }
+    }
+    if item.defaults.iter().any(|d| d.variant.trim().is_empty()) {
+        return Err("default variant labels must not be empty".to_string());
+    }
+
+    if let Some(level) = &item.risk_level {
+        if !RISK_LEVELS.contains(&level.as_str()) {
+            return Err(format!("risk_level must be one of {}, got {}", RISK_LEVELS.join(", "), level));
+        }
+    }
+    for rule in &item.rules {
+        if rule.kind != "requires" && rule.kind != "conflicts" {
+            return Err(format!("rule kind must be requires or conflicts, got {}", rule.kind));
+        }
+        if rule.target_switch < 1 || !(0..=7).contains(&rule.target_bit) {
+            return Err(format!("rule target SW {}-{} is out of range", rule.target_switch, rule.target_bit));
+        }
+        if calculator::rule_targets_itself(item.switch_number, item.bit_number, item.last_bit(), rule) {
+            return Err("a rule cannot target its own switch".to_string());
+        }
+    }
+
+    let width = item.last_bit() - item.bit_number + 1;
+    let max_value = (1 << width) - 1;
+    for rule in &item.rules {
+        if !(0..=max_value).contains(&rule.when_value) {
+            return Err(format!("rule value {} does not fit bits {}-{}", rule.when_value, item.bit_number, item.last_bit()));
+        }
+    }
+    let defaults = item.defaults.iter().map(|d| d.value.as_deref()).chain([item.default_val.as_deref()]);
+    for default in defaults {
+        if calculator::parse_setting_value(default, width).is_none() {
+            return Err(format!("default {} does not fit bits {}-{}", default.unwrap_or(""), item.bit_number, item.last_bit()));
+        }
+    }
+    let mut values = std::collections::HashSet::new();
+    for v in &item.values {
+        if !(0..=max_value).contains(&v.value) {
+            return Err(format!("value {} does not fit bits {}-{}", v.value, item.bit_number, item.last_bit()));
+        }
+        if !values.insert(v.value) {
+            return Err(format!("value {} is listed twice", v.value));
+        }
+    }
+    Ok(())
+}
+
+/// The stored switch, among the ones that stay, whose bits the item would overlap.
+fn stored_overlap<'a>(item: &DipSwitchImport, stored: impl Iterator<Item = &'a DipSwitch>) -> Option<String> {
+    let bits = item.bit_number..=item.last_bit();
+    stored
+        .filter(|s| s.switch_number == item.switch_number)
+        .find(|s| {
+            let last = s.end_bit.unwrap_or(s.bit_number);
+            s.bit_number <= *bits.end() && *bits.start() <= last
+        })
+        .map(|s| {
+            format!(
+                "SW {}-{} overlaps the stored SW {}-{}{}; include it in the import or import with prune",
+                item.switch_number,
+                item.bit_number,
+                s.switch_number,
+                s.bit_number,
+                s.end_bit.map(|e| format!("..{}", e)).unwrap_or_default(),
+            )
+        })
+}
+
+/// The first rule of the item whose target is not the first bit of a switch, or whose value does not fit it.
+fn rule_target_problem(item: &DipSwitchImport, widths: &HashMap<(i32, i32), i32>) -> Option<String> {
+    item.rules.iter().find_map(|rule| match widths.get(&(rule.target_switch, rule.target_bit)) {
+        None => Some(format!("rule target SW {}-{} does not exist on this model", rule.target_switch, rule.target_bit)),
+        Some(&width) if !(0..1 << width).contains(&rule.target_value) => Some(format!(
+            "rule target value {} does not fit SW {}-{}",
+            rule.target_value, rule.target_switch, rule.target_bit
+        )),
+        Some(_) => None,
+    })
+}
+
+/// Imports one model's switches: rejected items are reported, the valid ones are written together
+/// (see `Repository::write_dipswitches`), so a failing row is reported without losing the rest of the model.
+async fn import_dipsw_model(
+    db: &dyn Repository,
+    model: &str,
+    items: &[(usize, &DipSwitchImport)],
+    prune: bool,
+    audit_context: &AuditContext,
+    report: &mut Vec<DipSwitchImportItemReport>,
+) -> Result<DipSwitchModelReport, sqlx::Error> {
+    let mut summary = DipSwitchModelReport { model_name: model.to_string(), ..Default::default() };
+    let mut seen: HashMap<(i32, i32), usize> = HashMap::new();
+    let mut valid: Vec<(usize, &DipSwitchImport)> = Vec::new();
+    let mut statuses: Vec<(usize, &DipSwitchImport, ImportItemStatus, Option<String>)> = Vec::new();
+
+    let filter = DipSwitchFilter { models: vec![model.to_string()], ..Default::default() };
+    let stored: HashMap<(i32, i32), DipSwitch> = db.dipswitches(&filter)
+        .await?
+        .into_iter()
+        .map(|s| ((s.switch_number, s.bit_number), s))
+        .collect();
+
+    for &(index, item) in items {
+        match validate_dipsw_item(item, &seen) {
+            Err(msg) => statuses.push((index, item, ImportItemStatus::Rejected, Some(msg))),
+            Ok(()) => {
+                for bit in item.bit_number..=item.last_bit() {
+                    seen.insert((item.switch_number, bit), index);
+                }
+                valid.push((index, item));
+            }
+        }
+    }
+
+    // Every (switch, bit) named in the payload is kept, even when its row was rejected or failed
+    let keep: Vec<(i32, i32)> = items.iter().map(|(_, item)| (item.switch_number, item.bit_number)).collect();
+
+    // Stored switches that stay must not overlap the written ones, and rules must target a switch that
+    // exists afterwards. A rejection keeps the stored row of that key (or drops a rule target), which can
+    // make another item fail, hence the repeat until nothing changes.
+    loop {
+        let rewritten: HashSet<(i32, i32)> = valid.iter().map(|(_, item)| (item.switch_number, item.bit_number)).collect();
+        let remaining = stored
+            .values()
+            .filter(|s| !rewritten.contains(&(s.switch_number, s.bit_number)))
+            .filter(|s| !prune || keep.contains(&(s.switch_number, s.bit_number)));
+        let widths: HashMap<(i32, i32), i32> = remaining
+            .clone()
+            .map(|s| ((s.switch_number, s.bit_number), s.width()))
+            .chain(valid.iter().map(|(_, item)| ((item.switch_number, item.bit_number), item.last_bit() - item.bit_number + 1)))
+            .collect();
+        let overlaps: Vec<(usize, String)> = valid
+            .iter()
+            .enumerate()
+            .filter_map(|(position, (_, item))| {
+                stored_overlap(item, remaining.clone())
+                    .or_else(|| rule_target_problem(item, &widths))
+                    .map(|msg| (position, msg))
+            })
+            .collect();
+        if overlaps.is_empty() {
+            break;
+        }
+        for (position, msg) in overlaps.into_iter().rev() {
+            let (index, item) = valid.remove(position);
+            statuses.push((index, item, ImportItemStatus::Rejected, Some(msg)));
+        }
+    }
+
+    let to_write: Vec<&DipSwitchImport> = valid.iter().map(|&(_, item)| item).collect();
+    let write = db.write_dipswitches(model, &to_write, prune.then_some(keep.as_slice())).await?;
+
+    let mut audit_entries = Vec::new();
+    for (&(_, item), outcome) in valid.iter().zip(&write.outcomes) {
+        if outcome.is_err() {
+            continue;
+        }
+        let old = stored.get(&(item.switch_number, item.bit_number));
+        let key = format!("{}-{}", item.switch_number, item.bit_number);
+        let target = AuditTarget { entity: audit::ENTITY_DIPSWITCH, entity_id: old.map(|s| s.id), model_name: Some(model), entity_key: &key };
+        let action = if old.is_some() { audit::ACTION_UPDATE } else { audit::ACTION_CREATE };
+        audit_entries.extend(audit_context.entries(&target, action, audit::dipswitch_changes(old, Some(item))));
+    }
+    if prune {
+        for old in stored.values().filter(|s| !keep.contains(&(s.switch_number, s.bit_number))) {
+            let key = format!("{}-{}", old.switch_number, old.bit_number);
+            let target = AuditTarget { entity: audit::ENTITY_DIPSWITCH, entity_id: Some(old.id), model_name: Some(model), entity_key: &key };
+            audit_entries.extend(audit_context.entries(&target, audit::ACTION_DELETE, audit::dipswitch_changes(Some(old), None)));
+        }
+    }
+    record_audit(db, &audit_entries).await;
+
+    for (&(index, item), outcome) in valid.iter().zip(write.outcomes) {
+        let (status, message) = match outcome {
+            Ok(true) => (ImportItemStatus::Inserted, None),
+            Ok(false) => (ImportItemStatus::Updated, None),
+            Err(e) => (ImportItemStatus::Failed, Some(e)),
+        };
+        statuses.push((index, item, status, message));
+    }
+    summary.removed = write.removed;
+
+    for (index, item, status, message) in statuses {
+        match status {
+            ImportItemStatus::Inserted => summary.inserted += 1,
+            ImportItemStatus::Updated => summary.updated += 1,
+            ImportItemStatus::Rejected => summary.rejected += 1,
+            ImportItemStatus::Failed => summary.failed += 1,
+        }
+        report.push(DipSwitchImportItemReport {
+            index,
+            model_name: model.to_string(),
+            switch_number: item.switch_number,
+            bit_number: item.bit_number,
+            status,
+            message,
+        });
+    }
+
+    Ok(summary)
 }
 
 pub async fn import_dipsw(
     State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Query(params): Query<ImportDipswParams>,
     Json(payload): Json<Vec<DipSwitchImport>>,
 ) -> impl IntoResponse {
-    let mut tx = state.db.begin().await.unwrap();
-    
-    // Normalize model name from the first item if available
-    if let Some(first) = payload.first() {
-        let normalized_model = first.model_name.replace("Konica Minolta ", "").replace("KonicaMinolta", "").trim().to_string();
-        
-        // Clear existing switches for this model to prevent duplicates
-        sqlx::query("DELETE FROM dip_switches WHERE model_name = $1")
-            .bind(&normalized_model)
-            .execute(&mut *tx)
-            .await
-            .unwrap();
-
-        for item in payload {
-            sqlx::query(r#"
-                INSERT INTO dip_switches (model_name, switch_number, bit_number, function_name, setting_0, setting_1, default_val)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
-            "#)
-            .bind(&normalized_model)
-            .bind(item.switch_number)
-            .bind(item.bit_number)
-            .bind(&item.function_name)
-            .bind(&item.setting_0)
-            .bind(&item.setting_1)
-            .bind(&item.default_val)
-            .execute(&mut *tx)
-            .await
-            .unwrap();
+    let audit_context = AuditContext::new(&caller, None);
+    run_dipsw_import(state.db.as_ref(), &payload, flag(&params.prune), &audit_context).await
+}
+
+/// Same as `import_dipsw`, for a CSV/XLSX/JSON sheet with the `DIPSW_COLUMNS` headers (as written by
+/// `export_dipswitches`). A `model` form field fills in rows without `model_name`.
+pub async fn import_dipsw_file(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Query(params): Query<ImportDipswParams>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut model = String::new();
+    let mut csv_fields: HashMap<String, String> = HashMap::new();
+    let mut file_data: Option<Vec<u8>> = None;
+    let mut file_name: Option<String> = None;
+
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) => match field.name() {
+                Some("model") => model = field.text().await.unwrap_or_default(),
+                Some(name) if spreadsheet::CSV_OPTION_FIELDS.contains(&name) => {
+                    let name = name.to_string();
+                    csv_fields.insert(name, field.text().await.unwrap_or_default());
+                }
+                Some("file") => {
+                    file_name = field.file_name().map(|f| f.to_string());
+                    file_data = field.bytes().await.ok().map(|b| b.to_vec());
+                }
+                _ => {}
+            },
+            Ok(None) => break,
+            Err(e) => return invalid_upload(e),
         }
     }
-    
-    tx.commit().await.unwrap();
-    Json("Imported")
+
+    let Some(bytes) = file_data else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "A file is required" })),
+        )
+            .into_response();
+    };
+    let format = SheetFormat::detect(&bytes, file_name.as_deref());
+    let records = match CsvOptions::from_fields(&csv_fields).and_then(|options| spreadsheet::read_records(&bytes, format, &options)) {
+        Ok(sheet) => sheet.records,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "success": false, "message": e }))).into_response();
+        }
+    };
+
+    let mut payload = Vec::with_capacity(records.len());
+    for (index, record) in records.iter().enumerate() {
+        match dipsw_from_record(record, &model) {
+            Ok(item) => payload.push(item),
+            Err(e) => {
+                // Row 1 is the header
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "success": false, "message": format!("Row {}: {}", index + 2, e) })),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    let audit_context = AuditContext::new(&caller, file_name.as_deref());
+    run_dipsw_import(state.db.as_ref(), &payload, flag(&params.prune), &audit_context).await
+}
+
+/// Rebuilds an import item from a sheet row: numbers and booleans are parsed, the JSON columns decoded.
+fn dipsw_from_record(record: &Record, fallback_model: &str) -> Result<DipSwitchImport, String> {
+    let mut object = serde_json::Map::new();
+    for (column, raw) in record {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        let value = match column.as_str() {
+            "switch_number" | "bit_number" | "end_bit" => serde_json::Value::from(
+                raw.parse::<i32>().map_err(|_| format!("{} is not a number: {}", column, raw))?,
+            ),
+            "temporary_only" => serde_json::Value::Bool(matches!(raw.to_lowercase().as_str(), "1" | "true" | "yes")),
+            "defaults" | "values" | "rules" => {
+                serde_json::from_str(raw).map_err(|e| format!("{} is not valid JSON: {}", column, e))?
+            }
+            _ => serde_json::Value::String(raw.to_string()),
+        };
+        object.insert(column.clone(), value);
+    }
+    object
+        .entry("model_name")
+        .or_insert_with(|| serde_json::Value::String(fallback_model.to_string()));
+    serde_json::from_value(serde_json::Value::Object(object)).map_err(|e| e.to_string())
+}
+
+/// Groups the payload by each item's own model and imports model by model.
+async fn run_dipsw_import(
+    db: &dyn Repository,
+    payload: &[DipSwitchImport],
+    prune: bool,
+    audit_context: &AuditContext,
+) -> axum::response::Response {
+    let mut items: Vec<DipSwitchImportItemReport> = Vec::new();
+    let mut models: Vec<DipSwitchModelReport> = Vec::new();
+
+    // Group by each item's own model, keeping payload order within a model
+    let mut by_model: BTreeMap<String, Vec<(usize, &DipSwitchImport)>> = BTreeMap::new();
+    for (index, item) in payload.iter().enumerate() {
+        let model = normalize_model_name(&item.model_name);
+        if model.is_empty() {
+            items.push(DipSwitchImportItemReport {
+                index,
+                model_name: item.model_name.clone(),
+                switch_number: item.switch_number,
+                bit_number: item.bit_number,
+                status: ImportItemStatus::Rejected,
+                message: Some("model_name is required".to_string()),
+            });
+            continue;
+        }
+        by_model.entry(model).or_default().push((index, item));
+    }
+
+    for (model, model_items) in &by_model {
+        match import_dipsw_model(db, model, model_items, prune, audit_context, &mut items).await {
+            Ok(summary) => models.push(summary),
+            Err(e) => {
+                // Each model is written in its own transaction: report this one as not written and go on
+                tracing::error!("DIP switch import aborted for {}: {:?}", model, e);
+                let message = format!("Import aborted for {}: {}", model, e);
+                items.extend(model_items.iter().map(|&(index, item)| DipSwitchImportItemReport {
+                    index,
+                    model_name: model.clone(),
+                    switch_number: item.switch_number,
+                    bit_number: item.bit_number,
+                    status: ImportItemStatus::Failed,
+                    message: Some(message.clone()),
+                }));
+                models.push(DipSwitchModelReport {
+                    model_name: model.clone(),
+                    failed: model_items.len(),
+                    error: Some(message),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    items.sort_by_key(|i| i.index);
+    let success = items
+        .iter()
+        .all(|i| matches!(i.status, ImportItemStatus::Inserted | ImportItemStatus::Updated));
+    let status = if models.iter().any(|m| m.error.is_some()) {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(DipSwitchImportReport { success, models, items })).into_response()
 }
 
 #[derive(Deserialize)]
@@ -291,45 +1078,2063 @@ pub struct DipSwitchParams {
     pub model: Option<String>,
     pub switch: Option<i32>,
     pub bit: Option<i32>,
+    /// Default column to report in `default_val` (e.g. "EU", "Def2"). Falls back to the primary default.
+    pub variant: Option<String>,
+}
+
+/// Fills in defaults, value tables and rules. With `variant`, `default_val` reports that default column instead
+/// of the primary one; switches without that column keep their primary default.
+async fn attach_dipswitch_details<'a>(
+    db: &dyn Repository,
+    switches: impl IntoIterator<Item = &'a mut DipSwitch>,
+    variant: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let mut switches: Vec<&mut DipSwitch> = switches.into_iter().collect();
+    let ids: Vec<Uuid> = switches.iter().map(|s| s.id).collect();
+    let mut defaults = db.dipswitch_defaults(&ids).await?;
+    let mut values = db.dipswitch_values(&ids).await?;
+    let mut rules = db.dipswitch_rules(&ids).await?;
+
+    for sw in switches.iter_mut() {
+        sw.defaults = defaults.remove(&sw.id).unwrap_or_default();
+        sw.values = values.remove(&sw.id).unwrap_or_default();
+        sw.rules = rules.remove(&sw.id).unwrap_or_default();
+        let width = sw.width() as usize;
+        for v in &mut sw.values {
+            v.bits = format!("{:0width$b}", v.value, width = width);
+        }
+
+        if let Some(variant) = variant {
+            if let Some(d) = sw.defaults.iter().find(|d| d.variant.eq_ignore_ascii_case(variant.trim())) {
+                sw.default_val = d.value.clone();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// All switches of one (already normalized) model with their details, in switch/bit order.
+async fn load_model_dipswitches(
+    db: &dyn Repository,
+    model: &str,
+    variant: Option<&str>,
+) -> Result<Vec<DipSwitch>, sqlx::Error> {
+    let filter = DipSwitchFilter { models: vec![model.to_string()], ..Default::default() };
+    let mut switches = db.dipswitches(&filter).await?;
+    attach_dipswitch_details(db, &mut switches, variant).await?;
+    Ok(switches)
 }
 
 pub async fn get_dipswitches(
     State(state): State<Arc<AppState>>,
     Query(params): Query<DipSwitchParams>,
-) -> Json<Vec<crate::models::DipSwitch>> {
-    let mut sql = String::from("SELECT * FROM dip_switches WHERE 1=1");
-    let mut query_model = None;
-    
-    if let Some(model) = &params.model {
-        let normalized = model.replace("Konica Minolta ", "").replace("KonicaMinolta", "").trim().to_string();
-        sql.push_str(" AND model_name = $1");
-        query_model = Some(normalized);
-    }
-    
-    if let Some(sw) = params.switch {
-        sql.push_str(&format!(" AND switch_number = {}", sw));
-    }
-    
-    if let Some(bit) = params.bit {
-        sql.push_str(&format!(" AND bit_number = {}", bit));
-    }
-    
-    sql.push_str(" ORDER BY switch_number ASC, bit_number ASC");
-    
-    let mut query = sqlx::query_as::<_, crate::models::DipSwitch>(&sql);
-    
-    if let Some(m) = &query_model {
-        query = query.bind(m);
-    }
-    
-    let switches = query
-        .fetch_all(&state.db)
+) -> Json<Vec<DipSwitch>> {
+    let filter = DipSwitchFilter {
+        models: params.model.as_deref().map(normalize_model_name).into_iter().collect(),
+        switch: params.switch,
+        bit: params.bit,
+        since: 0,
+    };
+
+    let mut switches = state.db.dipswitches(&filter)
         .await
         .map_err(|e| {
             tracing::error!("Failed to fetch dipswitches: {:?}", e);
             e
         })
         .unwrap_or_default();
-        
+
+    if let Err(e) = attach_dipswitch_details(state.db.as_ref(), &mut switches, params.variant.as_deref()).await {
+        tracing::error!("Failed to fetch dipswitch details: {:?}", e);
+    }
+
     Json(switches)
 }
+
+#[derive(Deserialize)]
+pub struct CompareParams {
+    pub from: String,
+    pub to: String,
+    /// Minimum function-text similarity (0..1) for two switches to count as the same function
+    pub threshold: Option<f64>,
+    pub variant: Option<String>,
+}
+
+pub async fn compare_dipswitches(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CompareParams>,
+) -> impl IntoResponse {
+    let from_model = normalize_model_name(&params.from);
+    let to_model = normalize_model_name(&params.to);
+    let threshold = params.threshold.unwrap_or(compare::DEFAULT_THRESHOLD).clamp(0.0, 1.0);
+
+    let (from, to) = match (
+        load_model_dipswitches(state.db.as_ref(), &from_model, params.variant.as_deref()).await,
+        load_model_dipswitches(state.db.as_ref(), &to_model, params.variant.as_deref()).await,
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Failed to load dipswitches for comparison: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load DIP switches" })),
+            )
+                .into_response();
+        }
+    };
+
+    for (model, switches) in [(&from_model, &from), (&to_model, &to)] {
+        if switches.is_empty() {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": format!("No DIP switches for {}", model) })),
+            )
+                .into_response();
+        }
+    }
+
+    Json(compare::compare_models(from_model, from, to_model, to, threshold)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct DipSwitchSearchParams {
+    pub q: String,
+    /// Restrict to one model; all models when omitted
+    pub model: Option<String>,
+    pub limit: Option<i64>,
+    pub variant: Option<String>,
+}
+
+pub async fn search_dipswitches(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DipSwitchSearchParams>,
+) -> Json<Vec<DipSwitchSearchHit>> {
+    // Only letters and digits are searched
+    let words: Vec<String> = params
+        .q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect();
+    if words.is_empty() {
+        return Json(Vec::new());
+    }
+    let limit = params.limit.unwrap_or(50).clamp(1, 200);
+    let model = params.model.as_deref().map(normalize_model_name);
+
+    let mut hits = state.db.search_dipswitches(&words, model.as_deref(), limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to search dipswitches: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    if let Err(e) = attach_dipswitch_details(state.db.as_ref(), hits.iter_mut().map(|h| &mut h.switch), params.variant.as_deref()).await {
+        tracing::error!("Failed to fetch dipswitch details: {:?}", e);
+    }
+
+    Json(hits)
+}
+
+#[derive(Deserialize)]
+pub struct CalculateRequest {
+    pub model: String,
+    /// Default column to start from; the primary default when omitted
+    pub variant: Option<String>,
+    /// Values that differ from the defaults
+    #[serde(default)]
+    pub settings: Vec<DipSwitchSetting>,
+}
+
+/// Computes the switch bytes for a set of changes and checks them. Combinations that break a dependency
+/// rule or name unknown switches are refused with 422, warnings alone still return 200.
+pub async fn calculate_dipswitches(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CalculateRequest>,
+) -> impl IntoResponse {
+    let model = normalize_model_name(&request.model);
+    let switches = match load_model_dipswitches(state.db.as_ref(), &model, request.variant.as_deref()).await {
+        Ok(switches) if !switches.is_empty() => switches,
+        Ok(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": format!("No DIP switches for {}", model) })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to load dipswitches for {}: {:?}", model, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load DIP switches" })),
+            )
+                .into_response();
+        }
+    };
+
+    let calculation = calculator::calculate(&switches, &request.settings);
+    let status = if calculation.allowed { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+    (status, Json(calculation)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ProfileParams {
+    pub model: Option<String>,
+}
+
+pub async fn get_dipswitch_profiles(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ProfileParams>,
+) -> Json<Vec<DipSwitchProfile>> {
+    let model = params.model.as_deref().map(normalize_model_name);
+    let profiles = state.db.dipswitch_profiles(model.as_deref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch dipswitch profiles: {:?}", e);
+        e
+    })
+    .unwrap_or_default();
+
+    Json(profiles)
+}
+
+#[derive(Deserialize)]
+pub struct SaveProfileRequest {
+    pub model: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub variant: Option<String>,
+    pub settings: Vec<DipSwitchSetting>,
+}
+
+/// Saves (or replaces, by model and name) a profile. It is checked like a calculation and refused when not allowed;
+/// the warnings are returned with the saved profile.
+pub async fn save_dipswitch_profile(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SaveProfileRequest>,
+) -> impl IntoResponse {
+    let model = normalize_model_name(&request.model);
+    if model.is_empty() || request.name.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "Model and profile name are required" })),
+        )
+            .into_response();
+    }
+
+    let switches = match load_model_dipswitches(state.db.as_ref(), &model, request.variant.as_deref()).await {
+        Ok(switches) => switches,
+        Err(e) => {
+            tracing::error!("Failed to load dipswitches for {}: {:?}", model, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load DIP switches" })),
+            )
+                .into_response();
+        }
+    };
+
+    let calculation = calculator::calculate(&switches, &request.settings);
+    if !calculation.allowed {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "success": false,
+                "message": "Profile refused: the combination is not allowed",
+                "warnings": calculation.warnings,
+            })),
+        )
+            .into_response();
+    }
+
+    let saved = state.db.save_dipswitch_profile(
+        &model,
+        request.name.trim(),
+        request.description.as_deref(),
+        request.variant.as_deref(),
+        &request.settings,
+    )
+    .await;
+
+    match saved {
+        Ok(profile) => Json(serde_json::json!({
+            "success": true,
+            "profile": profile,
+            "warnings": calculation.warnings,
+        }))
+        .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to save dipswitch profile: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to save profile" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// A saved profile with its calculation re-run against the current switch data and rules.
+pub async fn get_dipswitch_profile(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let profile = state.db.dipswitch_profile(id).await;
+
+    let profile = match profile {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": "Profile not found" })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch dipswitch profile: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch profile" })),
+            )
+                .into_response();
+        }
+    };
+
+    let switches = match load_model_dipswitches(state.db.as_ref(), &profile.model_name, profile.variant.as_deref()).await {
+        Ok(switches) => switches,
+        Err(e) => {
+            tracing::error!("Failed to load dipswitches for {}: {:?}", profile.model_name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load DIP switches" })),
+            )
+                .into_response();
+        }
+    };
+    let calculation = calculator::calculate(&switches, &profile.settings);
+    Json(serde_json::json!({ "profile": profile, "calculation": calculation })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct DipSwitchExportParams {
+    pub model: String,
+    /// csv (default), xlsx or json
+    pub format: Option<String>,
+}
+
+/// Exports a model's switches with the `DipSwitchImport` fields as headers, ready for `import_dipsw_file`.
+pub async fn export_dipswitches(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DipSwitchExportParams>,
+) -> impl IntoResponse {
+    let Some(format) = SheetFormat::from_param(params.format.as_deref()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "format must be csv, xlsx or json" })),
+        )
+            .into_response();
+    };
+    let model = normalize_model_name(&params.model);
+    let switches = match load_model_dipswitches(state.db.as_ref(), &model, None).await {
+        Ok(switches) => switches,
+        Err(e) => {
+            tracing::error!("Failed to export dipswitches for {}: {:?}", model, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load DIP switches" })),
+            )
+                .into_response();
+        }
+    };
+
+    fn json_cell<T: Serialize>(items: &[T]) -> Option<String> {
+        if items.is_empty() {
+            None
+        } else {
+            serde_json::to_string(items).ok()
+        }
+    }
+
+    let rows: Vec<Vec<Option<String>>> = switches
+        .into_iter()
+        .map(|sw| {
+            let values: Vec<serde_json::Value> = sw
+                .values
+                .iter()
+                .map(|v| serde_json::json!({ "value": v.value, "meaning": v.meaning }))
+                .collect();
+            vec![
+                Some(sw.model_name),
+                Some(sw.switch_number.to_string()),
+                Some(sw.bit_number.to_string()),
+                sw.end_bit.map(|e| e.to_string()),
+                sw.function_name,
+                sw.setting_0,
+                sw.setting_1,
+                sw.default_val,
+                Some(sw.risk_level),
+                Some(sw.temporary_only.to_string()),
+                sw.risk_note,
+                json_cell(&sw.defaults),
+                json_cell(&values),
+                json_cell(&sw.rules),
+            ]
+        })
+        .collect();
+
+    match spreadsheet::write_records(&DIPSW_COLUMNS, &rows, format) {
+        Ok(body) => {
+            let disposition = format!("attachment; filename=\"dipswitches_{}.{}\"", model, format.extension());
+            (
+                [(header::CONTENT_TYPE, format.content_type().to_string()), (header::CONTENT_DISPOSITION, disposition)],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to write dipswitch export: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to write export file" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WorksheetParams {
+    pub model: String,
+    /// Default column to print; the primary default when omitted
+    pub variant: Option<String>,
+}
+
+/// Printable worksheet (HTML, A4) of a model's switches grouped by switch, with an empty column for the
+/// values found on the machine. Print it or save it as PDF from the browser.
+pub async fn dipswitch_worksheet(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WorksheetParams>,
+) -> impl IntoResponse {
+    let model = normalize_model_name(&params.model);
+    match load_model_dipswitches(state.db.as_ref(), &model, params.variant.as_deref()).await {
+        Ok(switches) if !switches.is_empty() => {
+            axum::response::Html(worksheet::render(&model, params.variant.as_deref(), &switches)).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, format!("No DIP switches for {}", model)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to load dipswitches for worksheet {}: {:?}", model, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load DIP switches").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BundleParams {
+    /// One model or a comma-separated list ("C4080,C4070")
+    pub models: String,
+    /// Embed part images ("1" or "true"); off by default, images make the bundle much larger
+    pub images: Option<String>,
+}
+
+/// Fills in the parts of each error code with their per-error ranking, like `search_errors`, in one query.
+/// Returns the ids of all parts involved, in first-seen order.
+async fn attach_error_parts(db: &dyn Repository, error_codes: &mut [ErrorCode]) -> Result<Vec<Uuid>, sqlx::Error> {
+    let error_ids: Vec<Uuid> = error_codes.iter().map(|e| e.id).collect();
+    let links = db.error_part_links(&error_ids).await?;
+    let mut parts_by_error: HashMap<Uuid, Vec<SparePart>> = HashMap::new();
+    let mut part_ids: Vec<Uuid> = Vec::new();
+    for link in links {
+        if !part_ids.contains(&link.part.id) {
+            part_ids.push(link.part.id);
+        }
+        let mut part = link.part;
+        let mut ranking = link.ranking;
+        ranking.explanation = ranking::explain(&ranking);
+        part.ranking_info = Some(ranking);
+        parts_by_error.entry(link.error_id).or_default().push(part);
+    }
+    for error in error_codes.iter_mut() {
+        error.parts = parts_by_error.remove(&error.id).unwrap_or_default();
+    }
+    Ok(part_ids)
+}
+
+/// Rows of a model set changed at or after `since` (everything for 0). An error code counts as changed
+/// when its own row, one of its part links or one of its parts changed, since it carries its parts.
+async fn load_bundle_data(db: &dyn Repository, models: &[String], since: i64) -> Result<bundle::BundleData, sqlx::Error> {
+    let printers = db.printers_for_models(models, since).await?;
+    let mut error_codes = db.changed_error_codes(models, since).await?;
+    let part_ids = attach_error_parts(db, &mut error_codes).await?;
+    let parts = db.spare_parts(&part_ids).await?;
+
+    let filter = DipSwitchFilter { models: models.to_vec(), since, ..Default::default() };
+    let mut dip_switches = db.dipswitches(&filter).await?;
+    attach_dipswitch_details(db, &mut dip_switches, None).await?;
+
+    Ok(bundle::BundleData { printers, error_codes, parts, dip_switches })
+}
+
+/// Offline bundle of a model set: printers, error codes with their parts, part images and DIP switches,
+/// as gzipped JSON. `ETag`/`X-Bundle-Version` identify the content (send it back as `If-None-Match` to get a
+/// 304 when nothing changed); `X-Bundle-Sha256` is the checksum of the downloaded body.
+pub async fn get_bundle(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<BundleParams>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let models = parse_model_list(&params.models);
+    if models.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "At least one model is required" })),
+        )
+            .into_response();
+    }
+    let with_images = flag(&params.images);
+
+    // Take the token *before* reading, so changes that commit while the client downloads are sent again next time
+    let loaded = match state.db.sync_token().await {
+        Ok(token) => load_bundle_data(state.db.as_ref(), &models, 0).await.map(|data| (token, data)),
+        Err(e) => Err(e),
+    };
+    let (sync_token, data) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!("Failed to load bundle data for {:?}: {:?}", models, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to build bundle" })),
+            )
+                .into_response();
+        }
+    };
+    let unknown: Vec<&String> = models
+        .iter()
+        .filter(|m| {
+            !data.printers.iter().any(|p| &p.model_name == *m) && !data.dip_switches.iter().any(|d| &d.model_name == *m)
+        })
+        .collect();
+    if !unknown.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": format!("Unknown models: {:?}", unknown) })),
+        )
+            .into_response();
+    }
+
+    let version = match bundle::version_of(&models, &data, with_images) {
+        Ok(version) => version,
+        Err(e) => {
+            tracing::error!("Failed to version bundle: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build bundle").into_response();
+        }
+    };
+    let etag = format!("\"{}\"", version);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let part_images = if with_images { state.images.fetch_part_images(&data.parts).await } else { Vec::new() };
+    let full = bundle::Bundle {
+        format: bundle::BUNDLE_FORMAT,
+        version,
+        generated_at: chrono::Utc::now(),
+        sync_token: sync_token.to_string(),
+        models: models.clone(),
+        data,
+        part_images,
+    };
+    match bundle::encode(&full) {
+        Ok(encoded) => {
+            let disposition = format!("attachment; filename=\"bundle_{}_{}.json.gz\"", models.join("_"), encoded.version);
+            (
+                [
+                    (header::CONTENT_TYPE, "application/gzip".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                    (header::ETAG, etag),
+                    (header::HeaderName::from_static("x-bundle-version"), encoded.version),
+                    (header::HeaderName::from_static("x-bundle-sha256"), encoded.sha256),
+                ],
+                encoded.body,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to encode bundle: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to build bundle" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangesParams {
+    /// One model or a comma-separated list ("C4080,C4070")
+    pub models: String,
+    /// `sync_token` of the bundle or of the previous call; everything when omitted
+    pub since: Option<String>,
+}
+
+/// Delta sync: what changed in a model set since a token, as rows to upsert (same shapes as the bundle)
+/// and tombstones to delete. Clients apply `deleted` first, then the upserts, and keep `token` for next time.
+pub async fn get_changes(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ChangesParams>,
+) -> impl IntoResponse {
+    let models = parse_model_list(&params.models);
+    if models.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": "At least one model is required" })),
+        )
+            .into_response();
+    }
+    let since = match params.since.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        None => 0,
+        Some(token) => match token.parse::<i64>() {
+            Ok(since) if since >= 0 => since,
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "success": false, "message": format!("Invalid sync token '{}'", token) })),
+                )
+                    .into_response();
+            }
+        },
+    };
+
+    let loaded = async {
+        let token = state.db.sync_token().await?;
+        let upserts = load_bundle_data(state.db.as_ref(), &models, since).await?;
+        // A first sync has nothing to delete
+        let deleted = if since == 0 {
+            Vec::new()
+        } else {
+            state.db.tombstones(since).await?
+        };
+        Ok::<_, sqlx::Error>((token, upserts, deleted))
+    }
+    .await;
+
+    match loaded {
+        Ok((token, upserts, deleted)) => Json(bundle::ChangeSet {
+            since: since.to_string(),
+            token: token.to_string(),
+            models,
+            upserts,
+            deleted,
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to load changes since {}: {:?}", since, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to load changes" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InterventionPartRequest {
+    pub oem_code: String,
+    pub quantity: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct InterventionRequest {
+    pub serial_number: String,
+    pub model: String,
+    /// Today when omitted
+    pub performed_on: Option<chrono::NaiveDate>,
+    pub minutes_spent: Option<i32>,
+    pub outcome: String,
+    pub notes: Option<String>,
+    /// Error codes seen, as shown on the panel (must exist for the model)
+    #[serde(default)]
+    pub error_codes: Vec<String>,
+    #[serde(default)]
+    pub parts: Vec<InterventionPartRequest>,
+    #[serde(default)]
+    pub dip_switches: Vec<InterventionDipSwitch>,
+}
+
+fn bad_request(message: String) -> axum::response::Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "success": false, "message": message }))).into_response()
+}
+
+/// Checks changed switches against the model's switch table: each must name an entry by its first bit,
+/// with values that fit the entry. Models without switch data are not checked.
+fn validate_intervention_switches(switches: &[DipSwitch], changes: &[InterventionDipSwitch]) -> Result<(), String> {
+    if switches.is_empty() {
+        return Ok(());
+    }
+    let mut seen = std::collections::HashSet::new();
+    for change in changes {
+        let Some(sw) = switches
+            .iter()
+            .find(|s| s.switch_number == change.switch_number && s.bit_number == change.bit_number)
+        else {
+            return Err(format!("SW {}-{} is not a switch entry of this model", change.switch_number, change.bit_number));
+        };
+        if !seen.insert((change.switch_number, change.bit_number)) {
+            return Err(format!("SW {}-{} is listed twice", change.switch_number, change.bit_number));
+        }
+        let max_value = (1 << sw.width()) - 1;
+        for value in std::iter::once(change.value).chain(change.previous_value) {
+            if !(0..=max_value).contains(&value) {
+                return Err(format!("value {} does not fit SW {}-{}", value, change.switch_number, change.bit_number));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records a service intervention. Error codes are linked to the model's catalog entries and parts to
+/// `spare_parts` by OEM code; unknown ones are refused so the history stays searchable.
+pub async fn create_intervention(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Json(request): Json<InterventionRequest>,
+) -> impl IntoResponse {
+    let model = normalize_model_name(&request.model);
+    let serial_number = request.serial_number.trim().to_string();
+    // Recorded under the authenticated key, never a name from the body
+    let technician = caller.name;
+    if serial_number.is_empty() || model.is_empty() {
+        return bad_request("Serial number and model are required".to_string());
+    }
+    if !INTERVENTION_OUTCOMES.contains(&request.outcome.as_str()) {
+        return bad_request(format!("outcome must be one of {}", INTERVENTION_OUTCOMES.join(", ")));
+    }
+    if request.minutes_spent.is_some_and(|m| m < 0) {
+        return bad_request("minutes_spent cannot be negative".to_string());
+    }
+
+    let mut codes: Vec<String> = Vec::new();
+    for code in request.error_codes.iter().map(|c| c.trim()).filter(|c| !c.is_empty()) {
+        if !codes.iter().any(|c| c == code) {
+            codes.push(code.to_string());
+        }
+    }
+    let mut parts: Vec<InterventionPart> = Vec::new();
+    for part in &request.parts {
+        let oem_code = part.oem_code.trim().to_string();
+        let quantity = part.quantity.unwrap_or(1);
+        if oem_code.is_empty() || quantity < 1 {
+            return bad_request("Each part needs an OEM code and a quantity of at least 1".to_string());
+        }
+        match parts.iter_mut().find(|p| p.oem_code == oem_code) {
+            Some(existing) => existing.quantity += quantity,
+            None => parts.push(InterventionPart { part_id: None, oem_code, quantity }),
+        }
+    }
+
+    let oem_codes: Vec<String> = parts.iter().map(|p| p.oem_code.clone()).collect();
+    let resolved = async {
+        let error_ids = state.db.error_code_ids(&model, &codes).await?;
+        let known_parts = state.db.spare_parts_by_oem(&oem_codes).await?;
+        let switches = load_model_dipswitches(state.db.as_ref(), &model, None).await?;
+        Ok::<_, sqlx::Error>((error_ids, known_parts, switches))
+    }
+    .await;
+    let (error_ids, known_parts, switches) = match resolved {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::error!("Failed to resolve intervention links: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to save intervention" })),
+            )
+                .into_response();
+        }
+    };
+
+    let unknown_codes: Vec<&str> = codes.iter().filter(|c| !error_ids.contains_key(*c)).map(|c| c.as_str()).collect();
+    if !unknown_codes.is_empty() {
+        return bad_request(format!("Unknown error codes for {}: {}", model, unknown_codes.join(", ")));
+    }
+    for part in &mut parts {
+        part.part_id = known_parts.iter().find(|p| p.oem_code == part.oem_code).map(|p| p.id);
+    }
+    let unknown_parts: Vec<&str> = parts.iter().filter(|p| p.part_id.is_none()).map(|p| p.oem_code.as_str()).collect();
+    if !unknown_parts.is_empty() {
+        return bad_request(format!("Unknown parts: {}", unknown_parts.join(", ")));
+    }
+    if let Err(e) = validate_intervention_switches(&switches, &request.dip_switches) {
+        return bad_request(e);
+    }
+
+    let intervention = Intervention {
+        id: Uuid::new_v4(),
+        serial_number,
+        model_name: model,
+        performed_on: request.performed_on.unwrap_or_else(|| chrono::Utc::now().date_naive()),
+        technician,
+        minutes_spent: request.minutes_spent,
+        outcome: request.outcome,
+        notes: request.notes.filter(|n| !n.trim().is_empty()),
+        error_codes: codes
+            .into_iter()
+            .map(|code| InterventionErrorCode { error_id: error_ids.get(&code).copied(), code })
+            .collect(),
+        parts,
+        dip_switches: request.dip_switches,
+    };
+
+    match state.db.save_intervention(&intervention).await {
+        Ok(()) => Json(serde_json::json!({ "success": true, "intervention": intervention })).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to save intervention: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to save intervention" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InterventionParams {
+    pub serial: Option<String>,
+    pub model: Option<String>,
+    pub technician: Option<String>,
+    /// Interventions where this error code was seen
+    pub code: Option<String>,
+    pub outcome: Option<String>,
+    /// Date range (YYYY-MM-DD), both ends included
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    pub limit: Option<i64>,
+}
+
+async fn attach_intervention_details(db: &dyn Repository, interventions: &mut [Intervention]) {
+    let ids: Vec<Uuid> = interventions.iter().map(|i| i.id).collect();
+    let mut details = db
+        .intervention_details(&ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch intervention details: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+    for intervention in interventions.iter_mut() {
+        intervention.error_codes = details.error_codes.remove(&intervention.id).unwrap_or_default();
+        intervention.parts = details.parts.remove(&intervention.id).unwrap_or_default();
+        intervention.dip_switches = details.dip_switches.remove(&intervention.id).unwrap_or_default();
+    }
+}
+
+/// Intervention history, newest first.
+pub async fn get_interventions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<InterventionParams>,
+) -> Json<Vec<Intervention>> {
+    let non_empty = |v: &Option<String>| v.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+    let filter = InterventionFilter {
+        serial_number: non_empty(&params.serial),
+        model: params.model.as_deref().map(normalize_model_name).filter(|m| !m.is_empty()),
+        technician: non_empty(&params.technician),
+        code: non_empty(&params.code),
+        outcome: non_empty(&params.outcome),
+        from: params.from,
+        to: params.to,
+        limit: params.limit.unwrap_or(100).clamp(1, 500),
+    };
+
+    let mut interventions = state.db.interventions(&filter)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch interventions: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+    attach_intervention_details(state.db.as_ref(), &mut interventions).await;
+
+    Json(interventions)
+}
+
+pub async fn get_intervention(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.db.intervention(id).await {
+        Ok(Some(intervention)) => {
+            let mut interventions = [intervention];
+            attach_intervention_details(state.db.as_ref(), &mut interventions).await;
+            let [intervention] = interventions;
+            Json(intervention).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Intervention not found" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to fetch intervention: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch intervention" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MachineRequest {
+    pub serial_number: String,
+    pub model: String,
+    pub customer: Option<String>,
+    pub site: Option<String>,
+    pub installed_on: Option<chrono::NaiveDate>,
+    pub firmware: Option<String>,
+    #[serde(default)]
+    pub options: Vec<String>,
+    pub notes: Option<String>,
+}
+
+/// Builds the stored machine from a request, creating the catalog model on first use.
+async fn machine_from_request(db: &dyn Repository, id: Uuid, request: MachineRequest) -> Result<Machine, axum::response::Response> {
+    let serial_number = request.serial_number.trim().to_string();
+    let model = normalize_model_name(&request.model);
+    if serial_number.is_empty() || model.is_empty() {
+        return Err(bad_request("Serial number and model are required".to_string()));
+    }
+    let printer_id = match db.get_or_create_printer(&model).await {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Failed to resolve printer {}: {:?}", model, e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": format!("Failed to resolve printer {}", model) })),
+            )
+                .into_response());
+        }
+    };
+    let text = |v: Option<String>| v.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let mut options: Vec<String> = Vec::new();
+    for option in request.options.iter().map(|o| o.trim()).filter(|o| !o.is_empty()) {
+        if !options.iter().any(|o| o == option) {
+            options.push(option.to_string());
+        }
+    }
+    Ok(Machine {
+        id,
+        serial_number,
+        printer_id,
+        model_name: model,
+        customer: text(request.customer),
+        site: text(request.site),
+        installed_on: request.installed_on,
+        firmware: text(request.firmware),
+        options: sqlx::types::Json(options),
+        notes: text(request.notes),
+    })
+}
+
+/// Maps a failed machine write: a taken serial number is a 409, anything else a 500.
+fn machine_write_error(serial_number: &str, e: sqlx::Error) -> axum::response::Response {
+    if matches!(&e, sqlx::Error::Database(db) if db.is_unique_violation()) {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "success": false, "message": format!("Serial number {} is already registered", serial_number) })),
+        )
+            .into_response();
+    }
+    tracing::error!("Failed to save machine {}: {:?}", serial_number, e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "success": false, "message": "Failed to save machine" })),
+    )
+        .into_response()
+}
+
+pub async fn create_machine(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Json(request): Json<MachineRequest>,
+) -> impl IntoResponse {
+    let machine = match machine_from_request(state.db.as_ref(), Uuid::new_v4(), request).await {
+        Ok(machine) => machine,
+        Err(response) => return response,
+    };
+    match state.db.insert_machine(&machine).await {
+        Ok(()) => {
+            audit_machine(state.db.as_ref(), &caller, audit::ACTION_CREATE, None, Some(&machine)).await;
+            (StatusCode::CREATED, Json(serde_json::json!({ "success": true, "machine": machine }))).into_response()
+        }
+        Err(e) => machine_write_error(&machine.serial_number, e),
+    }
+}
+
+/// Replaces every field of a registered machine.
+pub async fn update_machine(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<MachineRequest>,
+) -> impl IntoResponse {
+    let machine = match machine_from_request(state.db.as_ref(), id, request).await {
+        Ok(machine) => machine,
+        Err(response) => return response,
+    };
+    let old = stored_machine(state.db.as_ref(), id).await;
+    match state.db.update_machine(&machine).await {
+        Ok(true) => {
+            audit_machine(state.db.as_ref(), &caller, audit::ACTION_UPDATE, old.as_ref(), Some(&machine)).await;
+            Json(serde_json::json!({ "success": true, "machine": machine })).into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Machine not found" })),
+        )
+            .into_response(),
+        Err(e) => machine_write_error(&machine.serial_number, e),
+    }
+}
+
+pub async fn delete_machine(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let old = stored_machine(state.db.as_ref(), id).await;
+    match state.db.delete_machine(id).await {
+        Ok(true) => {
+            audit_machine(state.db.as_ref(), &caller, audit::ACTION_DELETE, old.as_ref(), None).await;
+            Json(serde_json::json!({ "success": true })).into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Machine not found" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete machine: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to delete machine" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MachineParams {
+    /// Part of the serial number, customer or site
+    pub q: Option<String>,
+    pub model: Option<String>,
+    pub limit: Option<i64>,
+}
+
+pub async fn get_machines(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<MachineParams>,
+) -> Json<Vec<Machine>> {
+    let filter = MachineFilter {
+        query: params.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(str::to_string),
+        model: params.model.as_deref().map(normalize_model_name).filter(|m| !m.is_empty()),
+        limit: params.limit.unwrap_or(100).clamp(1, 500),
+    };
+    let machines = state.db.machines(&filter)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch machines: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(machines)
+}
+
+async fn find_machine(db: &dyn Repository, id: Uuid) -> Result<Machine, axum::response::Response> {
+    match db.machine(id).await {
+        Ok(Some(machine)) => Ok(machine),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Machine not found" })),
+        )
+            .into_response()),
+        Err(e) => {
+            tracing::error!("Failed to fetch machine: {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch machine" })),
+            )
+                .into_response())
+        }
+    }
+}
+
+pub async fn get_machine(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match find_machine(state.db.as_ref(), id).await {
+        Ok(machine) => Json(machine).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Intervention history of one machine (by its serial number), newest first.
+pub async fn get_machine_interventions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let machine = match find_machine(state.db.as_ref(), id).await {
+        Ok(machine) => machine,
+        Err(response) => return response,
+    };
+    let filter = InterventionFilter { serial_number: Some(machine.serial_number), limit: 500, ..Default::default() };
+    let mut interventions = match state.db.interventions(&filter).await {
+        Ok(interventions) => interventions,
+        Err(e) => {
+            tracing::error!("Failed to fetch interventions: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch interventions" })),
+            )
+                .into_response();
+        }
+    };
+    attach_intervention_details(state.db.as_ref(), &mut interventions).await;
+    Json(interventions).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct RecalculateRankingsParams {
+    /// Only this model's links; all when omitted
+    pub model: Option<String>,
+}
+
+/// Recalculates the part rankings of error codes from the recorded interventions (see `ranking::rank`).
+pub async fn recalculate_rankings(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RecalculateRankingsParams>,
+) -> impl IntoResponse {
+    let model = params.model.as_deref().map(normalize_model_name).filter(|m| !m.is_empty());
+    let result = async {
+        let inputs = state.db.ranking_inputs(model.as_deref(), None).await?;
+        let updates: Vec<ranking::RankingUpdate> = inputs.iter().map(ranking::rank).collect();
+        let changed = state.db.apply_rankings(&updates).await?;
+        Ok::<_, sqlx::Error>((updates, changed))
+    }
+    .await;
+
+    match result {
+        Ok((updates, changed)) => {
+            let count = |source: &str| updates.iter().filter(|u| u.source == source).count();
+            Json(serde_json::json!({
+                "success": true,
+                "links": updates.len(),
+                "changed": changed,
+                "learned": count(ranking::SOURCE_LEARNED),
+                "manual": count(ranking::SOURCE_MANUAL),
+                "catalog": count(ranking::SOURCE_CATALOG),
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to recalculate rankings: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to recalculate rankings" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ManualRankingRequest {
+    /// 1–5, or null to go back to the catalog/learned ranking
+    pub ranking: Option<i32>,
+}
+
+/// Sets the ranking of one error-code/part link by hand. It wins over learned rankings until cleared.
+pub async fn set_part_ranking(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path((error_id, part_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<ManualRankingRequest>,
+) -> impl IntoResponse {
+    if request.ranking.is_some_and(|r| !(1..=5).contains(&r)) {
+        return bad_request("ranking must be between 1 and 5".to_string());
+    }
+    let result = async {
+        let previous = state.db.ranking_inputs(None, Some((error_id, part_id))).await?;
+        if !state.db.set_manual_ranking(error_id, part_id, request.ranking).await? {
+            return Ok(None);
+        }
+        let old = previous.first().and_then(|input| input.manual_ranking);
+        let key = part_id.to_string();
+        let target = AuditTarget { entity: audit::ENTITY_ERROR_PART, entity_id: Some(error_id), model_name: None, entity_key: &key };
+        let change = ("manual_ranking", old.map(|r| r.to_string()), request.ranking.map(|r| r.to_string()));
+        record_audit(state.db.as_ref(), &AuditContext::new(&caller, None).entries(&target, audit::ACTION_UPDATE, vec![change])).await;
+        let inputs = state.db.ranking_inputs(None, Some((error_id, part_id))).await?;
+        let updates: Vec<ranking::RankingUpdate> = inputs.iter().map(ranking::rank).collect();
+        state.db.apply_rankings(&updates).await?;
+        Ok::<_, sqlx::Error>(updates.into_iter().next())
+    }
+    .await;
+
+    match result {
+        Ok(Some(update)) => {
+            let mut info = PartRanking {
+                ranking_source: update.source.to_string(),
+                fix_attempts: update.attempts,
+                fix_successes: update.successes,
+                success_rate: update.success_rate,
+                explanation: String::new(),
+            };
+            info.explanation = ranking::explain(&info);
+            Json(serde_json::json!({ "success": true, "ranking": update.ranking, "ranking_info": info })).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "This part is not linked to the error code" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to set part ranking: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to set ranking" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Latest comments returned with each error code in `/api/errors`.
+const FEEDBACK_COMMENTS: usize = 3;
+
+/// Counts technician feedback per error code (solution and each part) and keeps the latest comments.
+async fn attach_feedback(db: &dyn Repository, error_codes: &mut [ErrorCode]) -> Result<(), sqlx::Error> {
+    if error_codes.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<Uuid> = error_codes.iter().map(|e| e.id).collect();
+    let mut summaries: HashMap<Uuid, FeedbackSummary> = HashMap::new();
+    // Newest first, so the first comments seen are the latest
+    for entry in db.feedback(&ids).await? {
+        let summary = summaries.entry(entry.error_id).or_default();
+        let counts = match entry.part_id {
+            None => (&mut summary.confirmed, &mut summary.rejected),
+            Some(part_id) => {
+                let index = match summary.parts.iter().position(|p| p.part_id == part_id) {
+                    Some(index) => index,
+                    None => {
+                        summary.parts.push(PartFeedback { part_id, confirmed: 0, rejected: 0 });
+                        summary.parts.len() - 1
+                    }
+                };
+                let part = &mut summary.parts[index];
+                (&mut part.confirmed, &mut part.rejected)
+            }
+        };
+        *(if entry.worked { counts.0 } else { counts.1 }) += 1;
+        if entry.comment.is_some() && summary.comments.len() < FEEDBACK_COMMENTS {
+            summary.comments.push(entry);
+        }
+    }
+    for error in error_codes.iter_mut() {
+        error.feedback = Some(summaries.remove(&error.id).unwrap_or_default());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct FeedbackRequest {
+    /// true: "this fixed it"
+    pub worked: bool,
+    /// The part the verdict is about; without it the verdict is on the solution text
+    pub part_id: Option<Uuid>,
+    pub comment: Option<String>,
+}
+
+/// Records a technician confirming or rejecting an error code's solution, or one part for it. The
+/// technician is the caller.
+pub async fn create_feedback(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path(error_id): Path<Uuid>,
+    Json(request): Json<FeedbackRequest>,
+) -> impl IntoResponse {
+    // A verdict on a part only counts for the parts linked to the code, it feeds their ranking
+    if let Some(part_id) = request.part_id {
+        match state.db.error_part_links(&[error_id]).await {
+            Ok(links) if links.iter().any(|l| l.part.id == part_id) => {}
+            Ok(_) => return bad_request(format!("Part {} is not linked to error code {}", part_id, error_id)),
+            Err(e) => {
+                tracing::error!("Failed to fetch error parts: {:?}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({ "success": false, "message": "Failed to save feedback" })),
+                )
+                    .into_response();
+            }
+        }
+    }
+    let feedback = ErrorFeedback {
+        id: Uuid::new_v4(),
+        error_id,
+        part_id: request.part_id,
+        worked: request.worked,
+        technician: caller.name,
+        comment: request.comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+        created_at: chrono::Utc::now(),
+    };
+
+    match state.db.save_feedback(&feedback).await {
+        Ok(()) => (StatusCode::CREATED, Json(serde_json::json!({ "success": true, "feedback": feedback }))).into_response(),
+        Err(sqlx::Error::Database(db)) if db.is_foreign_key_violation() => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Unknown error code or part" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to save feedback: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to save feedback" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// All feedback on one error code, newest first.
+pub async fn get_feedback(
+    State(state): State<Arc<AppState>>,
+    Path(error_id): Path<Uuid>,
+) -> Json<Vec<ErrorFeedback>> {
+    let feedback = state.db.feedback(&[error_id])
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch feedback: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(feedback)
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    /// admin, editor, technician or read-only
+    pub role: String,
+}
+
+/// Creates an API key. The key is only in this response; the server keeps its hash.
+pub async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ApiKeyRequest>,
+) -> impl IntoResponse {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return bad_request("name is required".to_string());
+    }
+    let Some(role) = Role::parse(&request.role) else {
+        return bad_request(format!("Unknown role '{}' (admin, editor, technician, read-only)", request.role));
+    };
+    let key = auth::generate_key();
+    let record = ApiKey {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        key_prefix: key.chars().take(auth::KEY_PREFIX.len() + 6).collect(),
+        key_hash: auth::hash_key(&key),
+        role: role.as_str().to_string(),
+        created_at: chrono::Utc::now(),
+        revoked_at: None,
+    };
+
+    match state.db.insert_api_key(&record).await {
+        Ok(()) => (
+            StatusCode::CREATED,
+            Json(serde_json::json!({ "success": true, "key": key, "api_key": record })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to create API key: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to create API key" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn get_api_keys(State(state): State<Arc<AppState>>) -> Json<Vec<ApiKey>> {
+    let keys = state.db.api_keys()
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch API keys: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(keys)
+}
+
+pub async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.db.revoke_api_key(id).await {
+        Ok(true) => Json(serde_json::json!({ "success": true })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "API key not found or already revoked" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to revoke API key: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to revoke API key" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct TokenRequest {
+    /// A lower role than the caller's, e.g. a read-only token for a kiosk
+    pub role: Option<String>,
+}
+
+/// Exchanges the caller's key for a signed, expiring token (for browser sessions and devices).
+pub async fn create_token(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    request: Option<Json<TokenRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let role = match request.role.as_deref().map(|r| (r, Role::parse(r))) {
+        None => caller.role,
+        Some((_, Some(role))) if role <= caller.role => role,
+        Some((r, _)) => return bad_request(format!("Cannot issue a token with role '{}'", r)),
+    };
+    let (token, expires_at) = auth::issue_token(&state.auth, &caller, role);
+    Json(serde_json::json!({
+        "success": true,
+        "token": token,
+        "role": role,
+        "expires_at": expires_at,
+    }))
+    .into_response()
+}
+
+/// Who the credentials belong to.
+pub async fn get_me(axum::Extension(caller): axum::Extension<Caller>) -> Json<Caller> {
+    Json(caller)
+}
+
+/// Stores audit entries after a write. The write already happened, so a failure is logged, not returned.
+async fn record_audit(db: &dyn Repository, entries: &[AuditEntry]) {
+    if entries.is_empty() {
+        return;
+    }
+    if let Err(e) = db.record_audit(entries).await {
+        tracing::error!("Failed to record {} audit entries: {:?}", entries.len(), e);
+    }
+}
+
+/// The machine as stored before a write, for its audit entries.
+async fn stored_machine(db: &dyn Repository, id: Uuid) -> Option<Machine> {
+    db.machine(id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch machine: {:?}", e);
+            e
+        })
+        .unwrap_or_default()
+}
+
+async fn audit_machine(db: &dyn Repository, caller: &Caller, action: &str, old: Option<&Machine>, new: Option<&Machine>) {
+    let Some(machine) = new.or(old) else {
+        return;
+    };
+    let target = AuditTarget {
+        entity: audit::ENTITY_MACHINE,
+        entity_id: Some(machine.id),
+        model_name: Some(&machine.model_name),
+        entity_key: &machine.serial_number,
+    };
+    let entries = AuditContext::new(caller, None).entries(&target, action, audit::machine_changes(old, new));
+    record_audit(db, &entries).await;
+}
+
+#[derive(Deserialize)]
+pub struct AuditParams {
+    pub model: Option<String>,
+    /// An error code of `model` (implies entity=error_code)
+    pub code: Option<String>,
+    /// Entries of one row by id: error code (including its part links), machine or DIP switch
+    pub error_id: Option<Uuid>,
+    pub entity: Option<String>,
+    pub user: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// The audit trail, newest first, per model, error code or user.
+pub async fn get_audit_log(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AuditParams>,
+) -> Json<Vec<AuditEntry>> {
+    let code = params.code.as_deref().map(str::trim).filter(|c| !c.is_empty()).map(str::to_string);
+    let filter = AuditFilter {
+        model: params.model.as_deref().map(normalize_model_name).filter(|m| !m.is_empty()),
+        entity: params.entity.or_else(|| code.as_ref().map(|_| audit::ENTITY_ERROR_CODE.to_string())),
+        entity_key: code,
+        entity_id: params.error_id,
+        user: params.user,
+        limit: params.limit.unwrap_or(200).clamp(1, 1000),
+    };
+    let entries = state.db.audit_log(&filter)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch audit log: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(entries)
+}
+
+#[derive(Deserialize)]
+pub struct RevisionParams {
+    pub model: String,
+    /// RFC 3339 time; the latest revisions when omitted
+    pub at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Point-in-time view of a model: the revision of each of its error codes in effect at `at`.
+pub async fn get_model_revisions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RevisionParams>,
+) -> Json<Vec<ErrorCodeRevision>> {
+    let model = normalize_model_name(&params.model);
+    let at = params.at.unwrap_or_else(chrono::Utc::now);
+    let revisions = state.db.error_code_revisions_at(&model, at)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch revisions of {}: {:?}", model, e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(revisions)
+}
+
+/// Every revision of one error code, newest first.
+pub async fn get_error_revisions(
+    State(state): State<Arc<AppState>>,
+    Path(error_id): Path<Uuid>,
+) -> Json<Vec<ErrorCodeRevision>> {
+    let revisions = state.db.error_code_revisions(error_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch revisions: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(revisions)
+}
+
+#[derive(Deserialize)]
+pub struct RevisionDiffParams {
+    pub from: i32,
+    pub to: i32,
+}
+
+#[derive(Serialize)]
+pub struct RevisionFieldDiff {
+    pub field: &'static str,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+fn revision_not_found(revision: i32) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "success": false, "message": format!("Revision {} not found", revision) })),
+    )
+        .into_response()
+}
+
+fn revisions_error(e: sqlx::Error) -> axum::response::Response {
+    tracing::error!("Failed to fetch revisions: {:?}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "success": false, "message": "Failed to fetch revisions" })),
+    )
+        .into_response()
+}
+
+/// The fields that differ between two revisions of an error code.
+pub async fn diff_error_revisions(
+    State(state): State<Arc<AppState>>,
+    Path(error_id): Path<Uuid>,
+    Query(params): Query<RevisionDiffParams>,
+) -> impl IntoResponse {
+    let revisions = match state.db.error_code_revisions(error_id).await {
+        Ok(revisions) => revisions,
+        Err(e) => return revisions_error(e),
+    };
+    let find = |number: i32| revisions.iter().find(|r| r.revision == number);
+    let Some(from) = find(params.from) else {
+        return revision_not_found(params.from);
+    };
+    let Some(to) = find(params.to) else {
+        return revision_not_found(params.to);
+    };
+    let changes: Vec<RevisionFieldDiff> = audit::revision_changes(from, to)
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, from, to)| RevisionFieldDiff { field, from, to })
+        .collect();
+    Json(serde_json::json!({ "code": to.code, "from": from.revision, "to": to.revision, "changes": changes })).into_response()
+}
+
+/// Texts to change on an error code: fields left out stay as they are, an empty text clears the field.
+#[derive(Deserialize)]
+pub struct EditErrorCodeRequest {
+    pub classification: Option<String>,
+    pub cause: Option<String>,
+    pub measures: Option<String>,
+    pub solution: Option<String>,
+    pub estimated_abnormal_parts: Option<String>,
+    pub correction: Option<String>,
+    pub faulty_part_isolation: Option<String>,
+    pub note: Option<String>,
+}
+
+fn edited<'a>(value: &'a Option<String>, stored: &'a Option<String>) -> Option<&'a str> {
+    match value {
+        Some(v) => Some(v.as_str()).filter(|v| !v.trim().is_empty()),
+        None => stored.as_deref(),
+    }
+}
+
+/// Edits the texts of an error code by hand, as a new revision. The fields it changes are marked
+/// manual, which imports keep by default.
+pub async fn edit_error_code(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path(error_id): Path<Uuid>,
+    Json(request): Json<EditErrorCodeRequest>,
+) -> impl IntoResponse {
+    let current = match state.db.error_code(error_id).await {
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": "Error code not found" })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch error code {}: {:?}", error_id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch error code" })),
+            )
+                .into_response();
+        }
+    };
+    let error = &current.error;
+    let fields = ErrorCodeFields {
+        code: &error.code,
+        classification: edited(&request.classification, &error.classification),
+        cause: edited(&request.cause, &error.cause),
+        measures: edited(&request.measures, &error.measures),
+        solution: edited(&request.solution, &error.solution),
+        estimated_abnormal_parts: edited(&request.estimated_abnormal_parts, &error.estimated_abnormal_parts),
+        correction: edited(&request.correction, &error.correction),
+        faulty_part_isolation: edited(&request.faulty_part_isolation, &error.faulty_part_isolation),
+        note: edited(&request.note, &error.note),
+    };
+
+    let audit_context = AuditContext::new(&caller, None);
+    let target = AuditTarget {
+        entity: audit::ENTITY_ERROR_CODE,
+        entity_id: Some(error_id),
+        model_name: Some(&current.model_name),
+        entity_key: &error.code,
+    };
+    let entries = audit_context.entries(&target, audit::ACTION_UPDATE, audit::error_code_changes(Some(error), &fields));
+    if entries.is_empty() {
+        return Json(serde_json::json!({ "success": true, "changed": 0, "field_sources": error.field_sources })).into_response();
+    }
+
+    let sources = merge::manual_sources(error, &fields);
+    let result = async {
+        state.db.update_error_code(error_id, &fields, &sources, Some(SOURCE_KIND_MANUAL)).await?;
+        state.db.add_error_code_revisions(&[audit_context.revision(error_id, audit::ACTION_UPDATE, &fields)]).await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            record_audit(state.db.as_ref(), &entries).await;
+            Json(serde_json::json!({ "success": true, "changed": entries.len(), "field_sources": sources })).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to edit error code {}: {:?}", error_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to edit error code" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Writes the texts of an earlier revision back to the error code, as a new revision.
+pub async fn restore_error_revision(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path((error_id, number)): Path<(Uuid, i32)>,
+) -> impl IntoResponse {
+    let revisions = match state.db.error_code_revisions(error_id).await {
+        Ok(revisions) => revisions,
+        Err(e) => return revisions_error(e),
+    };
+    let Some(revision) = revisions.iter().find(|r| r.revision == number) else {
+        return revision_not_found(number);
+    };
+    let current = match state.db.error_code(error_id).await {
+        Ok(Some(current)) => current,
+        Ok(None) => return revision_not_found(number),
+        Err(e) => return revisions_error(e),
+    };
+
+    let audit_context = AuditContext::new(&caller, None);
+    let target = AuditTarget {
+        entity: audit::ENTITY_ERROR_CODE,
+        entity_id: Some(error_id),
+        model_name: Some(&current.model_name),
+        entity_key: &current.error.code,
+    };
+    let entries = audit_context.entries(&target, audit::ACTION_RESTORE, audit::restore_changes(&current.error, revision));
+    if entries.is_empty() {
+        return Json(serde_json::json!({ "success": true, "restored": number, "changed": 0 })).into_response();
+    }
+
+    let fields = audit::revision_fields(revision);
+    let sources = merge::manual_sources(&current.error, &fields);
+    let result = async {
+        state.db.update_error_code(error_id, &fields, &sources, Some(SOURCE_KIND_MANUAL)).await?;
+        state.db.add_error_code_revisions(&[audit_context.revision(error_id, audit::ACTION_RESTORE, &fields)]).await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            record_audit(state.db.as_ref(), &entries).await;
+            Json(serde_json::json!({ "success": true, "restored": number, "changed": entries.len() })).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to restore revision {} of {}: {:?}", number, error_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to restore revision" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportBatchParams {
+    pub limit: Option<i64>,
+}
+
+/// Error code imports, newest first.
+pub async fn get_import_batches(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ImportBatchParams>,
+) -> Json<Vec<ImportBatch>> {
+    let batches = state.db.import_batches(params.limit.unwrap_or(50).clamp(1, 500))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch import batches: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(batches)
+}
+
+fn batch_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "success": false, "message": "Import batch not found" })),
+    )
+        .into_response()
+}
+
+pub async fn get_import_batch(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.db.import_batch(id).await {
+        Ok(Some(batch)) => Json(batch).into_response(),
+        Ok(None) => batch_not_found(),
+        Err(e) => {
+            tracing::error!("Failed to fetch import batch: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to fetch import batch" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// An error code the rollback left as it is, and why.
+#[derive(Serialize)]
+pub struct RollbackSkip {
+    pub error_id: Uuid,
+    pub code: String,
+    pub reason: String,
+}
+
+/// Undoes one import: codes it created are removed, codes it changed get their previous texts back
+/// and codes it marked obsolete are brought back. Codes changed again after the import are left alone
+/// and reported. Everything is written in one transaction, so a failed rollback can be retried.
+pub async fn rollback_import_batch(
+    State(state): State<Arc<AppState>>,
+    axum::Extension(caller): axum::Extension<Caller>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let batch = match state.db.import_batch(id).await {
+        Ok(Some(batch)) => batch,
+        Ok(None) => return batch_not_found(),
+        Err(e) => return revisions_error(e),
+    };
+    if batch.rolled_back_at.is_some() {
+        return rollback_conflict();
+    }
+    let (rollback, skipped) = match plan_batch_rollback(state.db.as_ref(), &batch, &caller).await {
+        Ok(plan) => plan,
+        Err(e) => return revisions_error(e),
+    };
+    match state.db.rollback_import_batch(&rollback).await {
+        Ok(true) => Json(serde_json::json!({
+            "success": skipped.is_empty(),
+            "restored": rollback.restores.len(),
+            "removed": rollback.deletes.len(),
+            "revived": rollback.revives.len(),
+            "skipped": skipped,
+        }))
+        .into_response(),
+        Ok(false) => rollback_conflict(),
+        Err(e) => {
+            tracing::error!("Rollback of batch {} failed: {:?}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Rollback failed, nothing was changed" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+fn rollback_conflict() -> axum::response::Response {
+    (
+        StatusCode::CONFLICT,
+        Json(serde_json::json!({ "success": false, "message": "This import was already rolled back" })),
+    )
+        .into_response()
+}
+
+/// What rolling back a batch writes, and the codes it leaves alone.
+async fn plan_batch_rollback(
+    db: &dyn Repository,
+    batch: &ImportBatch,
+    caller: &Caller,
+) -> Result<(BatchRollback, Vec<RollbackSkip>), sqlx::Error> {
+    let batch_revisions = db.batch_revisions(batch.id).await?;
+
+    // First and last revision the batch wrote per error code (a file may list a code twice)
+    let mut touched: BTreeMap<Uuid, (i32, i32)> = BTreeMap::new();
+    for revision in &batch_revisions {
+        let range = touched.entry(revision.error_id).or_insert((revision.revision, revision.revision));
+        range.0 = range.0.min(revision.revision);
+        range.1 = range.1.max(revision.revision);
+    }
+
+    let mut audit_context = AuditContext::new(caller, batch.source_file.as_deref());
+    audit_context.batch_id = Some(batch.id);
+    let mut rollback = BatchRollback {
+        batch_id: batch.id,
+        user: caller.name.clone(),
+        deletes: Vec::new(),
+        restores: Vec::new(),
+        revives: Vec::new(),
+        audit: Vec::new(),
+    };
+    let mut skipped: Vec<RollbackSkip> = Vec::new();
+
+    for (error_id, (first, last)) in touched {
+        let code = || batch_revisions.iter().find(|r| r.error_id == error_id).map(|r| r.code.clone()).unwrap_or_default();
+        let Some(current) = db.error_code(error_id).await? else {
+            skipped.push(RollbackSkip { error_id, code: code(), reason: "no longer exists".to_string() });
+            continue;
+        };
+        let history = db.error_code_revisions(error_id).await?;
+        if history.iter().any(|r| r.revision > last) {
+            skipped.push(RollbackSkip { error_id, code: code(), reason: "changed again after this import".to_string() });
+            continue;
+        }
+        let target = AuditTarget {
+            entity: audit::ENTITY_ERROR_CODE,
+            entity_id: Some(error_id),
+            model_name: Some(&current.model_name),
+            entity_key: &current.error.code,
+        };
+        match history.iter().find(|r| r.revision == first - 1) {
+            // Created by this import
+            None => {
+                let changes = audit::error_code_changes(Some(&current.error), &ErrorCodeFields {
+                    code: &current.error.code,
+                    ..Default::default()
+                });
+                rollback.audit.extend(audit_context.entries(&target, audit::ACTION_DELETE, changes));
+                rollback.deletes.push(error_id);
+            }
+            Some(previous) => {
+                let revision = audit_context.revision(error_id, audit::ACTION_ROLLBACK, &audit::revision_fields(previous));
+                let mut changes = audit::restore_changes(&current.error, previous);
+                // The import brought an obsolete code back: it goes back to obsolete
+                let imported = batch_revisions.iter().find(|r| r.error_id == error_id && r.revision == first);
+                let obsolete_at = imported.and_then(|r| r.previous_obsolete_at).filter(|_| current.error.obsolete_at.is_none());
+                if let Some(at) = obsolete_at {
+                    changes.push(("obsolete_at", None, Some(at.to_rfc3339())));
+                }
+                rollback.audit.extend(audit_context.entries(&target, audit::ACTION_ROLLBACK, changes));
+                rollback.restores.push(ErrorCodeRestore {
+                    revision,
+                    field_sources: current.error.field_sources.0.clone(),
+                    obsolete_at,
+                    obsolete_batch_id: obsolete_at.and(imported.and_then(|r| r.previous_obsolete_batch_id)),
+                });
+            }
+        }
+    }
+
+    for code in db.batch_obsolete_codes(batch.id).await? {
+        let target = AuditTarget {
+            entity: audit::ENTITY_ERROR_CODE,
+            entity_id: Some(code.error.id),
+            model_name: Some(&code.model_name),
+            entity_key: &code.error.code,
+        };
+        let change = ("obsolete_at", code.error.obsolete_at.map(|at| at.to_rfc3339()), None);
+        rollback.audit.extend(audit_context.entries(&target, audit::ACTION_ROLLBACK, vec![change]));
+        rollback.revives.push(code.error.id);
+    }
+    Ok((rollback, skipped))
+}
+
+/// The built-in column mapping and the saved ones.
+pub async fn get_column_mappings(State(state): State<Arc<AppState>>) -> Json<Vec<ColumnMapping>> {
+    let mappings = column_mappings(state.db.as_ref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch column mappings: {:?}", e);
+            e
+        })
+        .unwrap_or_default();
+
+    Json(mappings)
+}
+
+#[derive(Deserialize)]
+pub struct SaveColumnMappingRequest {
+    pub name: String,
+    pub description: Option<String>,
+    /// Import field → source column names, tried in order
+    pub columns: BTreeMap<String, Vec<String>>,
+}
+
+/// Saves (or replaces, by name) a column mapping for error code imports.
+pub async fn save_column_mapping(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SaveColumnMappingRequest>,
+) -> impl IntoResponse {
+    let name = request.name.trim();
+    let columns: BTreeMap<String, Vec<String>> = request
+        .columns
+        .into_iter()
+        .map(|(field, names)| {
+            let names = names.iter().map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect();
+            (field.trim().to_string(), names)
+        })
+        .collect();
+    let invalid = if name.is_empty() {
+        Err("Mapping name is required".to_string())
+    } else if name == spreadsheet::DEFAULT_MAPPING {
+        Err("The default mapping can't be changed".to_string())
+    } else {
+        spreadsheet::validate_mapping(&columns)
+    };
+    if let Err(message) = invalid {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "success": false, "message": message })),
+        )
+            .into_response();
+    }
+
+    match state.db.save_column_mapping(name, request.description.as_deref(), &columns).await {
+        Ok(mapping) => Json(serde_json::json!({ "success": true, "mapping": mapping })).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to save column mapping: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to save column mapping" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn delete_column_mapping(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.db.delete_column_mapping(&name).await {
+        Ok(true) => Json(serde_json::json!({ "success": true })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Column mapping not found" })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete column mapping: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": "Failed to delete column mapping" })),
+            )
+                .into_response()
+        }
+    }
+}
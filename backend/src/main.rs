@@ -1,20 +1,34 @@
 use axum::{
     routing::{get, post},
     extract::DefaultBodyLimit,
+    http::HeaderValue,
+    middleware,
     Router,
 };
+use auth::Role;
 use dotenvy::dotenv;
-use sqlx::postgres::PgPoolOptions;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod audit;
+mod auth;
+mod bundle;
+mod calculator;
+mod compare;
 mod handlers;
+mod merge;
 mod models;
+mod ranking;
+mod repository;
+mod spreadsheet;
+mod worksheet;
 
 pub struct AppState {
-    pub db: sqlx::PgPool,
+    pub db: Arc<dyn repository::Repository>,
+    pub auth: auth::AuthConfig,
+    pub images: Arc<bundle::ImageFetcher>,
 }
 
 #[tokio::main]
@@ -28,87 +42,118 @@ async fn main() {
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set").trim().to_string();
 
-    // Database Setup
-    let pool = match PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await {
-            Ok(pool) => {
-                tracing::info!("✅ Successfully connected to database");
-                pool
-            },
-            Err(e) => {
-                tracing::error!("❌ CRITICAL ERROR: Failed to connect to Postgres. URL: {}. Error: {}", database_url, e);
-                panic!("Database connection failed");
-            }
-        };
-
-    // Migration
-    sqlx::query(r#"
-        CREATE TABLE IF NOT EXISTS dip_switches (
-            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
-            model_name TEXT NOT NULL,
-            switch_number INTEGER NOT NULL,
-            bit_number INTEGER NOT NULL,
-            function_name TEXT,
-            setting_0 TEXT,
-            setting_1 TEXT,
-            default_val TEXT,
-            created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
-        );
-    "#)
-    .execute(&pool)
-    .await
-    .expect("Failed to run migration");
-
-    // Add faulty_part_isolation column if not exists
-    let _ = sqlx::query("ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS faulty_part_isolation TEXT")
-        .execute(&pool)
-        .await;
-
-    // CLEANUP: Deduplicate printers (Merge Konica Minolta variants -> Short names)
-    tracing::info!("Running printer deduplication...");
-    let _ = sqlx::query(r#"
-        -- 1. Ensure short entries exist
-        INSERT INTO printers (id, model_name) 
-        VALUES (gen_random_uuid(), 'C4080'), (gen_random_uuid(), 'C4070'), (gen_random_uuid(), 'C4065')
-        ON CONFLICT (model_name) DO NOTHING;
-
-        -- 2. Move errors from long names/variants to short names
-        -- Matches 'Konica Minolta C4080', 'KonicaMinolta C4080', etc.
-        UPDATE error_codes ec
-        SET printer_id = target.id
-        FROM printers source
-        JOIN printers target ON target.model_name = 
-            TRIM(REPLACE(REPLACE(source.model_name, 'Konica Minolta', ''), 'KonicaMinolta', ''))
-        WHERE ec.printer_id = source.id 
-        AND (source.model_name LIKE 'Konica Minolta %' OR source.model_name LIKE 'KonicaMinolta%')
-        AND target.model_name IN ('C4080', 'C4070', 'C4065');
-
-        -- 3. Delete long name entries
-        DELETE FROM printers 
-        WHERE model_name LIKE 'Konica Minolta %' 
-           OR model_name LIKE 'KonicaMinolta%';
-    "#)
-    .execute(&pool)
-    .await;
-    tracing::info!("Printer deduplication finished.");
+    // Database Setup (Postgres, or a local SQLite file for sqlite: URLs) and migrations
+    let db = match repository::connect(&database_url).await {
+        Ok(db) => {
+            tracing::info!("✅ Successfully connected to database");
+            db
+        },
+        Err(e) => {
+            tracing::error!("❌ CRITICAL ERROR: Failed to connect to database. URL: {}. Error: {}", database_url, e);
+            panic!("Database connection failed");
+        }
+    };
 
     // Redis Removed per user request
 
-    let state = Arc::new(AppState {
-        db: pool,
-    });
+    let auth = auth::AuthConfig::from_env();
+    tracing::info!("Catalog reads are {}", if auth.public_catalog { "public" } else { "authenticated" });
+    let images = Arc::new(bundle::ImageFetcher::from_env());
+    let state = Arc::new(AppState { db, auth, images });
 
-    let app = Router::new()
-        .route("/api/health", get(|| async { "OK" }))
+    // Catalog reads: public unless PUBLIC_CATALOG=false
+    let catalog = Router::new()
         .route("/api/printers", get(handlers::get_printers))
         .route("/api/errors", get(handlers::search_errors))
+        .route("/api/errors/:error_id/feedback", get(handlers::get_feedback))
+        .route("/api/errors/:error_id/revisions", get(handlers::get_error_revisions))
+        .route("/api/errors/:error_id/revisions/diff", get(handlers::diff_error_revisions))
+        .route("/api/revisions", get(handlers::get_model_revisions))
+        .route("/api/export", get(handlers::export_errors))
+        .route("/api/bundle", get(handlers::get_bundle))
+        .route("/api/sync/changes", get(handlers::get_changes))
+        .route("/api/dipswitches", get(handlers::get_dipswitches))
+        .route("/api/dipswitches/compare", get(handlers::compare_dipswitches))
+        .route("/api/dipswitches/search", get(handlers::search_dipswitches))
+        .route("/api/dipswitches/calculate", post(handlers::calculate_dipswitches))
+        .route("/api/dipswitches/export", get(handlers::export_dipswitches))
+        .route("/api/dipswitches/worksheet", get(handlers::dipswitch_worksheet))
+        .route("/api/dipswitch-profiles", get(handlers::get_dipswitch_profiles))
+        .route("/api/dipswitch-profiles/:id", get(handlers::get_dipswitch_profile));
+    let catalog = if state.auth.public_catalog {
+        catalog
+    } else {
+        catalog.route_layer(middleware::from_fn(|req, next| auth::require(Role::ReadOnly, req, next)))
+    };
+
+    // Any valid key or token
+    let account = Router::new()
+        .route("/api/auth/me", get(handlers::get_me))
+        .route("/api/auth/token", post(handlers::create_token))
+        .route_layer(middleware::from_fn(|req, next| auth::require(Role::ReadOnly, req, next)));
+
+    // Field work: feedback, interventions, installed base lookups, saved DIP switch profiles
+    let technician = Router::new()
+        .route("/api/errors/:error_id/feedback", post(handlers::create_feedback))
+        .route("/api/dipswitch-profiles", post(handlers::save_dipswitch_profile))
+        .route("/api/interventions", get(handlers::get_interventions).post(handlers::create_intervention))
+        .route("/api/interventions/:id", get(handlers::get_intervention))
+        .route("/api/machines", get(handlers::get_machines))
+        .route("/api/machines/:id", get(handlers::get_machine))
+        .route("/api/machines/:id/interventions", get(handlers::get_machine_interventions))
+        .route_layer(middleware::from_fn(|req, next| auth::require(Role::Technician, req, next)));
+
+    // Catalog and installed-base writes
+    let editor = Router::new()
         .route("/api/import", post(handlers::import_data))
         .route("/api/import-dipsw", post(handlers::import_dipsw))
-        .route("/api/dipswitches", get(handlers::get_dipswitches))
+        .route("/api/import-dipsw/file", post(handlers::import_dipsw_file))
+        .route("/api/errors/:error_id", axum::routing::put(handlers::edit_error_code))
+        .route("/api/errors/:error_id/parts/:part_id/ranking", axum::routing::put(handlers::set_part_ranking))
+        .route("/api/rankings/recalculate", post(handlers::recalculate_rankings))
+        .route("/api/audit", get(handlers::get_audit_log))
+        .route("/api/imports", get(handlers::get_import_batches))
+        .route("/api/column-mappings", get(handlers::get_column_mappings).post(handlers::save_column_mapping))
+        .route("/api/column-mappings/:name", axum::routing::delete(handlers::delete_column_mapping))
+        .route("/api/imports/:id", get(handlers::get_import_batch))
+        .route("/api/imports/:id/rollback", post(handlers::rollback_import_batch))
+        .route("/api/errors/:error_id/revisions/:revision/restore", post(handlers::restore_error_revision))
+        .route("/api/machines", post(handlers::create_machine))
+        .route("/api/machines/:id", axum::routing::put(handlers::update_machine).delete(handlers::delete_machine))
+        .route_layer(middleware::from_fn(|req, next| auth::require(Role::Editor, req, next)));
+
+    let admin = Router::new()
+        .route("/api/auth/keys", get(handlers::get_api_keys).post(handlers::create_api_key))
+        .route("/api/auth/keys/:id", axum::routing::delete(handlers::revoke_api_key))
+        .route_layer(middleware::from_fn(|req, next| auth::require(Role::Admin, req, next)));
+
+    // CORS_ORIGINS: comma-separated allowed origins; unset, browsers only call the API from its own origin
+    let origins: Vec<HeaderValue> = std::env::var("CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .filter_map(|o| HeaderValue::from_str(o).ok())
+        .collect();
+    let cors = if origins.is_empty() {
+        CorsLayer::new()
+    } else {
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods(Any)
+            .allow_headers(Any)
+    };
+
+    let app = Router::new()
+        .route("/api/health", get(|| async { "OK" }))
+        .merge(catalog)
+        .merge(account)
+        .merge(technician)
+        .merge(editor)
+        .merge(admin)
         .layer(DefaultBodyLimit::max(50 * 1024 * 1024)) // 50MB limit
-        .layer(CorsLayer::permissive())
+        .layer(middleware::from_fn_with_state(state.clone(), auth::authenticate))
+        .layer(cors)
         .with_state(state);
 
     let port = std::env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8000);
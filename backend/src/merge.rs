@@ -0,0 +1,319 @@
+//! Field-level merge of imported error code texts into the stored ones. Every text field remembers
+//! whether an import or a manual edit wrote it last (`ErrorCode::field_sources`), and a policy per
+//! field decides whether an import may replace it.
+
+use crate::models::ErrorCode;
+use crate::repository::ErrorCodeFields;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Written by an error code import
+pub const SOURCE_IMPORT: &str = "import";
+/// Written by someone editing or restoring the error code
+pub const SOURCE_MANUAL: &str = "manual";
+
+/// The text fields of an error code, as named in `field_sources` and in the audit log.
+pub const ERROR_CODE_FIELDS: [&str; 8] = [
+    "classification",
+    "cause",
+    "measures",
+    "solution",
+    "estimated_abnormal_parts",
+    "correction",
+    "faulty_part_isolation",
+    "note",
+];
+
+/// Whether an import may replace a stored field.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergePolicy {
+    /// The file always wins, an empty cell clears the field
+    Overwrite,
+    /// The file wins unless its cell is empty
+    IfNonEmpty,
+    /// The file wins unless the field was last written by hand
+    KeepManual,
+}
+
+impl MergePolicy {
+    pub fn parse(name: &str) -> Option<MergePolicy> {
+        match name {
+            "overwrite" => Some(MergePolicy::Overwrite),
+            "if-non-empty" => Some(MergePolicy::IfNonEmpty),
+            "keep-manual" => Some(MergePolicy::KeepManual),
+            _ => None,
+        }
+    }
+}
+
+/// The policy of every field of an import.
+#[derive(Debug, Clone)]
+pub struct MergePolicies {
+    pub default: MergePolicy,
+    pub fields: BTreeMap<&'static str, MergePolicy>,
+}
+
+impl Default for MergePolicies {
+    fn default() -> Self {
+        MergePolicies { default: MergePolicy::KeepManual, fields: BTreeMap::new() }
+    }
+}
+
+impl MergePolicies {
+    /// Comma-separated policies: a bare one applies to every field, `field=policy` to one, as in
+    /// `if-non-empty,note=keep-manual`. Empty means `keep-manual` everywhere.
+    pub fn parse(spec: &str) -> Result<MergePolicies, String> {
+        let mut policies = MergePolicies::default();
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (field, name) = match item.split_once('=') {
+                Some((field, name)) => (Some(field.trim()), name.trim()),
+                None => (None, item),
+            };
+            let policy = MergePolicy::parse(name)
+                .ok_or_else(|| format!("Unknown merge policy {}: use overwrite, if-non-empty or keep-manual", name))?;
+            match field {
+                None => policies.default = policy,
+                Some(field) => {
+                    let field = ERROR_CODE_FIELDS
+                        .iter()
+                        .find(|f| **f == field)
+                        .copied()
+                        .ok_or_else(|| format!("Unknown field {} in merge policy", field))?;
+                    policies.fields.insert(field, policy);
+                }
+            }
+        }
+        Ok(policies)
+    }
+
+    pub fn get(&self, field: &str) -> MergePolicy {
+        self.fields.get(field).copied().unwrap_or(self.default)
+    }
+}
+
+/// A field edited by hand that the file has another value for.
+#[derive(Serialize, Debug)]
+pub struct MergeConflict {
+    pub field: &'static str,
+    pub stored: Option<String>,
+    pub imported: Option<String>,
+    pub policy: MergePolicy,
+    /// Whether the stored value was kept
+    pub kept: bool,
+}
+
+/// An import row merged into the stored one.
+pub struct MergedFields<'a> {
+    pub fields: ErrorCodeFields<'a>,
+    pub sources: BTreeMap<String, String>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+fn field_mut<'f, 'a>(fields: &'f mut ErrorCodeFields<'a>, name: &str) -> &'f mut Option<&'a str> {
+    match name {
+        "classification" => &mut fields.classification,
+        "cause" => &mut fields.cause,
+        "measures" => &mut fields.measures,
+        "solution" => &mut fields.solution,
+        "estimated_abnormal_parts" => &mut fields.estimated_abnormal_parts,
+        "correction" => &mut fields.correction,
+        "faulty_part_isolation" => &mut fields.faulty_part_isolation,
+        "note" => &mut fields.note,
+        _ => unreachable!("not an error code field: {}", name),
+    }
+}
+
+fn stored_field<'e>(error: &'e ErrorCode, name: &str) -> Option<&'e str> {
+    match name {
+        "classification" => error.classification.as_deref(),
+        "cause" => error.cause.as_deref(),
+        "measures" => error.measures.as_deref(),
+        "solution" => error.solution.as_deref(),
+        "estimated_abnormal_parts" => error.estimated_abnormal_parts.as_deref(),
+        "correction" => error.correction.as_deref(),
+        "faulty_part_isolation" => error.faulty_part_isolation.as_deref(),
+        "note" => error.note.as_deref(),
+        _ => unreachable!("not an error code field: {}", name),
+    }
+}
+
+/// The texts an import row leaves on the stored error code (`None` for a new one), with the source
+/// of each field afterwards and the manual edits the file disagrees with.
+pub fn merge_import<'a>(stored: Option<&'a ErrorCode>, imported: ErrorCodeFields<'a>, policies: &MergePolicies) -> MergedFields<'a> {
+    let mut fields = imported;
+    let mut sources = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for field in ERROR_CODE_FIELDS {
+        let incoming = *field_mut(&mut fields, field);
+        let Some(stored) = stored else {
+            sources.insert(field.to_string(), SOURCE_IMPORT.to_string());
+            continue;
+        };
+        let current = stored_field(stored, field);
+        let source = stored.field_sources.get(field).map(String::as_str);
+        let policy = policies.get(field);
+        let keep = match policy {
+            MergePolicy::Overwrite => false,
+            MergePolicy::IfNonEmpty => incoming.is_none(),
+            MergePolicy::KeepManual => source == Some(SOURCE_MANUAL),
+        };
+        if source == Some(SOURCE_MANUAL) && incoming != current {
+            conflicts.push(MergeConflict {
+                field,
+                stored: current.map(str::to_string),
+                imported: incoming.map(str::to_string),
+                policy,
+                kept: keep,
+            });
+        }
+        if keep {
+            *field_mut(&mut fields, field) = current;
+            if let Some(source) = source {
+                sources.insert(field.to_string(), source.to_string());
+            }
+        } else {
+            sources.insert(field.to_string(), SOURCE_IMPORT.to_string());
+        }
+    }
+    MergedFields { fields, sources, conflicts }
+}
+
+/// The sources of a stored error code after writing `fields` by hand: the fields that change become manual.
+pub fn manual_sources(stored: &ErrorCode, fields: &ErrorCodeFields) -> BTreeMap<String, String> {
+    let mut sources = stored.field_sources.0.clone();
+    let mut fields = *fields;
+    for field in ERROR_CODE_FIELDS {
+        if *field_mut(&mut fields, field) != stored_field(stored, field) {
+            sources.insert(field.to_string(), SOURCE_MANUAL.to_string());
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Provenance;
+    use uuid::Uuid;
+
+    /// A stored code with `cause` and `note` set, `cause` last written by `cause_source`.
+    fn stored(cause_source: &str) -> ErrorCode {
+        ErrorCode {
+            id: Uuid::new_v4(),
+            printer_id: Uuid::new_v4(),
+            code: "C-0101".to_string(),
+            classification: None,
+            cause: Some("Stored cause".to_string()),
+            measures: None,
+            solution: None,
+            estimated_abnormal_parts: None,
+            correction: None,
+            faulty_part_isolation: None,
+            note: Some("Stored note".to_string()),
+            field_sources: sqlx::types::Json(BTreeMap::from([
+                ("cause".to_string(), cause_source.to_string()),
+                ("note".to_string(), SOURCE_IMPORT.to_string()),
+            ])),
+            obsolete_at: None,
+            obsolete_batch_id: None,
+            provenance: Provenance::default(),
+            parts: Vec::new(),
+            feedback: None,
+        }
+    }
+
+    fn imported(cause: Option<&'static str>) -> ErrorCodeFields<'static> {
+        ErrorCodeFields { code: "C-0101", cause, note: Some("File note"), ..Default::default() }
+    }
+
+    fn policies(spec: &str) -> MergePolicies {
+        MergePolicies::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn new_codes_take_the_file() {
+        let merged = merge_import(None, imported(Some("File cause")), &policies("keep-manual"));
+        assert_eq!(merged.fields.cause, Some("File cause"));
+        assert!(merged.sources.values().all(|s| s == SOURCE_IMPORT));
+        assert_eq!(merged.sources.len(), ERROR_CODE_FIELDS.len());
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn applies_each_policy() {
+        // (policy, source of the stored cause, cell in the file) => (cause afterwards, its source, conflict kept)
+        let cases = [
+            ("overwrite", SOURCE_MANUAL, Some("File cause"), Some("File cause"), SOURCE_IMPORT, Some(false)),
+            ("overwrite", SOURCE_MANUAL, None, None, SOURCE_IMPORT, Some(false)),
+            ("overwrite", SOURCE_IMPORT, None, None, SOURCE_IMPORT, None),
+            ("if-non-empty", SOURCE_MANUAL, Some("File cause"), Some("File cause"), SOURCE_IMPORT, Some(false)),
+            ("if-non-empty", SOURCE_MANUAL, None, Some("Stored cause"), SOURCE_MANUAL, Some(true)),
+            ("if-non-empty", SOURCE_IMPORT, None, Some("Stored cause"), SOURCE_IMPORT, None),
+            ("if-non-empty", SOURCE_IMPORT, Some("File cause"), Some("File cause"), SOURCE_IMPORT, None),
+            ("keep-manual", SOURCE_MANUAL, Some("File cause"), Some("Stored cause"), SOURCE_MANUAL, Some(true)),
+            ("keep-manual", SOURCE_MANUAL, None, Some("Stored cause"), SOURCE_MANUAL, Some(true)),
+            ("keep-manual", SOURCE_IMPORT, Some("File cause"), Some("File cause"), SOURCE_IMPORT, None),
+            ("keep-manual", SOURCE_IMPORT, None, None, SOURCE_IMPORT, None),
+        ];
+        for (policy, source, cell, cause, cause_source, kept) in cases {
+            let stored = stored(source);
+            let merged = merge_import(Some(&stored), imported(cell), &policies(policy));
+            let case = format!("{} on {} with {:?}", policy, source, cell);
+            assert_eq!(merged.fields.cause, cause, "{}", case);
+            assert_eq!(merged.sources["cause"], cause_source, "{}", case);
+            assert_eq!(merged.conflicts.iter().find(|c| c.field == "cause").map(|c| c.kept), kept, "{}", case);
+            // Fields last written by the import never conflict
+            assert!(merged.conflicts.iter().all(|c| c.field == "cause"), "{}", case);
+        }
+    }
+
+    #[test]
+    fn reports_conflicts_only_for_changed_manual_fields() {
+        let stored = stored(SOURCE_MANUAL);
+        let merged = merge_import(Some(&stored), imported(Some("Stored cause")), &policies("overwrite"));
+        assert!(merged.conflicts.is_empty());
+
+        let merged = merge_import(Some(&stored), imported(Some("File cause")), &policies("keep-manual"));
+        let conflict = &merged.conflicts[0];
+        assert_eq!(conflict.stored.as_deref(), Some("Stored cause"));
+        assert_eq!(conflict.imported.as_deref(), Some("File cause"));
+        assert_eq!(conflict.policy, MergePolicy::KeepManual);
+    }
+
+    #[test]
+    fn field_policies_override_the_default() {
+        let stored = stored(SOURCE_MANUAL);
+        let merged = merge_import(Some(&stored), imported(None), &policies("overwrite,cause=keep-manual"));
+        assert_eq!(merged.fields.cause, Some("Stored cause"));
+        assert_eq!(merged.fields.note, Some("File note"));
+        assert_eq!(merged.fields.classification, None);
+    }
+
+    #[test]
+    fn parses_policies() {
+        let parsed = policies("");
+        assert_eq!(parsed.default, MergePolicy::KeepManual);
+        assert!(parsed.fields.is_empty());
+
+        let parsed = policies(" if-non-empty , note = overwrite,cause=keep-manual ");
+        assert_eq!(parsed.default, MergePolicy::IfNonEmpty);
+        assert_eq!(parsed.get("note"), MergePolicy::Overwrite);
+        assert_eq!(parsed.get("cause"), MergePolicy::KeepManual);
+        assert_eq!(parsed.get("solution"), MergePolicy::IfNonEmpty);
+
+        for spec in ["replace", "note=replace", "code=overwrite", "notes=keep-manual", "overwrite,=overwrite"] {
+            assert!(MergePolicies::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn manual_edits_mark_changed_fields() {
+        let stored = stored(SOURCE_IMPORT);
+        let edited = ErrorCodeFields { code: "C-0101", cause: Some("Stored cause"), note: Some("Fixed by hand"), ..Default::default() };
+        let sources = manual_sources(&stored, &edited);
+        assert_eq!(sources["note"], SOURCE_MANUAL);
+        assert_eq!(sources["cause"], SOURCE_IMPORT);
+        assert!(!sources.contains_key("solution"));
+    }
+}
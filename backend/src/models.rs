@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+/// Variant label used when a switch only carries a single default column.
+pub const DEFAULT_VARIANT: &str = "default";
+
 #[derive(Serialize, Deserialize, FromRow, Debug)]
 pub struct Printer {
     pub id: Uuid,
@@ -15,6 +19,26 @@ pub struct SparePart {
     pub description: String,
     pub image_url: Option<String>,
     pub ranking: i32,
+    /// Where `ranking` comes from, for parts listed under an error code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub ranking_info: Option<PartRanking>,
+}
+
+/// Origin of a part's ranking for one error code: `catalog` (as imported), `learned` (from recorded
+/// interventions) or `manual` (set by hand, wins over the other two).
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct PartRanking {
+    pub ranking_source: String,
+    /// Recorded interventions on this code that replaced the part and have a final outcome
+    pub fix_attempts: i32,
+    /// ... of which resolved the code
+    pub fix_successes: i32,
+    /// Smoothed success rate the learned ranking was derived from
+    pub success_rate: Option<f64>,
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub explanation: String,
 }
 
 #[derive(Serialize, Deserialize, FromRow, Debug)]
@@ -30,24 +54,63 @@ pub struct ErrorCode {
     pub correction: Option<String>,
     pub faulty_part_isolation: Option<String>,
     pub note: Option<String>,
+    /// Per text field, whether an import or a manual edit wrote it last
+    #[serde(default)]
+    pub field_sources: sqlx::types::Json<BTreeMap<String, String>>,
+    /// Set when an import no longer listed the code; obsolete codes are hidden from search and export
+    #[serde(default)]
+    pub obsolete_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Import that marked the code obsolete
+    #[serde(default)]
+    pub obsolete_batch_id: Option<Uuid>,
+    #[serde(default)]
+    #[sqlx(flatten)]
+    pub provenance: Provenance,
     #[sqlx(skip)]
     pub parts: Vec<SparePart>,
+    /// What technicians reported about this entry (`/api/errors` only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub feedback: Option<FeedbackSummary>,
+}
+
+/// Written by a spreadsheet import
+pub const SOURCE_KIND_SHEET: &str = "sheet";
+/// Imported from rows extracted from a service manual PDF (rows with a page)
+pub const SOURCE_KIND_PDF: &str = "pdf";
+/// Texts edited or restored by hand since the import
+pub const SOURCE_KIND_MANUAL: &str = "manual";
+
+/// Where the texts of an error code come from: the document, sheet and row or page of the import
+/// that last wrote it, and whether it was edited by hand since.
+#[derive(Serialize, Deserialize, FromRow, Debug, Default, Clone)]
+pub struct Provenance {
+    /// One of the `SOURCE_KIND_*`; `None` for codes written before this was recorded
+    #[serde(rename = "kind")]
+    pub source_kind: Option<String>,
+    /// Service manual or workbook the rows come from
+    #[serde(rename = "document")]
+    pub source_document: Option<String>,
+    #[serde(rename = "sheet")]
+    pub source_sheet: Option<String>,
+    /// Row of the import file, first data row is 1
+    #[serde(rename = "row")]
+    pub source_row: Option<i32>,
+    /// Page of the service manual
+    #[serde(rename = "page")]
+    pub source_page: Option<i32>,
+    #[serde(rename = "batch_id")]
+    pub import_batch_id: Option<Uuid>,
+    pub imported_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ImportRow {
-    pub Code: String,
-    pub Classification: Option<String>,
-    pub Cause: Option<String>,
-    #[serde(rename = "Measures to take when an alert occurs")]
-    pub Measures: Option<String>,
-    pub Solution: Option<String>,
-    #[serde(rename = "Estimated abnormal parts")]
-    pub EstimatedParts: Option<String>,
-    pub Correction: Option<String>,
-    #[serde(rename = "Faulty part isolation DIPSW")]
-    pub FaultyPartIsolation: Option<String>,
-    pub Note: Option<String>,
+/// Trace of a deleted row for delta sync. `entity` is the table, `entity_key` its key columns
+/// (`{"id": ..}`, or `{"error_id": .., "part_id": ..}` for error_parts).
+#[derive(Serialize, FromRow, Debug)]
+pub struct Tombstone {
+    pub entity: String,
+    pub entity_key: sqlx::types::Json<serde_json::Value>,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Serialize, Deserialize, FromRow, Debug)]
@@ -60,4 +123,346 @@ pub struct DipSwitch {
     pub setting_0: Option<String>,
     pub setting_1: Option<String>,
     pub default_val: Option<String>,
+    /// Last bit of a multi-bit field (`bit_number` is the first one). `None` for plain single-bit switches.
+    pub end_bit: Option<i32>,
+    /// One of `RISK_LEVELS`
+    pub risk_level: String,
+    /// Only meant to be changed temporarily (e.g. while isolating a faulty part)
+    pub temporary_only: bool,
+    pub risk_note: Option<String>,
+    #[sqlx(skip)]
+    pub defaults: Vec<DipSwitchDefault>,
+    #[sqlx(skip)]
+    pub values: Vec<DipSwitchValue>,
+    #[sqlx(skip)]
+    pub rules: Vec<DipSwitchRule>,
+}
+
+/// One switch entry of a DIP switch import (JSON payload or sheet row).
+#[derive(Deserialize)]
+pub struct DipSwitchImport {
+    pub model_name: String,
+    pub switch_number: i32,
+    pub bit_number: i32,
+    pub function_name: Option<String>,
+    pub setting_0: Option<String>,
+    pub setting_1: Option<String>,
+    pub default_val: Option<String>,
+    /// All default columns of the manual, in table order (Def1, Def2, ...).
+    #[serde(default)]
+    pub defaults: Vec<DipSwitchDefault>,
+    /// Last bit of a multi-bit field starting at `bit_number`.
+    pub end_bit: Option<i32>,
+    /// Value table of a multi-bit field.
+    #[serde(default)]
+    pub values: Vec<DipSwitchValue>,
+    /// One of `RISK_LEVELS`; "none" when omitted
+    pub risk_level: Option<String>,
+    #[serde(default)]
+    pub temporary_only: bool,
+    pub risk_note: Option<String>,
+    /// Dependency rules triggered by this switch's value
+    #[serde(default)]
+    pub rules: Vec<DipSwitchRule>,
+}
+
+impl DipSwitchImport {
+    /// The variant defaults to store. Legacy payloads with only `default_val` get a single `DEFAULT_VARIANT` entry.
+    pub fn variant_defaults(&self) -> Vec<DipSwitchDefault> {
+        if !self.defaults.is_empty() {
+            return self.defaults.clone();
+        }
+        match &self.default_val {
+            Some(v) => vec![DipSwitchDefault { variant: DEFAULT_VARIANT.to_string(), value: Some(v.clone()) }],
+            None => Vec::new(),
+        }
+    }
+
+    /// Last bit covered by the item (equal to `bit_number` for single-bit switches).
+    pub fn last_bit(&self) -> i32 {
+        self.end_bit.unwrap_or(self.bit_number)
+    }
+
+    /// The primary default kept in `dip_switches.default_val`: the explicit one, otherwise the first variant.
+    pub fn primary_default(&self) -> Option<String> {
+        self.default_val
+            .clone()
+            .or_else(|| self.defaults.iter().find_map(|d| d.value.clone()))
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct DipSwitchSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub switch: DipSwitch,
+    pub rank: f32,
+    /// `function_name` excerpt as safe HTML: the text escaped, the matched words wrapped in `<mark>`
+    pub function_highlight: Option<String>,
+    /// Settings / value-table excerpt as safe HTML, like `function_highlight`
+    pub settings_highlight: Option<String>,
+}
+
+/// Risk levels of a DIP switch, from harmless to safety-relevant.
+pub const RISK_LEVELS: [&str; 4] = ["none", "low", "medium", "high"];
+
+/// One default column of the service manual table (e.g. a destination or sub-model variant).
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct DipSwitchDefault {
+    pub variant: String,
+    pub value: Option<String>,
+}
+
+/// One entry of a multi-bit field's value table. `value` reads the bit range with `bit_number` as the lowest bit.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct DipSwitchValue {
+    pub value: i32,
+    pub meaning: Option<String>,
+    /// Bit pattern as printed in the manual, highest bit first (e.g. "010"). Filled in on output.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    pub bits: String,
+}
+
+/// Dependency rule: while this switch is at `when_value`, the target must (`requires`) or must not
+/// (`conflicts`) be at `target_value`. The target is addressed by its first bit within the same model.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct DipSwitchRule {
+    #[serde(default = "default_rule_when")]
+    pub when_value: i32,
+    #[serde(default = "default_rule_kind")]
+    pub kind: String,
+    pub target_switch: i32,
+    pub target_bit: i32,
+    pub target_value: i32,
+    pub message: Option<String>,
+}
+
+fn default_rule_when() -> i32 {
+    1
+}
+
+fn default_rule_kind() -> String {
+    "requires".to_string()
+}
+
+/// A named set of DIP switch values for a model, checked against the safety rules before it is saved.
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+pub struct DipSwitchProfile {
+    pub id: Uuid,
+    pub model_name: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Default column the profile starts from
+    pub variant: Option<String>,
+    pub settings: sqlx::types::Json<Vec<DipSwitchSetting>>,
+}
+
+/// The value chosen for one switch entry (0/1, or the field value for multi-bit fields).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DipSwitchSetting {
+    pub switch_number: i32,
+    pub bit_number: i32,
+    pub value: i32,
+}
+
+impl DipSwitch {
+    /// Number of bits covered by this entry.
+    pub fn width(&self) -> i32 {
+        self.end_bit.map(|e| e - self.bit_number + 1).unwrap_or(1)
+    }
+}
+
+/// Outcomes of a service intervention.
+pub const INTERVENTION_OUTCOMES: [&str; 4] = ["resolved", "partially_resolved", "unresolved", "follow_up"];
+
+/// What a technician did on one machine visit. The linked error codes and parts keep their code text,
+/// so the record stays readable if the catalog entry is later removed (the link then becomes `null`).
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+pub struct Intervention {
+    pub id: Uuid,
+    pub serial_number: String,
+    pub model_name: String,
+    pub performed_on: chrono::NaiveDate,
+    pub technician: String,
+    pub minutes_spent: Option<i32>,
+    pub outcome: String,
+    pub notes: Option<String>,
+    #[sqlx(skip)]
+    pub error_codes: Vec<InterventionErrorCode>,
+    #[sqlx(skip)]
+    pub parts: Vec<InterventionPart>,
+    #[sqlx(skip)]
+    pub dip_switches: Vec<InterventionDipSwitch>,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct InterventionErrorCode {
+    pub error_id: Option<Uuid>,
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct InterventionPart {
+    pub part_id: Option<Uuid>,
+    pub oem_code: String,
+    pub quantity: i32,
+}
+
+/// A DIP switch changed during the intervention; `previous_value` is what the machine had before, if noted.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct InterventionDipSwitch {
+    pub switch_number: i32,
+    pub bit_number: i32,
+    pub previous_value: Option<i32>,
+    pub value: i32,
+}
+
+/// A physical machine we maintain. `printer_id`/`model_name` point at the model catalog, so error codes and
+/// DIP switches of the machine are those of its model.
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+pub struct Machine {
+    pub id: Uuid,
+    pub serial_number: String,
+    pub printer_id: Uuid,
+    pub model_name: String,
+    pub customer: Option<String>,
+    pub site: Option<String>,
+    pub installed_on: Option<chrono::NaiveDate>,
+    pub firmware: Option<String>,
+    /// Options fitted (finisher, fax board, ...)
+    pub options: sqlx::types::Json<Vec<String>>,
+    pub notes: Option<String>,
+}
+
+/// A technician's verdict on an error code's solution (`part_id` empty) or on one part for it.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct ErrorFeedback {
+    pub id: Uuid,
+    pub error_id: Uuid,
+    pub part_id: Option<Uuid>,
+    /// true: "this fixed it", false: it did not
+    pub worked: bool,
+    pub technician: String,
+    pub comment: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Feedback on one error code, counted per verdict.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FeedbackSummary {
+    pub confirmed: i64,
+    pub rejected: i64,
+    /// Latest comments on the solution and parts, newest first
+    pub comments: Vec<ErrorFeedback>,
+    pub parts: Vec<PartFeedback>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PartFeedback {
+    pub part_id: Uuid,
+    pub confirmed: i64,
+    pub rejected: i64,
+}
+
+/// An API key. The key itself is only returned when it is created.
+#[derive(Serialize, FromRow, Debug)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// First characters of the key, to tell keys apart
+    pub key_prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    /// admin, editor, technician or read-only
+    pub role: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// One changed field of one row. `old_value`/`new_value` are the column values as text (empty for
+/// created and deleted rows respectively).
+#[derive(Serialize, FromRow, Debug)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+    /// Name of the API key (or the key a token was issued for)
+    pub user_name: String,
+    /// create, update, delete or restore
+    pub action: String,
+    /// error_code, dip_switch, machine or error_part
+    pub entity: String,
+    pub entity_id: Option<Uuid>,
+    pub model_name: Option<String>,
+    /// The error code, "switch-bit", the serial number, or the part id for part links (whose
+    /// `entity_id` is the error code)
+    pub entity_key: String,
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    /// Uploaded file the change came from
+    pub source_file: Option<String>,
+}
+
+/// One stored version of an error code's texts. Revision 1 is the row as first seen: when it was
+/// imported, or its value when revisions started being kept (action "baseline").
+#[derive(Serialize, FromRow, Debug)]
+pub struct ErrorCodeRevision {
+    pub id: Uuid,
+    pub error_id: Uuid,
+    pub revision: i32,
+    /// baseline, create, update or restore
+    pub action: String,
+    pub user_name: Option<String>,
+    pub source_file: Option<String>,
+    /// Import batch that wrote the revision
+    pub batch_id: Option<Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub code: String,
+    pub classification: Option<String>,
+    pub cause: Option<String>,
+    pub measures: Option<String>,
+    pub solution: Option<String>,
+    pub estimated_abnormal_parts: Option<String>,
+    pub correction: Option<String>,
+    pub faulty_part_isolation: Option<String>,
+    pub note: Option<String>,
+    /// On the revision of an import that brought an obsolete code back: since when, and by which
+    /// import, it had been obsolete (a rollback retires it again)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub previous_obsolete_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub previous_obsolete_batch_id: Option<Uuid>,
+}
+
+/// One `import_data` call: the file, who uploaded it and what it did. Rows it wrote carry its id.
+#[derive(Serialize, FromRow, Debug)]
+pub struct ImportBatch {
+    pub id: Uuid,
+    pub source_file: Option<String>,
+    /// SHA-256 of the uploaded file, hex
+    pub content_hash: String,
+    pub user_name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub total_rows: i32,
+    pub created_count: i32,
+    pub updated_count: i32,
+    pub unchanged_count: i32,
+    pub failed_count: i32,
+    /// Stored codes the file no longer listed, marked obsolete
+    pub obsolete_count: i32,
+    pub rolled_back_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub rolled_back_by: Option<String>,
+}
+
+/// Saved header mapping for error code imports: per import field ("code", "cause", ...), the source
+/// column names that hold it, tried in order.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct ColumnMapping {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub columns: sqlx::types::Json<BTreeMap<String, Vec<String>>>,
 }
@@ -0,0 +1,94 @@
+use crate::models::PartRanking;
+use uuid::Uuid;
+
+/// Weight of the catalog ranking, in recorded interventions: with 2, a part needs a few field results
+/// before it moves far from where the catalog put it.
+const PRIOR_WEIGHT: f64 = 2.0;
+/// The catalog prior never claims certainty either way.
+const PRIOR_MIN: f64 = 0.1;
+const PRIOR_MAX: f64 = 0.9;
+
+pub const SOURCE_CATALOG: &str = "catalog";
+pub const SOURCE_LEARNED: &str = "learned";
+pub const SOURCE_MANUAL: &str = "manual";
+
+/// One error-code/part link with the field results recorded against it.
+#[derive(sqlx::FromRow, Debug)]
+pub struct RankingInput {
+    pub error_id: Uuid,
+    pub part_id: Uuid,
+    /// Ranking the catalog gave the link (1–5 stars)
+    pub catalog_ranking: i32,
+    pub manual_ranking: Option<i32>,
+    pub attempts: i64,
+    pub successes: i64,
+}
+
+/// The ranking to store for one link.
+#[derive(Debug)]
+pub struct RankingUpdate {
+    pub error_id: Uuid,
+    pub part_id: Uuid,
+    pub ranking: i32,
+    pub catalog_ranking: i32,
+    pub source: &'static str,
+    pub attempts: i32,
+    pub successes: i32,
+    pub success_rate: Option<f64>,
+}
+
+fn catalog_rate(ranking: i32) -> f64 {
+    (f64::from(ranking.clamp(1, 5) - 1) / 4.0).clamp(PRIOR_MIN, PRIOR_MAX)
+}
+
+/// 1 star for parts that never fix the code, 5 for parts that always do.
+fn rate_to_ranking(rate: f64) -> i32 {
+    (1.0 + (rate * 4.0).round()) as i32
+}
+
+/// Success rate of the link, pulled towards its catalog ranking while there are few results
+/// (`PRIOR_WEIGHT` pseudo-interventions at the catalog rate).
+pub fn smoothed_rate(successes: i64, attempts: i64, catalog_ranking: i32) -> f64 {
+    (successes as f64 + PRIOR_WEIGHT * catalog_rate(catalog_ranking)) / (attempts as f64 + PRIOR_WEIGHT)
+}
+
+/// Manual overrides win; otherwise links with recorded results get the learned ranking and the
+/// rest keep their catalog ranking.
+pub fn rank(input: &RankingInput) -> RankingUpdate {
+    // Rounded so that recalculating unchanged data stores exactly the same value
+    let success_rate = (input.attempts > 0)
+        .then(|| (smoothed_rate(input.successes, input.attempts, input.catalog_ranking) * 10_000.0).round() / 10_000.0);
+    let (ranking, source) = match (input.manual_ranking, success_rate) {
+        (Some(manual), _) => (manual, SOURCE_MANUAL),
+        (None, Some(rate)) => (rate_to_ranking(rate), SOURCE_LEARNED),
+        (None, None) => (input.catalog_ranking, SOURCE_CATALOG),
+    };
+    RankingUpdate {
+        error_id: input.error_id,
+        part_id: input.part_id,
+        ranking,
+        catalog_ranking: input.catalog_ranking,
+        source,
+        attempts: input.attempts as i32,
+        successes: input.successes as i32,
+        success_rate,
+    }
+}
+
+/// A sentence for the technician on where the ranking comes from.
+pub fn explain(info: &PartRanking) -> String {
+    let results = format!(
+        "{} of {} recorded repairs with this part resolved the code",
+        info.fix_successes, info.fix_attempts
+    );
+    match info.ranking_source.as_str() {
+        SOURCE_MANUAL if info.fix_attempts > 0 => format!("Set by hand ({})", results),
+        SOURCE_MANUAL => "Set by hand".to_string(),
+        SOURCE_LEARNED => format!(
+            "Learned: {} (smoothed success rate {:.0}%)",
+            results,
+            info.success_rate.unwrap_or_default() * 100.0
+        ),
+        _ => "Catalog ranking, no repairs with this part recorded yet".to_string(),
+    }
+}
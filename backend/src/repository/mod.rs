@@ -0,0 +1,397 @@
+//! Storage behind the handlers. `DATABASE_URL` picks the implementation: `sqlite:` URLs run fully local
+//! on a SQLite file (standalone laptops), anything else connects to Postgres.
+
+use crate::models::{
+    ApiKey, AuditEntry, ColumnMapping, DipSwitch, DipSwitchDefault, DipSwitchImport, DipSwitchProfile, DipSwitchRule, DipSwitchSearchHit,
+    DipSwitchSetting, DipSwitchValue, ErrorCode, ErrorCodeRevision, ErrorFeedback, ImportBatch, Intervention, InterventionDipSwitch, InterventionErrorCode,
+    InterventionPart, Machine, PartRanking, Printer, Provenance, SparePart, Tombstone,
+};
+use crate::ranking::{RankingInput, RankingUpdate};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use uuid::Uuid;
+
+mod postgres;
+mod sqlite;
+
+pub use postgres::PgRepository;
+pub use sqlite::SqliteRepository;
+
+pub type DbResult<T> = Result<T, sqlx::Error>;
+
+/// How `/api/errors` matches a typed code: digits only ("24" finds "C-2411") or the code without
+/// dashes and spaces ("c24" finds "C-2411"), both as a prefix.
+#[derive(Debug, Clone)]
+pub enum CodeQuery {
+    Digits(String),
+    Compact(String),
+}
+
+impl CodeQuery {
+    pub fn parse(code: &str) -> CodeQuery {
+        if code.chars().all(|x| x.is_numeric() || x.is_whitespace() || x == '-') {
+            CodeQuery::Digits(code.chars().filter(|x| x.is_numeric()).collect())
+        } else {
+            CodeQuery::Compact(code.replace(['-', ' '], ""))
+        }
+    }
+}
+
+/// The columns of one imported error-code row (empty cells already mapped to `None`).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ErrorCodeFields<'a> {
+    pub code: &'a str,
+    pub classification: Option<&'a str>,
+    pub cause: Option<&'a str>,
+    pub measures: Option<&'a str>,
+    pub solution: Option<&'a str>,
+    pub estimated_abnormal_parts: Option<&'a str>,
+    pub correction: Option<&'a str>,
+    pub faulty_part_isolation: Option<&'a str>,
+    pub note: Option<&'a str>,
+}
+
+/// Rows per multi-row statement of an error code import.
+const IMPORT_CHUNK_ROWS: usize = 500;
+
+/// Put around the matched words by the search queries, before the text is escaped (see `marked_html`).
+const MARK_START: char = '\u{1}';
+const MARK_END: char = '\u{2}';
+
+/// Appends `c` to `out`, escaped for HTML text and attributes.
+fn push_escaped(out: &mut String, c: char) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        _ => out.push(c),
+    }
+}
+
+/// Search excerpt with `MARK_START`/`MARK_END` around the matches as safe HTML with `<mark>` tags.
+fn marked_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            MARK_START => out.push_str("<mark>"),
+            MARK_END => out.push_str("</mark>"),
+            _ => push_escaped(&mut out, c),
+        }
+    }
+    out
+}
+
+/// One row of an error code import, with the revision and audit entries stored along with it.
+#[derive(Debug)]
+pub struct ErrorCodeImportRow<'a> {
+    /// Id of the stored row, or a new id for a code the model doesn't have yet
+    pub id: Uuid,
+    pub model: &'a str,
+    pub fields: ErrorCodeFields<'a>,
+    /// Per text field, whether an import or a manual edit wrote it (see `merge`)
+    pub field_sources: BTreeMap<String, String>,
+    /// Where the row comes from; the batch id is the one of the import
+    pub provenance: Provenance,
+    /// None when the row leaves the stored texts as they are (the row is then not written)
+    pub revision: Option<ErrorCodeRevision>,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// A stored error code the import file doesn't list any more, to mark obsolete, with its audit entries.
+#[derive(Debug)]
+pub struct ObsoleteCode {
+    pub id: Uuid,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// An error code a rollback gives its earlier texts back, with the revision recording it.
+#[derive(Debug)]
+pub struct ErrorCodeRestore {
+    /// The texts to write back (`error_id` names the code)
+    pub revision: ErrorCodeRevision,
+    pub field_sources: BTreeMap<String, String>,
+    /// Obsolete state to put back, when the import brought the code back and it is still active
+    pub obsolete_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub obsolete_batch_id: Option<Uuid>,
+}
+
+/// Everything one import rollback writes, in a single transaction with the claim of the batch.
+#[derive(Debug)]
+pub struct BatchRollback {
+    pub batch_id: Uuid,
+    pub user: String,
+    /// Codes the batch created
+    pub deletes: Vec<Uuid>,
+    /// Codes the batch changed
+    pub restores: Vec<ErrorCodeRestore>,
+    /// Codes the batch marked obsolete
+    pub revives: Vec<Uuid>,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// An import row the database refused: its position in the rows written, and the error.
+#[derive(Serialize, Debug)]
+pub struct ImportFailure {
+    pub row: usize,
+    pub message: String,
+}
+
+/// Sets the batch counts from the rows written. `failed_count` adds to the rows rejected before writing.
+fn tally_import(batch: &mut ImportBatch, rows: &[ErrorCodeImportRow], failures: &[ImportFailure]) {
+    batch.failed_count += failures.len() as i32;
+    for (i, row) in rows.iter().enumerate() {
+        if failures.iter().any(|f| f.row == i) {
+            continue;
+        }
+        match &row.revision {
+            Some(revision) if revision.action == crate::audit::ACTION_CREATE => batch.created_count += 1,
+            Some(_) => batch.updated_count += 1,
+            None => batch.unchanged_count += 1,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct ModelErrorCode {
+    pub model_name: String,
+    #[sqlx(flatten)]
+    pub error: ErrorCode,
+}
+
+/// A part linked to an error code, with the ranking of that link and where it comes from.
+#[derive(sqlx::FromRow, Debug)]
+pub struct ErrorPartLink {
+    pub error_id: Uuid,
+    #[sqlx(flatten)]
+    pub part: SparePart,
+    #[sqlx(flatten)]
+    pub ranking: PartRanking,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub model: Option<String>,
+    pub entity: Option<String>,
+    pub entity_key: Option<String>,
+    pub entity_id: Option<Uuid>,
+    pub user: Option<String>,
+    pub limit: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct DipSwitchFilter {
+    /// All models when empty
+    pub models: Vec<String>,
+    pub switch: Option<i32>,
+    /// Multi-bit fields match any bit inside their range
+    pub bit: Option<i32>,
+    /// Only rows changed at or after this sync token (0 for all)
+    pub since: i64,
+}
+
+/// Result of writing one model's DIP switches: per item `Ok(inserted)` or the database error, and the
+/// number of rows removed by pruning.
+#[derive(Debug, Default)]
+pub struct DipSwitchWrite {
+    pub outcomes: Vec<Result<bool, String>>,
+    pub removed: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct InterventionFilter {
+    pub serial_number: Option<String>,
+    pub model: Option<String>,
+    /// Matched case-insensitively
+    pub technician: Option<String>,
+    /// Only interventions where this error code was seen
+    pub code: Option<String>,
+    pub outcome: Option<String>,
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    pub limit: i64,
+}
+
+/// Linked rows of several interventions, keyed by intervention id.
+#[derive(Debug, Default)]
+pub struct InterventionDetails {
+    pub error_codes: HashMap<Uuid, Vec<InterventionErrorCode>>,
+    pub parts: HashMap<Uuid, Vec<InterventionPart>>,
+    pub dip_switches: HashMap<Uuid, Vec<InterventionDipSwitch>>,
+}
+
+#[derive(Debug, Default)]
+pub struct MachineFilter {
+    /// Part of the serial number, customer or site, case-insensitive
+    pub query: Option<String>,
+    pub model: Option<String>,
+    pub limit: i64,
+}
+
+#[async_trait]
+pub trait Repository: Send + Sync {
+    async fn printers(&self) -> DbResult<Vec<Printer>>;
+    /// Looks up a printer by (normalized) model name, creating it on first use.
+    async fn get_or_create_printer(&self, model: &str) -> DbResult<Uuid>;
+    /// Printers of the given models changed at or after `since`.
+    async fn printers_for_models(&self, models: &[String], since: i64) -> DbResult<Vec<Printer>>;
+
+    /// Obsolete codes are left out unless `include_obsolete`.
+    async fn search_error_codes(&self, model: &str, code: Option<&CodeQuery>, limit: Option<i32>, include_obsolete: bool) -> DbResult<Vec<ErrorCode>>;
+    /// Writes a whole error code import in one transaction: the batch, the rows in multi-row upserts
+    /// (printers created as needed, obsolete codes brought back), the `obsolete` codes marked, then the
+    /// revisions and audit entries of the rows written and the batch counts. The rows of a failing
+    /// statement are retried one at a time to single out the bad ones; with `atomic` any failure rolls
+    /// the whole import back, batch included.
+    async fn import_error_codes(
+        &self,
+        batch: &mut ImportBatch,
+        rows: &[ErrorCodeImportRow<'_>],
+        obsolete: &[ObsoleteCode],
+        atomic: bool,
+    ) -> DbResult<Vec<ImportFailure>>;
+    /// False when it doesn't exist.
+    async fn delete_error_code(&self, id: Uuid) -> DbResult<bool>;
+    /// Replaces the texts of one error code (the code itself is kept), and its provenance kind unless `None`.
+    /// False when it doesn't exist.
+    async fn update_error_code(
+        &self,
+        id: Uuid,
+        fields: &ErrorCodeFields<'_>,
+        field_sources: &BTreeMap<String, String>,
+        source_kind: Option<&str>,
+    ) -> DbResult<bool>;
+    async fn error_code(&self, id: Uuid) -> DbResult<Option<ModelErrorCode>>;
+    /// Error codes of the given models, obsolete ones left out, ordered by model and code.
+    async fn export_error_codes(&self, models: &[String]) -> DbResult<Vec<ModelErrorCode>>;
+    /// Error codes of the given models that changed at or after `since`, counting changes to their part
+    /// links and parts (removed links included).
+    async fn changed_error_codes(&self, models: &[String], since: i64) -> DbResult<Vec<ErrorCode>>;
+    /// Parts of the given error codes, best ranked first.
+    async fn error_part_links(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorPartLink>>;
+    /// Error-code/part links (of one model, or one link) with the outcomes of the interventions that replaced
+    /// the part while the code was seen.
+    async fn ranking_inputs(&self, model: Option<&str>, link: Option<(Uuid, Uuid)>) -> DbResult<Vec<RankingInput>>;
+    /// Stores recalculated rankings; returns how many links changed.
+    async fn apply_rankings(&self, updates: &[RankingUpdate]) -> DbResult<u64>;
+    async fn save_feedback(&self, feedback: &ErrorFeedback) -> DbResult<()>;
+    /// Feedback on the given error codes, newest first.
+    async fn feedback(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorFeedback>>;
+    /// Sets (or with `None` clears) the hand-set ranking of a link. Returns false when the link does not exist.
+    async fn set_manual_ranking(&self, error_id: Uuid, part_id: Uuid, ranking: Option<i32>) -> DbResult<bool>;
+    /// Parts by id with their general ranking, ordered by OEM code.
+    async fn spare_parts(&self, ids: &[Uuid]) -> DbResult<Vec<SparePart>>;
+    /// Ids of the given codes of one model; unknown codes are missing from the map.
+    async fn error_code_ids(&self, model: &str, codes: &[String]) -> DbResult<HashMap<String, Uuid>>;
+    /// Parts with the given OEM codes (unknown codes are skipped).
+    async fn spare_parts_by_oem(&self, oem_codes: &[String]) -> DbResult<Vec<SparePart>>;
+
+    /// Current sync token: every change committed after it is stamped at or above it.
+    async fn sync_token(&self) -> DbResult<i64>;
+    async fn tombstones(&self, since: i64) -> DbResult<Vec<Tombstone>>;
+
+    /// Switches in model, switch and bit order (details not attached).
+    async fn dipswitches(&self, filter: &DipSwitchFilter) -> DbResult<Vec<DipSwitch>>;
+    async fn dipswitch_defaults(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchDefault>>>;
+    async fn dipswitch_values(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchValue>>>;
+    async fn dipswitch_rules(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchRule>>>;
+    /// Writes one model's (already validated) items in a single transaction, each in its own savepoint so
+    /// a failing item does not lose the rest. With `keep`, every other switch of the model is removed.
+    async fn write_dipswitches(
+        &self,
+        model: &str,
+        items: &[&DipSwitchImport],
+        keep: Option<&[(i32, i32)]>,
+    ) -> DbResult<DipSwitchWrite>;
+    /// Full-text search over function and setting texts; every word must match as a prefix.
+    async fn search_dipswitches(&self, words: &[String], model: Option<&str>, limit: i64) -> DbResult<Vec<DipSwitchSearchHit>>;
+
+    async fn dipswitch_profiles(&self, model: Option<&str>) -> DbResult<Vec<DipSwitchProfile>>;
+    async fn dipswitch_profile(&self, id: Uuid) -> DbResult<Option<DipSwitchProfile>>;
+    /// Saves or replaces (by model and name) a profile.
+    async fn save_dipswitch_profile(
+        &self,
+        model: &str,
+        name: &str,
+        description: Option<&str>,
+        variant: Option<&str>,
+        settings: &[DipSwitchSetting],
+    ) -> DbResult<DipSwitchProfile>;
+
+    /// Stores a new intervention with its linked rows, all or nothing.
+    async fn save_intervention(&self, intervention: &Intervention) -> DbResult<()>;
+    /// Interventions matching the filter, newest first (linked rows not attached).
+    async fn interventions(&self, filter: &InterventionFilter) -> DbResult<Vec<Intervention>>;
+    async fn intervention(&self, id: Uuid) -> DbResult<Option<Intervention>>;
+    async fn intervention_details(&self, ids: &[Uuid]) -> DbResult<InterventionDetails>;
+
+    /// Machines matching the filter, by serial number.
+    async fn machines(&self, filter: &MachineFilter) -> DbResult<Vec<Machine>>;
+    async fn machine(&self, id: Uuid) -> DbResult<Option<Machine>>;
+    /// Fails with a unique violation when the serial number is already registered.
+    async fn insert_machine(&self, machine: &Machine) -> DbResult<()>;
+    /// Returns false when there is no machine with that id.
+    async fn update_machine(&self, machine: &Machine) -> DbResult<bool>;
+    async fn delete_machine(&self, id: Uuid) -> DbResult<bool>;
+
+    /// The key with this hash, unless revoked.
+    async fn api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>>;
+    /// The key with this id, unless revoked.
+    async fn api_key(&self, id: Uuid) -> DbResult<Option<ApiKey>>;
+    async fn api_keys(&self) -> DbResult<Vec<ApiKey>>;
+    async fn insert_api_key(&self, key: &ApiKey) -> DbResult<()>;
+    /// false when the key doesn't exist or was already revoked.
+    async fn revoke_api_key(&self, id: Uuid) -> DbResult<bool>;
+
+    /// Appends revisions, numbered after the latest one of their error code (`revision` is ignored).
+    /// At most one revision per error code per call.
+    async fn add_error_code_revisions(&self, revisions: &[ErrorCodeRevision]) -> DbResult<()>;
+    /// Revisions of one error code, newest first.
+    async fn error_code_revisions(&self, error_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>>;
+    /// The revision of each error code of `model` in effect at `at`.
+    async fn error_code_revisions_at(
+        &self,
+        model: &str,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> DbResult<Vec<ErrorCodeRevision>>;
+    /// Newest first.
+    async fn import_batches(&self, limit: i64) -> DbResult<Vec<ImportBatch>>;
+    async fn import_batch(&self, id: Uuid) -> DbResult<Option<ImportBatch>>;
+    /// Revisions written by a batch, by error code and revision.
+    async fn batch_revisions(&self, batch_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>>;
+    /// The error codes a batch marked obsolete that still are.
+    async fn batch_obsolete_codes(&self, batch_id: Uuid) -> DbResult<Vec<ModelErrorCode>>;
+    /// Marks the batch as rolled back and writes the rollback, all or nothing. False (and nothing
+    /// written) when the batch doesn't exist or was already rolled back.
+    async fn rollback_import_batch(&self, rollback: &BatchRollback) -> DbResult<bool>;
+    /// Saved import column mappings, by name.
+    async fn column_mappings(&self) -> DbResult<Vec<ColumnMapping>>;
+    /// Saves or replaces (by name) a mapping.
+    async fn save_column_mapping(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        columns: &BTreeMap<String, Vec<String>>,
+    ) -> DbResult<ColumnMapping>;
+    /// False when it doesn't exist.
+    async fn delete_column_mapping(&self, name: &str) -> DbResult<bool>;
+    async fn record_audit(&self, entries: &[AuditEntry]) -> DbResult<()>;
+    /// Newest first.
+    async fn audit_log(&self, filter: &AuditFilter) -> DbResult<Vec<AuditEntry>>;
+}
+
+/// Connects to `database_url` and brings the schema up to date.
+pub async fn connect(database_url: &str) -> DbResult<Arc<dyn Repository>> {
+    if database_url.starts_with("sqlite:") {
+        let repo = SqliteRepository::connect(database_url).await?;
+        repo.migrate().await?;
+        Ok(Arc::new(repo))
+    } else {
+        let repo = PgRepository::connect(database_url).await?;
+        repo.migrate().await?;
+        Ok(Arc::new(repo))
+    }
+}
@@ -0,0 +1,1894 @@
+use super::{
+    marked_html, tally_import, AuditFilter, BatchRollback, CodeQuery, DbResult, DipSwitchFilter, DipSwitchWrite, ErrorCodeFields, ErrorCodeImportRow,
+    ErrorPartLink, ImportFailure, InterventionDetails, ObsoleteCode, InterventionFilter, MachineFilter, ModelErrorCode, Repository,
+    IMPORT_CHUNK_ROWS, MARK_END, MARK_START,
+};
+use crate::models::{
+    ApiKey, AuditEntry, ColumnMapping, DipSwitch, DipSwitchDefault, DipSwitchImport, DipSwitchProfile, DipSwitchRule, DipSwitchSearchHit,
+    DipSwitchSetting, DipSwitchValue, ErrorCode, ErrorCodeRevision, ErrorFeedback, ImportBatch, Intervention, InterventionDipSwitch, InterventionErrorCode,
+    InterventionPart, Machine, Printer, SparePart, Tombstone,
+};
+use crate::ranking::{RankingInput, RankingUpdate};
+use async_trait::async_trait;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::Connection;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+pub struct PgRepository {
+    pool: sqlx::PgPool,
+}
+
+#[derive(sqlx::FromRow)]
+struct DipSwitchRuleRow {
+    dip_switch_id: Uuid,
+    #[sqlx(flatten)]
+    rule: DipSwitchRule,
+}
+
+/// Turns search words ("toner", "near", "end") into a prefix tsquery ("toner:* & near:* & end:*").
+/// Only letters and digits survive, so the result is always a valid tsquery.
+fn to_prefix_tsquery(words: &[String]) -> Option<String> {
+    let terms: Vec<String> = words
+        .iter()
+        .flat_map(|w| w.split(|c: char| !c.is_alphanumeric()))
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("{}:*", w.to_lowercase()))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" & "))
+    }
+}
+
+impl PgRepository {
+    pub async fn connect(database_url: &str) -> DbResult<PgRepository> {
+        let pool = PgPoolOptions::new().max_connections(5).connect(database_url).await?;
+        Ok(PgRepository { pool })
+    }
+
+    /// Startup migrations; the same statements are kept in `migrations/` for manual runs.
+    pub async fn migrate(&self) -> DbResult<()> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS dip_switches (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                model_name TEXT NOT NULL,
+                switch_number INTEGER NOT NULL,
+                bit_number INTEGER NOT NULL,
+                function_name TEXT,
+                setting_0 TEXT,
+                setting_1 TEXT,
+                default_val TEXT,
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        // One row per (model, switch, bit): drop older duplicates, then enforce it for upserts
+        let _ = sqlx::query(r#"
+            DELETE FROM dip_switches a
+            USING dip_switches b
+            WHERE a.model_name = b.model_name
+              AND a.switch_number = b.switch_number
+              AND a.bit_number = b.bit_number
+              AND a.created_at < b.created_at
+        "#)
+            .execute(&self.pool)
+            .await;
+
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_dip_switches_model_switch_bit ON dip_switches (model_name, switch_number, bit_number)")
+            .execute(&self.pool)
+            .await?;
+
+        // Per-variant defaults (Def1/Def2/Def3 columns of the manual)
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS dip_switch_defaults (
+                dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+                variant TEXT NOT NULL,
+                value TEXT,
+                position INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (dip_switch_id, variant)
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        // Carry over the single default of switches without variants, once: the extra 'default' rows an
+        // unguarded carry-over left next to imported variants are dropped first
+        sqlx::query(r#"
+            DELETE FROM dip_switch_defaults x
+            WHERE x.variant = 'default'
+            AND EXISTS (
+                SELECT 1 FROM dip_switch_defaults o
+                WHERE o.dip_switch_id = x.dip_switch_id AND o.variant <> 'default' AND o.position = x.position
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(r#"
+            INSERT INTO dip_switch_defaults (dip_switch_id, variant, value)
+            SELECT d.id, 'default', d.default_val FROM dip_switches d
+            WHERE d.default_val IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM dip_switch_defaults x WHERE x.dip_switch_id = d.id)
+            ON CONFLICT (dip_switch_id, variant) DO NOTHING
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        // Multi-bit fields: bit range plus an enumerated value table
+        sqlx::query("ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS end_bit INTEGER")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS dip_switch_values (
+                dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+                value INTEGER NOT NULL,
+                meaning TEXT,
+                PRIMARY KEY (dip_switch_id, value)
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        // Safety annotations, dependency rules and saved profiles
+        for ddl in [
+            "ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS risk_level TEXT NOT NULL DEFAULT 'none'",
+            "ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS temporary_only BOOLEAN NOT NULL DEFAULT FALSE",
+            "ALTER TABLE dip_switches ADD COLUMN IF NOT EXISTS risk_note TEXT",
+            r#"
+            CREATE TABLE IF NOT EXISTS dip_switch_rules (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                dip_switch_id UUID NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+                when_value INTEGER NOT NULL DEFAULT 1,
+                kind TEXT NOT NULL DEFAULT 'requires' CHECK (kind IN ('requires', 'conflicts')),
+                target_switch INTEGER NOT NULL,
+                target_bit INTEGER NOT NULL,
+                target_value INTEGER NOT NULL,
+                message TEXT
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS dip_switch_profiles (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                model_name TEXT NOT NULL,
+                name TEXT NOT NULL,
+                description TEXT,
+                variant TEXT,
+                settings JSONB NOT NULL DEFAULT '[]',
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+                UNIQUE (model_name, name)
+            )
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Change tracking for delta sync: stamps on every change, tombstones on delete
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS tombstones (
+                id BIGSERIAL PRIMARY KEY,
+                entity TEXT NOT NULL,
+                entity_key JSONB NOT NULL,
+                deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                change_xid BIGINT NOT NULL
+            );
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_tombstones_change_xid ON tombstones (change_xid)",
+            r#"
+            CREATE OR REPLACE FUNCTION track_change() RETURNS trigger AS $$
+            BEGIN
+                IF TG_OP = 'UPDATE'
+                   AND (to_jsonb(NEW) - 'updated_at' - 'change_xid') = (to_jsonb(OLD) - 'updated_at' - 'change_xid') THEN
+                    RETURN NEW;
+                END IF;
+                NEW.updated_at := NOW();
+                NEW.change_xid := pg_current_xact_id()::text::bigint;
+                RETURN NEW;
+            END
+            $$ LANGUAGE plpgsql
+            "#,
+            r#"
+            CREATE OR REPLACE FUNCTION record_tombstone() RETURNS trigger AS $$
+            DECLARE
+                key JSONB := '{}';
+                col TEXT;
+            BEGIN
+                FOREACH col IN ARRAY TG_ARGV LOOP
+                    key := key || jsonb_build_object(col, to_jsonb(OLD) -> col);
+                END LOOP;
+                INSERT INTO tombstones (entity, entity_key, change_xid)
+                VALUES (TG_TABLE_NAME, key, pg_current_xact_id()::text::bigint);
+                RETURN OLD;
+            END
+            $$ LANGUAGE plpgsql
+            "#,
+            r#"
+            CREATE OR REPLACE FUNCTION touch_dip_switch() RETURNS trigger AS $$
+            DECLARE
+                switch_id UUID;
+            BEGIN
+                IF TG_OP = 'DELETE' THEN
+                    switch_id := OLD.dip_switch_id;
+                ELSE
+                    switch_id := NEW.dip_switch_id;
+                END IF;
+                UPDATE dip_switches
+                SET change_xid = pg_current_xact_id()::text::bigint, updated_at = NOW()
+                WHERE id = switch_id AND change_xid <> pg_current_xact_id()::text::bigint;
+                RETURN NULL;
+            END
+            $$ LANGUAGE plpgsql
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+        for (table, key) in [
+            ("printers", "'id'"),
+            ("error_codes", "'id'"),
+            ("spare_parts", "'id'"),
+            ("error_parts", "'error_id', 'part_id'"),
+            ("dip_switches", "'id'"),
+        ] {
+            for ddl in [
+                format!("ALTER TABLE {table} ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()"),
+                format!("ALTER TABLE {table} ADD COLUMN IF NOT EXISTS change_xid BIGINT NOT NULL DEFAULT 0"),
+                format!("CREATE INDEX IF NOT EXISTS idx_{table}_change_xid ON {table} (change_xid)"),
+                format!("DROP TRIGGER IF EXISTS {table}_track_change ON {table}"),
+                format!("CREATE TRIGGER {table}_track_change BEFORE INSERT OR UPDATE ON {table} FOR EACH ROW EXECUTE FUNCTION track_change()"),
+                format!("DROP TRIGGER IF EXISTS {table}_tombstone ON {table}"),
+                format!("CREATE TRIGGER {table}_tombstone AFTER DELETE ON {table} FOR EACH ROW EXECUTE FUNCTION record_tombstone({key})"),
+            ] {
+                sqlx::query(&ddl)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+        for table in ["dip_switch_defaults", "dip_switch_values", "dip_switch_rules"] {
+            for ddl in [
+                format!("DROP TRIGGER IF EXISTS {table}_touch ON {table}"),
+                format!("CREATE TRIGGER {table}_touch AFTER INSERT OR UPDATE OR DELETE ON {table} FOR EACH ROW EXECUTE FUNCTION touch_dip_switch()"),
+            ] {
+                sqlx::query(&ddl)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        // Service interventions: what was done on a machine, with the codes, parts and switches involved
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS interventions (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                serial_number TEXT NOT NULL,
+                model_name TEXT NOT NULL,
+                performed_on DATE NOT NULL,
+                technician TEXT NOT NULL,
+                minutes_spent INTEGER CHECK (minutes_spent >= 0),
+                outcome TEXT NOT NULL CHECK (outcome IN ('resolved', 'partially_resolved', 'unresolved', 'follow_up')),
+                notes TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_interventions_serial ON interventions (serial_number, performed_on)",
+            "CREATE INDEX IF NOT EXISTS idx_interventions_performed_on ON interventions (performed_on)",
+            r#"
+            CREATE TABLE IF NOT EXISTS intervention_error_codes (
+                intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+                error_id UUID REFERENCES error_codes(id) ON DELETE SET NULL,
+                code TEXT NOT NULL,
+                PRIMARY KEY (intervention_id, code)
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS intervention_parts (
+                intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+                part_id UUID REFERENCES spare_parts(id) ON DELETE SET NULL,
+                oem_code TEXT NOT NULL,
+                quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+                PRIMARY KEY (intervention_id, oem_code)
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS intervention_dip_switches (
+                intervention_id UUID NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+                switch_number INTEGER NOT NULL,
+                bit_number INTEGER NOT NULL,
+                previous_value INTEGER,
+                value INTEGER NOT NULL,
+                PRIMARY KEY (intervention_id, switch_number, bit_number)
+            )
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Installed base: the physical machines, each of a catalog model
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS machines (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                serial_number TEXT NOT NULL UNIQUE,
+                printer_id UUID NOT NULL REFERENCES printers(id),
+                customer TEXT,
+                site TEXT,
+                installed_on DATE,
+                firmware TEXT,
+                options JSONB NOT NULL DEFAULT '[]',
+                notes TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_machines_printer ON machines (printer_id)",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Learned part rankings: the catalog value, a hand-set override and the field results per link
+        for ddl in [
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS catalog_ranking INTEGER",
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS manual_ranking INTEGER CHECK (manual_ranking >= 1 AND manual_ranking <= 5)",
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS ranking_source TEXT NOT NULL DEFAULT 'catalog'",
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS fix_attempts INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS fix_successes INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE error_parts ADD COLUMN IF NOT EXISTS success_rate DOUBLE PRECISION",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Technician feedback on solutions and parts
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS error_feedback (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                error_id UUID NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+                part_id UUID REFERENCES spare_parts(id) ON DELETE CASCADE,
+                worked BOOLEAN NOT NULL,
+                technician TEXT NOT NULL,
+                comment TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_error_feedback_error ON error_feedback (error_id, created_at)",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // API keys (hashed) for authentication
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS api_keys (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                name TEXT NOT NULL,
+                key_prefix TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                role TEXT NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                revoked_at TIMESTAMP WITH TIME ZONE
+            )
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Audit trail of data changes
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                user_name TEXT NOT NULL,
+                action TEXT NOT NULL,
+                entity TEXT NOT NULL,
+                entity_id UUID,
+                model_name TEXT,
+                entity_key TEXT NOT NULL,
+                field TEXT,
+                old_value TEXT,
+                new_value TEXT,
+                source_file TEXT
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log (entity, model_name, entity_key, changed_at)",
+            "CREATE INDEX IF NOT EXISTS idx_audit_log_entity_id ON audit_log (entity_id)",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Revisions of error code texts; rows from before revisions were kept get a baseline revision
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS error_code_revisions (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                error_id UUID NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+                revision INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                user_name TEXT,
+                source_file TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                code TEXT NOT NULL,
+                classification TEXT,
+                cause TEXT,
+                measures TEXT,
+                solution TEXT,
+                estimated_abnormal_parts TEXT,
+                correction TEXT,
+                faulty_part_isolation TEXT,
+                note TEXT,
+                UNIQUE (error_id, revision)
+            )
+            "#,
+            r#"
+            INSERT INTO error_code_revisions (error_id, revision, action, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note)
+            SELECT e.id, 1, 'baseline', e.code, e.classification, e.cause, e.measures, e.solution, e.estimated_abnormal_parts, e.correction, e.faulty_part_isolation, e.note
+            FROM error_codes e
+            WHERE NOT EXISTS (SELECT 1 FROM error_code_revisions r WHERE r.error_id = e.id)
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Import batches; rows and revisions carry the batch that last wrote them
+        for ddl in [
+            r#"
+            CREATE TABLE IF NOT EXISTS import_batches (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                source_file TEXT,
+                content_hash TEXT NOT NULL,
+                user_name TEXT NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                total_rows INTEGER NOT NULL DEFAULT 0,
+                created_count INTEGER NOT NULL DEFAULT 0,
+                updated_count INTEGER NOT NULL DEFAULT 0,
+                unchanged_count INTEGER NOT NULL DEFAULT 0,
+                failed_count INTEGER NOT NULL DEFAULT 0,
+                rolled_back_at TIMESTAMP WITH TIME ZONE,
+                rolled_back_by TEXT
+            )
+            "#,
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS import_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL",
+            "ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL",
+            "CREATE INDEX IF NOT EXISTS idx_error_code_revisions_batch ON error_code_revisions (batch_id)",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Saved header mappings for error code imports
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS column_mappings (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                name TEXT NOT NULL UNIQUE,
+                description TEXT,
+                columns JSONB NOT NULL DEFAULT '{}',
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        // Per text field, whether an import or a manual edit wrote it last
+        sqlx::query("ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS field_sources JSONB NOT NULL DEFAULT '{}'")
+            .execute(&self.pool)
+            .await?;
+
+        // Error codes retired by an import that no longer lists them
+        for ddl in [
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS obsolete_at TIMESTAMP WITH TIME ZONE",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS obsolete_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL",
+            "ALTER TABLE import_batches ADD COLUMN IF NOT EXISTS obsolete_count INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS previous_obsolete_at TIMESTAMP WITH TIME ZONE",
+            "ALTER TABLE error_code_revisions ADD COLUMN IF NOT EXISTS previous_obsolete_batch_id UUID REFERENCES import_batches(id) ON DELETE SET NULL",
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Where each error code comes from; codes imported before are traced back to their batch
+        for ddl in [
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_kind TEXT",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_document TEXT",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_sheet TEXT",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_row INTEGER",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS source_page INTEGER",
+            "ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS imported_at TIMESTAMP WITH TIME ZONE",
+            r#"
+            UPDATE error_codes e SET source_kind = 'sheet', source_document = b.source_file, imported_at = b.created_at
+            FROM import_batches b
+            WHERE b.id = e.import_batch_id AND e.source_kind IS NULL
+            "#,
+        ] {
+            sqlx::query(ddl)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Add faulty_part_isolation column if not exists
+        let _ = sqlx::query("ALTER TABLE error_codes ADD COLUMN IF NOT EXISTS faulty_part_isolation TEXT")
+            .execute(&self.pool)
+            .await;
+
+        // CLEANUP: Deduplicate printers (Merge Konica Minolta variants -> Short names)
+        tracing::info!("Running printer deduplication...");
+        let _ = sqlx::query(r#"
+            -- 1. Ensure short entries exist
+            INSERT INTO printers (id, model_name) 
+            VALUES (gen_random_uuid(), 'C4080'), (gen_random_uuid(), 'C4070'), (gen_random_uuid(), 'C4065')
+            ON CONFLICT (model_name) DO NOTHING;
+
+            -- 2. Move errors from long names/variants to short names
+            -- Matches 'Konica Minolta C4080', 'KonicaMinolta C4080', etc.
+            UPDATE error_codes ec
+            SET printer_id = target.id
+            FROM printers source
+            JOIN printers target ON target.model_name = 
+                TRIM(REPLACE(REPLACE(source.model_name, 'Konica Minolta', ''), 'KonicaMinolta', ''))
+            WHERE ec.printer_id = source.id 
+            AND (source.model_name LIKE 'Konica Minolta %' OR source.model_name LIKE 'KonicaMinolta%')
+            AND target.model_name IN ('C4080', 'C4070', 'C4065');
+
+            -- 3. Delete long name entries
+            DELETE FROM printers 
+            WHERE model_name LIKE 'Konica Minolta %' 
+               OR model_name LIKE 'KonicaMinolta%';
+        "#)
+        .execute(&self.pool)
+        .await;
+        tracing::info!("Printer deduplication finished.");
+        Ok(())
+    }
+
+    /// Upserts one switch and replaces its variant defaults. Returns true when the row was newly inserted.
+    async fn upsert_dipswitch(conn: &mut sqlx::PgConnection, model: &str, item: &DipSwitchImport) -> DbResult<bool> {
+        let (switch_id, inserted): (Uuid, bool) = sqlx::query_as(r#"
+            INSERT INTO dip_switches (
+                model_name, switch_number, bit_number, function_name, setting_0, setting_1, default_val, end_bit,
+                risk_level, temporary_only, risk_note
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (model_name, switch_number, bit_number) DO UPDATE SET
+                function_name = EXCLUDED.function_name,
+                setting_0 = EXCLUDED.setting_0,
+                setting_1 = EXCLUDED.setting_1,
+                default_val = EXCLUDED.default_val,
+                end_bit = EXCLUDED.end_bit,
+                risk_level = EXCLUDED.risk_level,
+                temporary_only = EXCLUDED.temporary_only,
+                risk_note = EXCLUDED.risk_note
+            RETURNING id, (xmax = 0) AS inserted
+        "#)
+        .bind(model)
+        .bind(item.switch_number)
+        .bind(item.bit_number)
+        .bind(&item.function_name)
+        .bind(&item.setting_0)
+        .bind(&item.setting_1)
+        .bind(item.primary_default())
+        .bind(item.end_bit.filter(|&e| e > item.bit_number))
+        .bind(item.risk_level.as_deref().unwrap_or("none"))
+        .bind(item.temporary_only)
+        .bind(&item.risk_note)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // A field replaces the single-bit half-entries stored earlier for the bits it now covers
+        if item.last_bit() > item.bit_number {
+            sqlx::query(r#"
+                DELETE FROM dip_switches
+                WHERE model_name = $1 AND switch_number = $2 AND bit_number > $3 AND bit_number <= $4
+            "#)
+            .bind(model)
+            .bind(item.switch_number)
+            .bind(item.bit_number)
+            .bind(item.last_bit())
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_values WHERE dip_switch_id = $1")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+
+        for value in &item.values {
+            sqlx::query("INSERT INTO dip_switch_values (dip_switch_id, value, meaning) VALUES ($1, $2, $3)")
+                .bind(switch_id)
+                .bind(value.value)
+                .bind(&value.meaning)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_defaults WHERE dip_switch_id = $1")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+
+        for (position, default) in item.variant_defaults().iter().enumerate() {
+            sqlx::query(r#"
+                INSERT INTO dip_switch_defaults (dip_switch_id, variant, value, position)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (dip_switch_id, variant) DO UPDATE SET value = EXCLUDED.value
+            "#)
+            .bind(switch_id)
+            .bind(default.variant.trim())
+            .bind(&default.value)
+            .bind(position as i32)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_rules WHERE dip_switch_id = $1")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+
+        for rule in &item.rules {
+            sqlx::query(r#"
+                INSERT INTO dip_switch_rules (dip_switch_id, when_value, kind, target_switch, target_bit, target_value, message)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#)
+            .bind(switch_id)
+            .bind(rule.when_value)
+            .bind(&rule.kind)
+            .bind(rule.target_switch)
+            .bind(rule.target_bit)
+            .bind(rule.target_value)
+            .bind(&rule.message)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(inserted)
+    }
+
+    async fn printer_id(conn: &mut sqlx::PgConnection, model: &str) -> DbResult<Uuid> {
+        let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM printers WHERE model_name = $1")
+            .bind(model)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+        sqlx::query_scalar("INSERT INTO printers (model_name) VALUES ($1) RETURNING id")
+            .bind(model)
+            .fetch_one(&mut *conn)
+            .await
+    }
+
+    /// Upserts import rows with a single statement; the rows must not repeat a code of the same model.
+    /// Rows that leave the stored texts as they are aren't written, so they keep their batch and provenance.
+    async fn upsert_error_codes(
+        conn: &mut sqlx::PgConnection,
+        batch_id: Uuid,
+        printers: &HashMap<&str, Uuid>,
+        rows: &[ErrorCodeImportRow<'_>],
+    ) -> DbResult<()> {
+        let rows: Vec<&ErrorCodeImportRow> = rows.iter().filter(|row| row.revision.is_some()).collect();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(
+            "INSERT INTO error_codes (id, printer_id, code, classification, cause, measures, solution, \
+             estimated_abnormal_parts, correction, faulty_part_isolation, note, field_sources, import_batch_id, \
+             source_kind, source_document, source_sheet, source_row, source_page, imported_at) ",
+        );
+        qb.push_values(rows, |mut b, row| {
+            let fields = &row.fields;
+            b.push_bind(row.id)
+                .push_bind(printers[row.model])
+                .push_bind(fields.code)
+                .push_bind(fields.classification)
+                .push_bind(fields.cause)
+                .push_bind(fields.measures)
+                .push_bind(fields.solution)
+                .push_bind(fields.estimated_abnormal_parts)
+                .push_bind(fields.correction)
+                .push_bind(fields.faulty_part_isolation)
+                .push_bind(fields.note)
+                .push_bind(sqlx::types::Json(&row.field_sources))
+                .push_bind(batch_id)
+                .push_bind(&row.provenance.source_kind)
+                .push_bind(&row.provenance.source_document)
+                .push_bind(&row.provenance.source_sheet)
+                .push_bind(row.provenance.source_row)
+                .push_bind(row.provenance.source_page)
+                .push_bind(row.provenance.imported_at);
+        });
+        qb.push(r#"
+            ON CONFLICT (printer_id, code) DO UPDATE SET
+                classification = EXCLUDED.classification,
+                cause = EXCLUDED.cause,
+                measures = EXCLUDED.measures,
+                solution = EXCLUDED.solution,
+                estimated_abnormal_parts = EXCLUDED.estimated_abnormal_parts,
+                correction = EXCLUDED.correction,
+                faulty_part_isolation = EXCLUDED.faulty_part_isolation,
+                note = EXCLUDED.note,
+                field_sources = EXCLUDED.field_sources,
+                import_batch_id = EXCLUDED.import_batch_id,
+                source_kind = EXCLUDED.source_kind,
+                source_document = EXCLUDED.source_document,
+                source_sheet = EXCLUDED.source_sheet,
+                source_row = EXCLUDED.source_row,
+                source_page = EXCLUDED.source_page,
+                imported_at = EXCLUDED.imported_at,
+                obsolete_at = NULL,
+                obsolete_batch_id = NULL
+            WHERE (
+                error_codes.classification, error_codes.cause, error_codes.measures, error_codes.solution,
+                error_codes.estimated_abnormal_parts, error_codes.correction, error_codes.faulty_part_isolation,
+                error_codes.note, error_codes.obsolete_at
+            ) IS DISTINCT FROM (
+                EXCLUDED.classification, EXCLUDED.cause, EXCLUDED.measures, EXCLUDED.solution,
+                EXCLUDED.estimated_abnormal_parts, EXCLUDED.correction, EXCLUDED.faulty_part_isolation,
+                EXCLUDED.note, NULL::TIMESTAMP WITH TIME ZONE
+            )
+        "#);
+        qb.build().execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Marks error codes obsolete by a batch; codes already obsolete keep their date. Returns how many were marked.
+    async fn mark_obsolete(conn: &mut sqlx::PgConnection, batch: &ImportBatch, ids: &[Uuid]) -> DbResult<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let result = sqlx::query(
+            "UPDATE error_codes SET obsolete_at = $2, obsolete_batch_id = $1 WHERE id = ANY($3) AND obsolete_at IS NULL",
+        )
+        .bind(batch.id)
+        .bind(batch.created_at)
+        .bind(ids)
+        .execute(&mut *conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_revisions(conn: &mut sqlx::PgConnection, revisions: &[&ErrorCodeRevision]) -> DbResult<()> {
+        for chunk in revisions.chunks(IMPORT_CHUNK_ROWS) {
+            let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(r#"
+                INSERT INTO error_code_revisions (
+                    id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note,
+                    previous_obsolete_at, previous_obsolete_batch_id
+                )
+                SELECT v.id, v.error_id, COALESCE((SELECT MAX(r.revision) FROM error_code_revisions r WHERE r.error_id = v.error_id), 0) + 1,
+                    v.action, v.user_name, v.source_file, v.batch_id, v.created_at, v.code, v.classification, v.cause, v.measures, v.solution, v.estimated_abnormal_parts, v.correction, v.faulty_part_isolation, v.note,
+                    v.previous_obsolete_at, v.previous_obsolete_batch_id
+                FROM (
+            "#);
+            qb.push_values(chunk, |mut b, revision| {
+                b.push_bind(revision.id)
+                    .push_bind(revision.error_id)
+                    .push_bind(&revision.action)
+                    .push_bind(&revision.user_name)
+                    .push_bind(&revision.source_file)
+                    .push_bind(revision.batch_id)
+                    .push_bind(revision.created_at)
+                    .push_bind(&revision.code)
+                    .push_bind(&revision.classification)
+                    .push_bind(&revision.cause)
+                    .push_bind(&revision.measures)
+                    .push_bind(&revision.solution)
+                    .push_bind(&revision.estimated_abnormal_parts)
+                    .push_bind(&revision.correction)
+                    .push_bind(&revision.faulty_part_isolation)
+                    .push_bind(&revision.note)
+                    .push_bind(revision.previous_obsolete_at)
+                    .push_bind(revision.previous_obsolete_batch_id);
+            });
+            qb.push(") AS v (id, error_id, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note, previous_obsolete_at, previous_obsolete_batch_id)");
+            qb.build().execute(&mut *conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_audit(conn: &mut sqlx::PgConnection, entries: &[&AuditEntry]) -> DbResult<()> {
+        for chunk in entries.chunks(IMPORT_CHUNK_ROWS) {
+            let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(
+                "INSERT INTO audit_log (id, changed_at, user_name, action, entity, entity_id, model_name, entity_key, field, old_value, new_value, source_file) ",
+            );
+            qb.push_values(chunk, |mut b, entry| {
+                b.push_bind(entry.id)
+                    .push_bind(entry.changed_at)
+                    .push_bind(&entry.user_name)
+                    .push_bind(&entry.action)
+                    .push_bind(&entry.entity)
+                    .push_bind(entry.entity_id)
+                    .push_bind(&entry.model_name)
+                    .push_bind(&entry.entity_key)
+                    .push_bind(&entry.field)
+                    .push_bind(&entry.old_value)
+                    .push_bind(&entry.new_value)
+                    .push_bind(&entry.source_file);
+            });
+            qb.build().execute(&mut *conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_import_batch(conn: &mut sqlx::PgConnection, batch: &ImportBatch) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO import_batches (id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, rolled_back_at, rolled_back_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        "#)
+        .bind(batch.id)
+        .bind(&batch.source_file)
+        .bind(&batch.content_hash)
+        .bind(&batch.user_name)
+        .bind(batch.created_at)
+        .bind(batch.total_rows)
+        .bind(batch.created_count)
+        .bind(batch.updated_count)
+        .bind(batch.unchanged_count)
+        .bind(batch.failed_count)
+        .bind(batch.rolled_back_at)
+        .bind(&batch.rolled_back_by)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_import_batch_counts(conn: &mut sqlx::PgConnection, batch: &ImportBatch) -> DbResult<()> {
+        sqlx::query(r#"
+            UPDATE import_batches SET
+                total_rows = $2,
+                created_count = $3,
+                updated_count = $4,
+                unchanged_count = $5,
+                failed_count = $6,
+                obsolete_count = $7
+            WHERE id = $1
+        "#)
+        .bind(batch.id)
+        .bind(batch.total_rows)
+        .bind(batch.created_count)
+        .bind(batch.updated_count)
+        .bind(batch.unchanged_count)
+        .bind(batch.failed_count)
+        .bind(batch.obsolete_count)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Repository for PgRepository {
+    async fn printers(&self) -> DbResult<Vec<Printer>> {
+        sqlx::query_as::<_, Printer>("SELECT * FROM printers ORDER BY model_name")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_or_create_printer(&self, model: &str) -> DbResult<Uuid> {
+        let mut conn = self.pool.acquire().await?;
+        Self::printer_id(&mut conn, model).await
+    }
+
+    async fn printers_for_models(&self, models: &[String], since: i64) -> DbResult<Vec<Printer>> {
+        sqlx::query_as::<_, Printer>(
+            "SELECT * FROM printers WHERE model_name = ANY($1) AND change_xid >= $2 ORDER BY model_name",
+        )
+        .bind(models)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn search_error_codes(&self, model: &str, code: Option<&CodeQuery>, limit: Option<i32>, include_obsolete: bool) -> DbResult<Vec<ErrorCode>> {
+        let mut sql = String::from(r#"
+            SELECT e.* FROM error_codes e
+            JOIN printers p ON e.printer_id = p.id
+            WHERE p.model_name = $1
+        "#);
+        if !include_obsolete {
+            sql.push_str(" AND e.obsolete_at IS NULL");
+        }
+        match code {
+            // Smart Starts With (Numeric)
+            Some(CodeQuery::Digits(_)) => sql.push_str(" AND regexp_replace(e.code, '[^0-9]', '', 'g') LIKE $2"),
+            // Smart Starts With (Alphanumeric)
+            Some(CodeQuery::Compact(_)) => sql.push_str(" AND REPLACE(REPLACE(e.code, '-', ''), ' ', '') ILIKE $2"),
+            None => {}
+        }
+        if let Some(l) = limit {
+            sql.push_str(&format!(" LIMIT {}", l));
+        }
+
+        let query = sqlx::query_as::<_, ErrorCode>(&sql).bind(model);
+        let query = match code {
+            // Bind: "24%" / "C2%" (Implicitly starts with)
+            Some(CodeQuery::Digits(prefix) | CodeQuery::Compact(prefix)) => query.bind(format!("{}%", prefix)),
+            None => query,
+        };
+        query.fetch_all(&self.pool).await
+    }
+
+    async fn import_error_codes(
+        &self,
+        batch: &mut ImportBatch,
+        rows: &[ErrorCodeImportRow<'_>],
+        obsolete: &[ObsoleteCode],
+        atomic: bool,
+    ) -> DbResult<Vec<ImportFailure>> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_import_batch(&mut tx, batch).await?;
+        let mut printers: HashMap<&str, Uuid> = HashMap::new();
+        for row in rows {
+            if !printers.contains_key(row.model) {
+                printers.insert(row.model, Self::printer_id(&mut tx, row.model).await?);
+            }
+        }
+
+        let mut failures: Vec<ImportFailure> = Vec::new();
+        for (chunk_index, chunk) in rows.chunks(IMPORT_CHUNK_ROWS).enumerate() {
+            let mut savepoint = tx.begin().await?;
+            if Self::upsert_error_codes(&mut savepoint, batch.id, &printers, chunk).await.is_ok() {
+                savepoint.commit().await?;
+                continue;
+            }
+            savepoint.rollback().await?;
+            // Write the rows of the failed statement one by one to find the ones refused
+            for (i, row) in chunk.iter().enumerate() {
+                let mut savepoint = tx.begin().await?;
+                match Self::upsert_error_codes(&mut savepoint, batch.id, &printers, std::slice::from_ref(row)).await {
+                    Ok(()) => savepoint.commit().await?,
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        failures.push(ImportFailure { row: chunk_index * IMPORT_CHUNK_ROWS + i, message: e.to_string() });
+                    }
+                }
+            }
+            if atomic && !failures.is_empty() {
+                break;
+            }
+        }
+        if atomic && !failures.is_empty() {
+            tx.rollback().await?;
+            batch.failed_count += failures.len() as i32;
+            return Ok(failures);
+        }
+
+        let written: Vec<&ErrorCodeImportRow> = rows
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !failures.iter().any(|f| f.row == *i))
+            .map(|(_, row)| row)
+            .collect();
+        let revisions: Vec<&ErrorCodeRevision> = written.iter().filter_map(|row| row.revision.as_ref()).collect();
+        let entries: Vec<&AuditEntry> = written.iter().flat_map(|row| &row.audit).chain(obsolete.iter().flat_map(|o| &o.audit)).collect();
+        let obsolete_ids: Vec<Uuid> = obsolete.iter().map(|o| o.id).collect();
+        batch.obsolete_count = Self::mark_obsolete(&mut tx, batch, &obsolete_ids).await? as i32;
+        Self::insert_revisions(&mut tx, &revisions).await?;
+        Self::insert_audit(&mut tx, &entries).await?;
+        tally_import(batch, rows, &failures);
+        Self::update_import_batch_counts(&mut tx, batch).await?;
+        tx.commit().await?;
+        Ok(failures)
+    }
+
+    async fn delete_error_code(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM error_codes WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_error_code(
+        &self,
+        id: Uuid,
+        fields: &ErrorCodeFields<'_>,
+        field_sources: &BTreeMap<String, String>,
+        source_kind: Option<&str>,
+    ) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE error_codes SET
+                classification = $2,
+                cause = $3,
+                measures = $4,
+                solution = $5,
+                estimated_abnormal_parts = $6,
+                correction = $7,
+                faulty_part_isolation = $8,
+                note = $9,
+                field_sources = $10,
+                source_kind = COALESCE($11, source_kind)
+            WHERE id = $1
+        "#)
+        .bind(id)
+        .bind(fields.classification)
+        .bind(fields.cause)
+        .bind(fields.measures)
+        .bind(fields.solution)
+        .bind(fields.estimated_abnormal_parts)
+        .bind(fields.correction)
+        .bind(fields.faulty_part_isolation)
+        .bind(fields.note)
+        .bind(sqlx::types::Json(field_sources))
+        .bind(source_kind)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn error_code(&self, id: Uuid) -> DbResult<Option<ModelErrorCode>> {
+        sqlx::query_as::<_, ModelErrorCode>(
+            "SELECT p.model_name, e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE e.id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn export_error_codes(&self, models: &[String]) -> DbResult<Vec<ModelErrorCode>> {
+        sqlx::query_as::<_, ModelErrorCode>(r#"
+            SELECT p.model_name, e.* FROM error_codes e
+            JOIN printers p ON e.printer_id = p.id
+            WHERE p.model_name = ANY($1) AND e.obsolete_at IS NULL
+            ORDER BY p.model_name ASC, e.code ASC
+        "#)
+        .bind(models)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn changed_error_codes(&self, models: &[String], since: i64) -> DbResult<Vec<ErrorCode>> {
+        sqlx::query_as::<_, ErrorCode>(r#"
+            SELECT e.* FROM error_codes e
+            JOIN printers p ON p.id = e.printer_id
+            WHERE p.model_name = ANY($1)
+            AND (
+                e.change_xid >= $2
+                OR EXISTS (
+                    SELECT 1 FROM error_parts ep JOIN spare_parts sp ON sp.id = ep.part_id
+                    WHERE ep.error_id = e.id AND (ep.change_xid >= $2 OR sp.change_xid >= $2)
+                )
+                OR EXISTS (
+                    SELECT 1 FROM tombstones t
+                    WHERE t.entity = 'error_parts' AND t.change_xid >= $2 AND t.entity_key->>'error_id' = e.id::text
+                )
+            )
+            ORDER BY e.printer_id ASC, e.code ASC
+        "#)
+        .bind(models)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn error_part_links(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorPartLink>> {
+        sqlx::query_as::<_, ErrorPartLink>(r#"
+            SELECT ep.error_id, sp.id, sp.oem_code, sp.description, sp.image_url, COALESCE(ep.ranking, 5) AS ranking,
+                   ep.ranking_source, ep.fix_attempts, ep.fix_successes, ep.success_rate
+            FROM error_parts ep
+            JOIN spare_parts sp ON sp.id = ep.part_id
+            WHERE ep.error_id = ANY($1)
+            ORDER BY ep.error_id ASC, ep.ranking DESC, sp.oem_code ASC
+        "#)
+        .bind(error_ids)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn spare_parts(&self, ids: &[Uuid]) -> DbResult<Vec<SparePart>> {
+        sqlx::query_as::<_, SparePart>(
+            "SELECT id, oem_code, description, image_url, COALESCE(ranking, 3) AS ranking FROM spare_parts WHERE id = ANY($1) ORDER BY oem_code ASC",
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Take it *before* reading, so changes that commit meanwhile are sent again next time rather than lost.
+    async fn sync_token(&self) -> DbResult<i64> {
+        sqlx::query_scalar("SELECT pg_snapshot_xmin(pg_current_snapshot())::text::bigint")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn tombstones(&self, since: i64) -> DbResult<Vec<Tombstone>> {
+        sqlx::query_as::<_, Tombstone>(
+            "SELECT entity, entity_key, deleted_at FROM tombstones WHERE change_xid >= $1 ORDER BY id ASC",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn dipswitches(&self, filter: &DipSwitchFilter) -> DbResult<Vec<DipSwitch>> {
+        let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new("SELECT * FROM dip_switches WHERE change_xid >= ");
+        qb.push_bind(filter.since);
+        if !filter.models.is_empty() {
+            qb.push(" AND model_name = ANY(").push_bind(&filter.models).push(")");
+        }
+        if let Some(sw) = filter.switch {
+            qb.push(" AND switch_number = ").push_bind(sw);
+        }
+        if let Some(bit) = filter.bit {
+            qb.push(" AND bit_number <= ").push_bind(bit);
+            qb.push(" AND COALESCE(end_bit, bit_number) >= ").push_bind(bit);
+        }
+        qb.push(" ORDER BY model_name ASC, switch_number ASC, bit_number ASC");
+        qb.build_query_as::<DipSwitch>().fetch_all(&self.pool).await
+    }
+
+    async fn dipswitch_defaults(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchDefault>>> {
+        let rows: Vec<(Uuid, String, Option<String>)> = sqlx::query_as(r#"
+            SELECT dip_switch_id, variant, value FROM dip_switch_defaults
+            WHERE dip_switch_id = ANY($1)
+            ORDER BY position ASC
+        "#)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchDefault>> = HashMap::new();
+        for (id, variant, value) in rows {
+            by_switch.entry(id).or_default().push(DipSwitchDefault { variant, value });
+        }
+        Ok(by_switch)
+    }
+
+    async fn dipswitch_values(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchValue>>> {
+        let rows: Vec<(Uuid, i32, Option<String>)> = sqlx::query_as(r#"
+            SELECT dip_switch_id, value, meaning FROM dip_switch_values
+            WHERE dip_switch_id = ANY($1)
+            ORDER BY value ASC
+        "#)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchValue>> = HashMap::new();
+        for (id, value, meaning) in rows {
+            by_switch.entry(id).or_default().push(DipSwitchValue { value, meaning, bits: String::new() });
+        }
+        Ok(by_switch)
+    }
+
+    async fn dipswitch_rules(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchRule>>> {
+        let rows: Vec<DipSwitchRuleRow> = sqlx::query_as(r#"
+            SELECT dip_switch_id, when_value, kind, target_switch, target_bit, target_value, message
+            FROM dip_switch_rules
+            WHERE dip_switch_id = ANY($1)
+            ORDER BY target_switch ASC, target_bit ASC
+        "#)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchRule>> = HashMap::new();
+        for row in rows {
+            by_switch.entry(row.dip_switch_id).or_default().push(row.rule);
+        }
+        Ok(by_switch)
+    }
+
+    async fn write_dipswitches(
+        &self,
+        model: &str,
+        items: &[&DipSwitchImport],
+        keep: Option<&[(i32, i32)]>,
+    ) -> DbResult<DipSwitchWrite> {
+        let mut write = DipSwitchWrite::default();
+        let mut tx = self.pool.begin().await?;
+
+        for item in items {
+            let mut savepoint = tx.begin().await?;
+            match Self::upsert_dipswitch(&mut savepoint, model, item).await {
+                Ok(inserted) => {
+                    savepoint.commit().await?;
+                    write.outcomes.push(Ok(inserted));
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    tracing::error!("DIP switch import failed for {} SW {}-{}: {:?}", model, item.switch_number, item.bit_number, e);
+                    write.outcomes.push(Err(e.to_string()));
+                }
+            }
+        }
+
+        if let Some(keep) = keep {
+            let (switches, bits): (Vec<i32>, Vec<i32>) = keep.iter().copied().unzip();
+            let removed = sqlx::query(r#"
+                DELETE FROM dip_switches d
+                WHERE d.model_name = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM UNNEST($2::int[], $3::int[]) AS k(sw, bit)
+                    WHERE k.sw = d.switch_number AND k.bit = d.bit_number
+                )
+            "#)
+            .bind(model)
+            .bind(&switches)
+            .bind(&bits)
+            .execute(&mut *tx)
+            .await?;
+            write.removed = removed.rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(write)
+    }
+
+    async fn search_dipswitches(&self, words: &[String], model: Option<&str>, limit: i64) -> DbResult<Vec<DipSwitchSearchHit>> {
+        let Some(tsquery) = to_prefix_tsquery(words) else {
+            return Ok(Vec::new());
+        };
+        // Function text weighs more than the settings; value-table meanings count as settings. The
+        // excerpts mark the matches with control characters (removed from the text first), made HTML below
+        let mut hits = sqlx::query_as::<_, DipSwitchSearchHit>(r#"
+            WITH docs AS (
+                SELECT d.*,
+                       concat_ws(' ', d.setting_0, d.setting_1, v.meanings) AS settings_text,
+                       setweight(to_tsvector('english', coalesce(d.function_name, '')), 'A') ||
+                       setweight(to_tsvector('english', concat_ws(' ', d.setting_0, d.setting_1, v.meanings)), 'B') AS doc
+                FROM dip_switches d
+                LEFT JOIN LATERAL (
+                    SELECT string_agg(meaning, ' ' ORDER BY value) AS meanings
+                    FROM dip_switch_values WHERE dip_switch_id = d.id
+                ) v ON TRUE
+                WHERE ($2::text IS NULL OR d.model_name = $2)
+            )
+            SELECT docs.id, docs.model_name, docs.switch_number, docs.bit_number, docs.function_name,
+                   docs.setting_0, docs.setting_1, docs.default_val, docs.end_bit,
+                   docs.risk_level, docs.temporary_only, docs.risk_note,
+                   ts_rank(docs.doc, q) AS rank,
+                   ts_headline('english', translate(coalesce(docs.function_name, ''), $4 || $5, ''), q,
+                       'StartSel=' || $4 || ', StopSel=' || $5 || ', MaxWords=35, MinWords=15') AS function_highlight,
+                   ts_headline('english', translate(docs.settings_text, $4 || $5, ''), q,
+                       'StartSel=' || $4 || ', StopSel=' || $5 || ', MaxWords=35, MinWords=15') AS settings_highlight
+            FROM docs, to_tsquery('english', $1) q
+            WHERE docs.doc @@ q
+            ORDER BY rank DESC, docs.model_name ASC, docs.switch_number ASC, docs.bit_number ASC
+            LIMIT $3
+        "#)
+        .bind(&tsquery)
+        .bind(model)
+        .bind(limit)
+        .bind(MARK_START.to_string())
+        .bind(MARK_END.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+        for hit in &mut hits {
+            hit.function_highlight = hit.function_highlight.as_deref().map(marked_html);
+            hit.settings_highlight = hit.settings_highlight.as_deref().map(marked_html);
+        }
+        Ok(hits)
+    }
+
+    async fn dipswitch_profiles(&self, model: Option<&str>) -> DbResult<Vec<DipSwitchProfile>> {
+        sqlx::query_as::<_, DipSwitchProfile>(r#"
+            SELECT id, model_name, name, description, variant, settings FROM dip_switch_profiles
+            WHERE ($1::text IS NULL OR model_name = $1)
+            ORDER BY model_name ASC, name ASC
+        "#)
+        .bind(model)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn dipswitch_profile(&self, id: Uuid) -> DbResult<Option<DipSwitchProfile>> {
+        sqlx::query_as::<_, DipSwitchProfile>(
+            "SELECT id, model_name, name, description, variant, settings FROM dip_switch_profiles WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn save_dipswitch_profile(
+        &self,
+        model: &str,
+        name: &str,
+        description: Option<&str>,
+        variant: Option<&str>,
+        settings: &[DipSwitchSetting],
+    ) -> DbResult<DipSwitchProfile> {
+        sqlx::query_as::<_, DipSwitchProfile>(r#"
+            INSERT INTO dip_switch_profiles (model_name, name, description, variant, settings)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (model_name, name) DO UPDATE SET
+                description = EXCLUDED.description,
+                variant = EXCLUDED.variant,
+                settings = EXCLUDED.settings
+            RETURNING id, model_name, name, description, variant, settings
+        "#)
+        .bind(model)
+        .bind(name)
+        .bind(description)
+        .bind(variant)
+        .bind(sqlx::types::Json(settings))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn error_code_ids(&self, model: &str, codes: &[String]) -> DbResult<HashMap<String, Uuid>> {
+        let rows: Vec<(String, Uuid)> = sqlx::query_as(r#"
+            SELECT e.code, e.id FROM error_codes e
+            JOIN printers p ON p.id = e.printer_id
+            WHERE p.model_name = $1 AND e.code = ANY($2)
+        "#)
+        .bind(model)
+        .bind(codes)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    async fn spare_parts_by_oem(&self, oem_codes: &[String]) -> DbResult<Vec<SparePart>> {
+        sqlx::query_as::<_, SparePart>(
+            "SELECT id, oem_code, description, image_url, COALESCE(ranking, 3) AS ranking FROM spare_parts WHERE oem_code = ANY($1) ORDER BY oem_code ASC",
+        )
+        .bind(oem_codes)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn save_intervention(&self, intervention: &Intervention) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(r#"
+            INSERT INTO interventions (id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#)
+        .bind(intervention.id)
+        .bind(&intervention.serial_number)
+        .bind(&intervention.model_name)
+        .bind(intervention.performed_on)
+        .bind(&intervention.technician)
+        .bind(intervention.minutes_spent)
+        .bind(&intervention.outcome)
+        .bind(&intervention.notes)
+        .execute(&mut *tx)
+        .await?;
+
+        for error in &intervention.error_codes {
+            sqlx::query("INSERT INTO intervention_error_codes (intervention_id, error_id, code) VALUES ($1, $2, $3)")
+                .bind(intervention.id)
+                .bind(error.error_id)
+                .bind(&error.code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for part in &intervention.parts {
+            sqlx::query("INSERT INTO intervention_parts (intervention_id, part_id, oem_code, quantity) VALUES ($1, $2, $3, $4)")
+                .bind(intervention.id)
+                .bind(part.part_id)
+                .bind(&part.oem_code)
+                .bind(part.quantity)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for switch in &intervention.dip_switches {
+            sqlx::query(r#"
+                INSERT INTO intervention_dip_switches (intervention_id, switch_number, bit_number, previous_value, value)
+                VALUES ($1, $2, $3, $4, $5)
+            "#)
+            .bind(intervention.id)
+            .bind(switch.switch_number)
+            .bind(switch.bit_number)
+            .bind(switch.previous_value)
+            .bind(switch.value)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn interventions(&self, filter: &InterventionFilter) -> DbResult<Vec<Intervention>> {
+        let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(
+            "SELECT id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes FROM interventions i WHERE 1=1",
+        );
+        if let Some(serial) = &filter.serial_number {
+            qb.push(" AND serial_number = ").push_bind(serial);
+        }
+        if let Some(model) = &filter.model {
+            qb.push(" AND model_name = ").push_bind(model);
+        }
+        if let Some(technician) = &filter.technician {
+            qb.push(" AND lower(technician) = lower(").push_bind(technician).push(")");
+        }
+        if let Some(code) = &filter.code {
+            qb.push(" AND EXISTS (SELECT 1 FROM intervention_error_codes x WHERE x.intervention_id = i.id AND x.code = ")
+                .push_bind(code)
+                .push(")");
+        }
+        if let Some(outcome) = &filter.outcome {
+            qb.push(" AND outcome = ").push_bind(outcome);
+        }
+        if let Some(from) = filter.from {
+            qb.push(" AND performed_on >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            qb.push(" AND performed_on <= ").push_bind(to);
+        }
+        qb.push(" ORDER BY performed_on DESC, created_at DESC LIMIT ").push_bind(filter.limit);
+        qb.build_query_as::<Intervention>().fetch_all(&self.pool).await
+    }
+
+    async fn intervention(&self, id: Uuid) -> DbResult<Option<Intervention>> {
+        sqlx::query_as::<_, Intervention>(
+            "SELECT id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes FROM interventions WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn intervention_details(&self, ids: &[Uuid]) -> DbResult<InterventionDetails> {
+        let mut details = InterventionDetails::default();
+
+        let rows: Vec<(Uuid, Option<Uuid>, String)> = sqlx::query_as(
+            "SELECT intervention_id, error_id, code FROM intervention_error_codes WHERE intervention_id = ANY($1) ORDER BY code ASC",
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for (id, error_id, code) in rows {
+            details.error_codes.entry(id).or_default().push(InterventionErrorCode { error_id, code });
+        }
+
+        let rows: Vec<(Uuid, Option<Uuid>, String, i32)> = sqlx::query_as(
+            "SELECT intervention_id, part_id, oem_code, quantity FROM intervention_parts WHERE intervention_id = ANY($1) ORDER BY oem_code ASC",
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for (id, part_id, oem_code, quantity) in rows {
+            details.parts.entry(id).or_default().push(InterventionPart { part_id, oem_code, quantity });
+        }
+
+        let rows: Vec<(Uuid, i32, i32, Option<i32>, i32)> = sqlx::query_as(r#"
+            SELECT intervention_id, switch_number, bit_number, previous_value, value FROM intervention_dip_switches
+            WHERE intervention_id = ANY($1)
+            ORDER BY switch_number ASC, bit_number ASC
+        "#)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for (id, switch_number, bit_number, previous_value, value) in rows {
+            details
+                .dip_switches
+                .entry(id)
+                .or_default()
+                .push(InterventionDipSwitch { switch_number, bit_number, previous_value, value });
+        }
+
+        Ok(details)
+    }
+
+    async fn machines(&self, filter: &MachineFilter) -> DbResult<Vec<Machine>> {
+        let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(
+            "SELECT m.id, m.serial_number, m.printer_id, p.model_name, m.customer, m.site, m.installed_on, m.firmware, m.options, m.notes FROM machines m JOIN printers p ON p.id = m.printer_id WHERE 1=1",
+        );
+        if let Some(query) = &filter.query {
+            let pattern = format!("%{}%", query);
+            qb.push(" AND (m.serial_number ILIKE ").push_bind(pattern.clone());
+            qb.push(" OR m.customer ILIKE ").push_bind(pattern.clone());
+            qb.push(" OR m.site ILIKE ").push_bind(pattern).push(")");
+        }
+        if let Some(model) = &filter.model {
+            qb.push(" AND p.model_name = ").push_bind(model);
+        }
+        qb.push(" ORDER BY m.serial_number ASC LIMIT ").push_bind(filter.limit);
+        qb.build_query_as::<Machine>().fetch_all(&self.pool).await
+    }
+
+    async fn machine(&self, id: Uuid) -> DbResult<Option<Machine>> {
+        sqlx::query_as::<_, Machine>(
+            "SELECT m.id, m.serial_number, m.printer_id, p.model_name, m.customer, m.site, m.installed_on, m.firmware, m.options, m.notes FROM machines m JOIN printers p ON p.id = m.printer_id WHERE m.id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn insert_machine(&self, machine: &Machine) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO machines (id, serial_number, printer_id, customer, site, installed_on, firmware, options, notes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#)
+        .bind(machine.id)
+        .bind(&machine.serial_number)
+        .bind(machine.printer_id)
+        .bind(&machine.customer)
+        .bind(&machine.site)
+        .bind(machine.installed_on)
+        .bind(&machine.firmware)
+        .bind(&machine.options)
+        .bind(&machine.notes)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_machine(&self, machine: &Machine) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE machines SET
+                serial_number = $1, printer_id = $2, customer = $3, site = $4, installed_on = $5,
+                firmware = $6, options = $7, notes = $8, updated_at = NOW()
+            WHERE id = $9
+        "#)
+        .bind(&machine.serial_number)
+        .bind(machine.printer_id)
+        .bind(&machine.customer)
+        .bind(&machine.site)
+        .bind(machine.installed_on)
+        .bind(&machine.firmware)
+        .bind(&machine.options)
+        .bind(&machine.notes)
+        .bind(machine.id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_machine(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM machines WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn ranking_inputs(&self, model: Option<&str>, link: Option<(Uuid, Uuid)>) -> DbResult<Vec<RankingInput>> {
+        sqlx::query_as::<_, RankingInput>(r#"
+            SELECT ep.error_id, ep.part_id,
+                   COALESCE(ep.catalog_ranking, ep.ranking, 5) AS catalog_ranking,
+                   ep.manual_ranking,
+                   COALESCE(SUM(CASE WHEN i.outcome IN ('resolved', 'partially_resolved', 'unresolved') THEN 1 ELSE 0 END), 0) AS attempts,
+                   COALESCE(SUM(CASE WHEN i.outcome = 'resolved' THEN 1 ELSE 0 END), 0) AS successes
+            FROM error_parts ep
+            JOIN error_codes e ON e.id = ep.error_id
+            JOIN printers p ON p.id = e.printer_id
+            LEFT JOIN intervention_error_codes iec ON iec.error_id = ep.error_id
+            LEFT JOIN intervention_parts ip ON ip.intervention_id = iec.intervention_id AND ip.part_id = ep.part_id
+            LEFT JOIN interventions i ON i.id = ip.intervention_id
+            WHERE ($1::text IS NULL OR p.model_name = $1)
+            AND ($2::uuid IS NULL OR (ep.error_id = $2 AND ep.part_id = $3))
+            GROUP BY ep.error_id, ep.part_id, ep.catalog_ranking, ep.ranking, ep.manual_ranking
+        "#)
+        .bind(model)
+        .bind(link.map(|(error_id, _)| error_id))
+        .bind(link.map(|(_, part_id)| part_id))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn apply_rankings(&self, updates: &[RankingUpdate]) -> DbResult<u64> {
+        let mut changed = 0;
+        let mut tx = self.pool.begin().await?;
+        for update in updates {
+            // Unchanged links are left alone, so a recalculation does not show up in delta sync
+            changed += sqlx::query(r#"
+                UPDATE error_parts SET
+                    ranking = $3, catalog_ranking = $4, ranking_source = $5,
+                    fix_attempts = $6, fix_successes = $7, success_rate = $8
+                WHERE error_id = $1 AND part_id = $2
+                AND (ranking IS DISTINCT FROM $3 OR catalog_ranking IS DISTINCT FROM $4 OR ranking_source IS DISTINCT FROM $5
+                     OR fix_attempts IS DISTINCT FROM $6 OR fix_successes IS DISTINCT FROM $7 OR success_rate IS DISTINCT FROM $8)
+            "#)
+            .bind(update.error_id)
+            .bind(update.part_id)
+            .bind(update.ranking)
+            .bind(update.catalog_ranking)
+            .bind(update.source)
+            .bind(update.attempts)
+            .bind(update.successes)
+            .bind(update.success_rate)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+
+    async fn set_manual_ranking(&self, error_id: Uuid, part_id: Uuid, ranking: Option<i32>) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE error_parts SET manual_ranking = $3, catalog_ranking = COALESCE(catalog_ranking, ranking)
+            WHERE error_id = $1 AND part_id = $2
+        "#)
+        .bind(error_id)
+        .bind(part_id)
+        .bind(ranking)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn save_feedback(&self, feedback: &ErrorFeedback) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO error_feedback (id, error_id, part_id, worked, technician, comment, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#)
+        .bind(feedback.id)
+        .bind(feedback.error_id)
+        .bind(feedback.part_id)
+        .bind(feedback.worked)
+        .bind(&feedback.technician)
+        .bind(&feedback.comment)
+        .bind(feedback.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn feedback(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorFeedback>> {
+        sqlx::query_as::<_, ErrorFeedback>(
+            "SELECT id, error_id, part_id, worked, technician, comment, created_at FROM error_feedback WHERE error_id = ANY($1) ORDER BY created_at DESC",
+        )
+        .bind(error_ids)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(
+            "SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn api_key(&self, id: Uuid) -> DbResult<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>("SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys WHERE id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn api_keys(&self) -> DbResult<Vec<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>("SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO api_keys (id, name, key_prefix, key_hash, role, created_at, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#)
+        .bind(key.id)
+        .bind(&key.name)
+        .bind(&key.key_prefix)
+        .bind(&key.key_hash)
+        .bind(&key.role)
+        .bind(key.created_at)
+        .bind(key.revoked_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn revoke_api_key(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_audit(&self, entries: &[AuditEntry]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_audit(&mut tx, &entries.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter) -> DbResult<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>(r#"
+            SELECT id, changed_at, user_name, action, entity, entity_id, model_name, entity_key, field, old_value, new_value, source_file
+            FROM audit_log
+            WHERE ($1::text IS NULL OR model_name = $1)
+            AND ($2::text IS NULL OR entity = $2)
+            AND ($3::text IS NULL OR entity_key = $3)
+            AND ($4::uuid IS NULL OR entity_id = $4)
+            AND ($5::text IS NULL OR user_name = $5)
+            ORDER BY changed_at DESC, entity_key, field
+            LIMIT $6
+        "#)
+        .bind(&filter.model)
+        .bind(&filter.entity)
+        .bind(&filter.entity_key)
+        .bind(filter.entity_id)
+        .bind(&filter.user)
+        .bind(filter.limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn add_error_code_revisions(&self, revisions: &[ErrorCodeRevision]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_revisions(&mut tx, &revisions.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await
+    }
+
+    async fn error_code_revisions(&self, error_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(
+            "SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note FROM error_code_revisions WHERE error_id = $1 ORDER BY revision DESC",
+        )
+        .bind(error_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn error_code_revisions_at(
+        &self,
+        model: &str,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(r#"
+            SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note FROM (
+                SELECT r.*, ROW_NUMBER() OVER (PARTITION BY r.error_id ORDER BY r.revision DESC) AS rn
+                FROM error_code_revisions r
+                JOIN error_codes e ON e.id = r.error_id
+                JOIN printers p ON p.id = e.printer_id
+                WHERE p.model_name = $1 AND r.created_at <= $2
+            ) latest
+            WHERE rn = 1
+            ORDER BY code
+        "#)
+        .bind(model)
+        .bind(at)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn import_batches(&self, limit: i64) -> DbResult<Vec<ImportBatch>> {
+        sqlx::query_as::<_, ImportBatch>("SELECT id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, obsolete_count, rolled_back_at, rolled_back_by FROM import_batches ORDER BY created_at DESC LIMIT $1")
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn import_batch(&self, id: Uuid) -> DbResult<Option<ImportBatch>> {
+        sqlx::query_as::<_, ImportBatch>("SELECT id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, obsolete_count, rolled_back_at, rolled_back_by FROM import_batches WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn batch_revisions(&self, batch_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(
+            "SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note, previous_obsolete_at, previous_obsolete_batch_id FROM error_code_revisions WHERE batch_id = $1 ORDER BY error_id, revision",
+        )
+        .bind(batch_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn column_mappings(&self) -> DbResult<Vec<ColumnMapping>> {
+        sqlx::query_as::<_, ColumnMapping>("SELECT id, name, description, columns FROM column_mappings ORDER BY name")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn save_column_mapping(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        columns: &BTreeMap<String, Vec<String>>,
+    ) -> DbResult<ColumnMapping> {
+        sqlx::query_as::<_, ColumnMapping>(r#"
+            INSERT INTO column_mappings (name, description, columns)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET
+                description = EXCLUDED.description,
+                columns = EXCLUDED.columns
+            RETURNING id, name, description, columns
+        "#)
+        .bind(name)
+        .bind(description)
+        .bind(sqlx::types::Json(columns))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn delete_column_mapping(&self, name: &str) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM column_mappings WHERE name = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn batch_obsolete_codes(&self, batch_id: Uuid) -> DbResult<Vec<ModelErrorCode>> {
+        sqlx::query_as::<_, ModelErrorCode>(
+            "SELECT p.model_name, e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE e.obsolete_batch_id = $1 ORDER BY p.model_name, e.code",
+        )
+        .bind(batch_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn rollback_import_batch(&self, rollback: &BatchRollback) -> DbResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let claimed = sqlx::query(
+            "UPDATE import_batches SET rolled_back_at = NOW(), rolled_back_by = $2 WHERE id = $1 AND rolled_back_at IS NULL",
+        )
+        .bind(rollback.batch_id)
+        .bind(&rollback.user)
+        .execute(&mut *tx)
+        .await?;
+        if claimed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM error_codes WHERE id = ANY($1)")
+            .bind(&rollback.deletes)
+            .execute(&mut *tx)
+            .await?;
+        for restore in &rollback.restores {
+            let revision = &restore.revision;
+            sqlx::query(r#"
+                UPDATE error_codes SET
+                    classification = $2,
+                    cause = $3,
+                    measures = $4,
+                    solution = $5,
+                    estimated_abnormal_parts = $6,
+                    correction = $7,
+                    faulty_part_isolation = $8,
+                    note = $9,
+                    field_sources = $10
+                WHERE id = $1
+            "#)
+            .bind(revision.error_id)
+            .bind(&revision.classification)
+            .bind(&revision.cause)
+            .bind(&revision.measures)
+            .bind(&revision.solution)
+            .bind(&revision.estimated_abnormal_parts)
+            .bind(&revision.correction)
+            .bind(&revision.faulty_part_isolation)
+            .bind(&revision.note)
+            .bind(sqlx::types::Json(&restore.field_sources))
+            .execute(&mut *tx)
+            .await?;
+            if restore.obsolete_at.is_some() {
+                sqlx::query("UPDATE error_codes SET obsolete_at = $2, obsolete_batch_id = $3 WHERE id = $1 AND obsolete_at IS NULL")
+                    .bind(revision.error_id)
+                    .bind(restore.obsolete_at)
+                    .bind(restore.obsolete_batch_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        Self::insert_revisions(&mut tx, &rollback.restores.iter().map(|r| &r.revision).collect::<Vec<_>>()).await?;
+        sqlx::query("UPDATE error_codes SET obsolete_at = NULL, obsolete_batch_id = NULL WHERE id = ANY($1) AND obsolete_batch_id = $2")
+            .bind(&rollback.revives)
+            .bind(rollback.batch_id)
+            .execute(&mut *tx)
+            .await?;
+        Self::insert_audit(&mut tx, &rollback.audit.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,2012 @@
+use super::{
+    push_escaped, tally_import, AuditFilter, BatchRollback, CodeQuery, DbResult, DipSwitchFilter, DipSwitchWrite, ErrorCodeFields, ErrorCodeImportRow,
+    ErrorPartLink, ImportFailure, InterventionDetails, ObsoleteCode, InterventionFilter, MachineFilter, ModelErrorCode, Repository,
+    IMPORT_CHUNK_ROWS,
+};
+use crate::models::{
+    ApiKey, AuditEntry, ColumnMapping, DipSwitch, DipSwitchDefault, DipSwitchImport, DipSwitchProfile, DipSwitchRule, DipSwitchSearchHit,
+    DipSwitchSetting, DipSwitchValue, ErrorCode, ErrorCodeRevision, ErrorFeedback, ImportBatch, Intervention, InterventionDipSwitch, InterventionErrorCode,
+    InterventionPart, Machine, Printer, SparePart, Tombstone,
+};
+use crate::ranking::{RankingInput, RankingUpdate};
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, QueryBuilder, Sqlite};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Local single-file storage for standalone installs. UUIDs are stored as 16-byte blobs and generated
+/// here rather than by the database; sync tokens come from a counter bumped by triggers.
+pub struct SqliteRepository {
+    pool: sqlx::SqlitePool,
+}
+
+/// Timestamp in the RFC 3339 form the Postgres columns decode to.
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+/// The columns whose changes bump `change_xid`, per tracked table, and the key stored in its tombstones.
+const TRACKED_TABLES: [(&str, &[&str], &[&str]); 5] = [
+    ("printers", &["model_name"], &["id"]),
+    (
+        "error_codes",
+        &[
+            "printer_id", "code", "classification", "cause", "measures", "solution", "estimated_abnormal_parts",
+            "correction", "faulty_part_isolation", "note", "field_sources", "obsolete_at", "obsolete_batch_id",
+            "import_batch_id", "source_kind", "source_document", "source_sheet", "source_row", "source_page",
+            "imported_at",
+        ],
+        &["id"],
+    ),
+    ("spare_parts", &["oem_code", "description", "image_url", "ranking"], &["id"]),
+    (
+        "error_parts",
+        &["ranking", "ranking_source", "fix_attempts", "fix_successes", "success_rate"],
+        &["error_id", "part_id"],
+    ),
+    (
+        "dip_switches",
+        &[
+            "model_name", "switch_number", "bit_number", "function_name", "setting_0", "setting_1", "default_val",
+            "end_bit", "risk_level", "temporary_only", "risk_note",
+        ],
+        &["id"],
+    ),
+];
+
+const SCHEMA: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS printers (
+        id BLOB PRIMARY KEY,
+        model_name TEXT NOT NULL UNIQUE,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TEXT,
+        change_xid INTEGER NOT NULL DEFAULT 0
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS spare_parts (
+        id BLOB PRIMARY KEY,
+        oem_code TEXT NOT NULL UNIQUE,
+        description TEXT NOT NULL,
+        image_url TEXT,
+        ranking INTEGER DEFAULT 3 CHECK (ranking >= 1 AND ranking <= 5),
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TEXT,
+        change_xid INTEGER NOT NULL DEFAULT 0
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS error_codes (
+        id BLOB PRIMARY KEY,
+        printer_id BLOB NOT NULL REFERENCES printers(id) ON DELETE CASCADE,
+        code TEXT NOT NULL,
+        classification TEXT,
+        cause TEXT,
+        measures TEXT,
+        solution TEXT,
+        estimated_abnormal_parts TEXT,
+        correction TEXT,
+        faulty_part_isolation TEXT,
+        note TEXT,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TEXT,
+        change_xid INTEGER NOT NULL DEFAULT 0,
+        UNIQUE (printer_id, code)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS error_parts (
+        error_id BLOB NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+        part_id BLOB NOT NULL REFERENCES spare_parts(id) ON DELETE CASCADE,
+        ranking INTEGER DEFAULT 5 CHECK (ranking >= 1 AND ranking <= 5),
+        updated_at TEXT,
+        change_xid INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (error_id, part_id)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS dip_switches (
+        id BLOB PRIMARY KEY,
+        model_name TEXT NOT NULL,
+        switch_number INTEGER NOT NULL,
+        bit_number INTEGER NOT NULL,
+        function_name TEXT,
+        setting_0 TEXT,
+        setting_1 TEXT,
+        default_val TEXT,
+        end_bit INTEGER,
+        risk_level TEXT NOT NULL DEFAULT 'none',
+        temporary_only BOOLEAN NOT NULL DEFAULT FALSE,
+        risk_note TEXT,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TEXT,
+        change_xid INTEGER NOT NULL DEFAULT 0,
+        UNIQUE (model_name, switch_number, bit_number)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS dip_switch_defaults (
+        dip_switch_id BLOB NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+        variant TEXT NOT NULL,
+        value TEXT,
+        position INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (dip_switch_id, variant)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS dip_switch_values (
+        dip_switch_id BLOB NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+        value INTEGER NOT NULL,
+        meaning TEXT,
+        PRIMARY KEY (dip_switch_id, value)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS dip_switch_rules (
+        id BLOB PRIMARY KEY,
+        dip_switch_id BLOB NOT NULL REFERENCES dip_switches(id) ON DELETE CASCADE,
+        when_value INTEGER NOT NULL DEFAULT 1,
+        kind TEXT NOT NULL DEFAULT 'requires' CHECK (kind IN ('requires', 'conflicts')),
+        target_switch INTEGER NOT NULL,
+        target_bit INTEGER NOT NULL,
+        target_value INTEGER NOT NULL,
+        message TEXT
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS dip_switch_profiles (
+        id BLOB PRIMARY KEY,
+        model_name TEXT NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT,
+        variant TEXT,
+        settings TEXT NOT NULL DEFAULT '[]',
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE (model_name, name)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS tombstones (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        entity TEXT NOT NULL,
+        entity_key TEXT NOT NULL,
+        deleted_at TEXT NOT NULL,
+        change_xid INTEGER NOT NULL
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS sync_clock (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        value INTEGER NOT NULL
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS interventions (
+        id BLOB PRIMARY KEY,
+        serial_number TEXT NOT NULL,
+        model_name TEXT NOT NULL,
+        performed_on TEXT NOT NULL,
+        technician TEXT NOT NULL,
+        minutes_spent INTEGER CHECK (minutes_spent >= 0),
+        outcome TEXT NOT NULL CHECK (outcome IN ('resolved', 'partially_resolved', 'unresolved', 'follow_up')),
+        notes TEXT,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_interventions_serial ON interventions (serial_number, performed_on)",
+    "CREATE INDEX IF NOT EXISTS idx_interventions_performed_on ON interventions (performed_on)",
+    r#"
+    CREATE TABLE IF NOT EXISTS intervention_error_codes (
+        intervention_id BLOB NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+        error_id BLOB REFERENCES error_codes(id) ON DELETE SET NULL,
+        code TEXT NOT NULL,
+        PRIMARY KEY (intervention_id, code)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS intervention_parts (
+        intervention_id BLOB NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+        part_id BLOB REFERENCES spare_parts(id) ON DELETE SET NULL,
+        oem_code TEXT NOT NULL,
+        quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+        PRIMARY KEY (intervention_id, oem_code)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS intervention_dip_switches (
+        intervention_id BLOB NOT NULL REFERENCES interventions(id) ON DELETE CASCADE,
+        switch_number INTEGER NOT NULL,
+        bit_number INTEGER NOT NULL,
+        previous_value INTEGER,
+        value INTEGER NOT NULL,
+        PRIMARY KEY (intervention_id, switch_number, bit_number)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS machines (
+        id BLOB PRIMARY KEY,
+        serial_number TEXT NOT NULL UNIQUE,
+        printer_id BLOB NOT NULL REFERENCES printers(id),
+        customer TEXT,
+        site TEXT,
+        installed_on TEXT,
+        firmware TEXT,
+        options TEXT NOT NULL DEFAULT '[]',
+        notes TEXT,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_machines_printer ON machines (printer_id)",
+    r#"
+    CREATE TABLE IF NOT EXISTS error_feedback (
+        id BLOB PRIMARY KEY,
+        error_id BLOB NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+        part_id BLOB REFERENCES spare_parts(id) ON DELETE CASCADE,
+        worked BOOLEAN NOT NULL,
+        technician TEXT NOT NULL,
+        comment TEXT,
+        created_at TEXT NOT NULL
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_error_feedback_error ON error_feedback (error_id, created_at)",
+    r#"
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id BLOB PRIMARY KEY,
+        name TEXT NOT NULL,
+        key_prefix TEXT NOT NULL,
+        key_hash TEXT NOT NULL UNIQUE,
+        role TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        revoked_at TEXT
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id BLOB PRIMARY KEY,
+        changed_at TEXT NOT NULL,
+        user_name TEXT NOT NULL,
+        action TEXT NOT NULL,
+        entity TEXT NOT NULL,
+        entity_id BLOB,
+        model_name TEXT,
+        entity_key TEXT NOT NULL,
+        field TEXT,
+        old_value TEXT,
+        new_value TEXT,
+        source_file TEXT
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log (entity, model_name, entity_key, changed_at)",
+    "CREATE INDEX IF NOT EXISTS idx_audit_log_entity_id ON audit_log (entity_id)",
+    r#"
+    CREATE TABLE IF NOT EXISTS error_code_revisions (
+        id BLOB PRIMARY KEY,
+        error_id BLOB NOT NULL REFERENCES error_codes(id) ON DELETE CASCADE,
+        revision INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        user_name TEXT,
+        source_file TEXT,
+        created_at TEXT NOT NULL,
+        code TEXT NOT NULL,
+        classification TEXT,
+        cause TEXT,
+        measures TEXT,
+        solution TEXT,
+        estimated_abnormal_parts TEXT,
+        correction TEXT,
+        faulty_part_isolation TEXT,
+        note TEXT,
+        UNIQUE (error_id, revision)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS import_batches (
+        id BLOB PRIMARY KEY,
+        source_file TEXT,
+        content_hash TEXT NOT NULL,
+        user_name TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        total_rows INTEGER NOT NULL DEFAULT 0,
+        created_count INTEGER NOT NULL DEFAULT 0,
+        updated_count INTEGER NOT NULL DEFAULT 0,
+        unchanged_count INTEGER NOT NULL DEFAULT 0,
+        failed_count INTEGER NOT NULL DEFAULT 0,
+        rolled_back_at TEXT,
+        rolled_back_by TEXT
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS column_mappings (
+        id BLOB PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        description TEXT,
+        columns TEXT NOT NULL DEFAULT '{}',
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    )
+    "#,
+];
+
+/// Columns added after the table was first created, as (table, column, definition).
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("error_parts", "catalog_ranking", "INTEGER"),
+    ("error_parts", "manual_ranking", "INTEGER CHECK (manual_ranking >= 1 AND manual_ranking <= 5)"),
+    ("error_parts", "ranking_source", "TEXT NOT NULL DEFAULT 'catalog'"),
+    ("error_parts", "fix_attempts", "INTEGER NOT NULL DEFAULT 0"),
+    ("error_parts", "fix_successes", "INTEGER NOT NULL DEFAULT 0"),
+    ("error_parts", "success_rate", "REAL"),
+    ("error_codes", "import_batch_id", "BLOB REFERENCES import_batches(id) ON DELETE SET NULL"),
+    ("error_codes", "field_sources", "TEXT NOT NULL DEFAULT '{}'"),
+    ("error_codes", "obsolete_at", "TEXT"),
+    ("error_codes", "obsolete_batch_id", "BLOB REFERENCES import_batches(id) ON DELETE SET NULL"),
+    ("import_batches", "obsolete_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("error_codes", "source_kind", "TEXT"),
+    ("error_codes", "source_document", "TEXT"),
+    ("error_codes", "source_sheet", "TEXT"),
+    ("error_codes", "source_row", "INTEGER"),
+    ("error_codes", "source_page", "INTEGER"),
+    ("error_codes", "imported_at", "TEXT"),
+    ("error_code_revisions", "batch_id", "BLOB REFERENCES import_batches(id) ON DELETE SET NULL"),
+    ("error_code_revisions", "previous_obsolete_at", "TEXT"),
+    ("error_code_revisions", "previous_obsolete_batch_id", "BLOB REFERENCES import_batches(id) ON DELETE SET NULL"),
+];
+
+/// SQL expression rendering a blob UUID column as the usual hyphenated text.
+fn uuid_text(column: &str) -> String {
+    let hex = format!("hex({})", column);
+    format!(
+        "lower(substr({hex}, 1, 8) || '-' || substr({hex}, 9, 4) || '-' || substr({hex}, 13, 4) || '-' || substr({hex}, 17, 4) || '-' || substr({hex}, 21, 12))"
+    )
+}
+
+/// Bumps the sync clock and stamps the row (`NEW`) with it.
+fn stamp_statements(table: &str, row: &str) -> String {
+    format!(
+        "UPDATE sync_clock SET value = value + 1 WHERE id = 1; \
+         UPDATE {table} SET change_xid = (SELECT value FROM sync_clock WHERE id = 1), updated_at = {NOW} WHERE {row};"
+    )
+}
+
+fn push_in_list<'a, T>(qb: &mut QueryBuilder<'a, Sqlite>, values: &'a [T])
+where
+    &'a T: sqlx::Encode<'a, Sqlite> + sqlx::Type<Sqlite> + Send + 'a,
+{
+    qb.push("(");
+    let mut separated = qb.separated(", ");
+    for value in values {
+        separated.push_bind(value);
+    }
+    qb.push(")");
+}
+
+/// Lowercased letters-and-digits words, as the search compares them.
+fn search_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// `text` as safe HTML with the words that start with one of `terms` wrapped in `<mark>`, like the
+/// Postgres search gives it.
+fn highlight(text: &str, terms: &[String]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        if word.is_empty() {
+            return;
+        }
+        let lower = word.to_lowercase();
+        if terms.iter().any(|t| lower.starts_with(t.as_str())) {
+            out.push_str("<mark>");
+            out.push_str(word);
+            out.push_str("</mark>");
+        } else {
+            out.push_str(word);
+        }
+        word.clear();
+    };
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            push_escaped(&mut out, c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+#[derive(sqlx::FromRow)]
+struct DipSwitchRuleRow {
+    dip_switch_id: Uuid,
+    #[sqlx(flatten)]
+    rule: DipSwitchRule,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    switch: DipSwitch,
+    meanings: Option<String>,
+}
+
+impl SqliteRepository {
+    pub async fn connect(database_url: &str) -> DbResult<SqliteRepository> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new().max_connections(5).connect_with(options).await?;
+        Ok(SqliteRepository { pool })
+    }
+
+    /// Creates the schema and the change-tracking triggers (recreated on every start, so edits to
+    /// `TRACKED_TABLES` apply to existing files).
+    pub async fn migrate(&self) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        for ddl in SCHEMA {
+            sqlx::query(ddl).execute(&mut *tx).await?;
+        }
+        // SQLite has no ADD COLUMN IF NOT EXISTS
+        for (table, column, definition) in ADDED_COLUMNS {
+            let exists: bool = sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_one(&mut *tx)
+                .await?;
+            if !exists {
+                sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        // Codes imported before provenance was recorded are traced back to their batch
+        sqlx::query(
+            "UPDATE error_codes SET source_kind = 'sheet', \
+             source_document = (SELECT b.source_file FROM import_batches b WHERE b.id = error_codes.import_batch_id), \
+             imported_at = (SELECT b.created_at FROM import_batches b WHERE b.id = error_codes.import_batch_id) \
+             WHERE source_kind IS NULL AND import_batch_id IN (SELECT id FROM import_batches)",
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("INSERT OR IGNORE INTO sync_clock (id, value) VALUES (1, 0)")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_tombstones_change_xid ON tombstones (change_xid)")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_error_code_revisions_batch ON error_code_revisions (batch_id)")
+            .execute(&mut *tx)
+            .await?;
+        // Switches without variants carry over their single default as the 'default' variant
+        sqlx::query(
+            "INSERT OR IGNORE INTO dip_switch_defaults (dip_switch_id, variant, value) \
+             SELECT d.id, 'default', d.default_val FROM dip_switches d \
+             WHERE d.default_val IS NOT NULL \
+             AND NOT EXISTS (SELECT 1 FROM dip_switch_defaults x WHERE x.dip_switch_id = d.id)",
+        )
+        .execute(&mut *tx)
+        .await?;
+        // Rows from before revisions were kept get a baseline revision
+        sqlx::query(&format!(
+            "INSERT INTO error_code_revisions (id, error_id, revision, action, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note) \
+             SELECT randomblob(16), e.id, 1, 'baseline', {NOW}, e.code, e.classification, e.cause, e.measures, e.solution, e.estimated_abnormal_parts, e.correction, e.faulty_part_isolation, e.note \
+             FROM error_codes e \
+             WHERE NOT EXISTS (SELECT 1 FROM error_code_revisions r WHERE r.error_id = e.id)"
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        for (table, columns, key) in TRACKED_TABLES {
+            let changed = columns
+                .iter()
+                .map(|c| format!("NEW.{c} IS NOT OLD.{c}"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            let tombstone_key = key
+                .iter()
+                .map(|k| format!("'{}', {}", k, uuid_text(&format!("OLD.{k}"))))
+                .collect::<Vec<_>>()
+                .join(", ");
+            for ddl in [
+                format!("CREATE INDEX IF NOT EXISTS idx_{table}_change_xid ON {table} (change_xid)"),
+                format!("DROP TRIGGER IF EXISTS {table}_track_insert"),
+                format!(
+                    "CREATE TRIGGER {table}_track_insert AFTER INSERT ON {table} BEGIN {} END",
+                    stamp_statements(table, "rowid = NEW.rowid")
+                ),
+                format!("DROP TRIGGER IF EXISTS {table}_track_update"),
+                format!(
+                    "CREATE TRIGGER {table}_track_update AFTER UPDATE ON {table} WHEN {changed} BEGIN {} END",
+                    stamp_statements(table, "rowid = NEW.rowid")
+                ),
+                format!("DROP TRIGGER IF EXISTS {table}_tombstone"),
+                format!(
+                    "CREATE TRIGGER {table}_tombstone AFTER DELETE ON {table} BEGIN \
+                     UPDATE sync_clock SET value = value + 1 WHERE id = 1; \
+                     INSERT INTO tombstones (entity, entity_key, deleted_at, change_xid) \
+                     VALUES ('{table}', json_object({tombstone_key}), {NOW}, (SELECT value FROM sync_clock WHERE id = 1)); END"
+                ),
+            ] {
+                sqlx::query(&ddl).execute(&mut *tx).await?;
+            }
+        }
+
+        // Defaults, value tables and rules are part of their switch
+        for table in ["dip_switch_defaults", "dip_switch_values", "dip_switch_rules"] {
+            for (event, row) in [("INSERT", "NEW"), ("UPDATE", "NEW"), ("DELETE", "OLD")] {
+                let trigger = format!("{table}_touch_{}", event.to_lowercase());
+                for ddl in [
+                    format!("DROP TRIGGER IF EXISTS {trigger}"),
+                    format!(
+                        "CREATE TRIGGER {trigger} AFTER {event} ON {table} BEGIN {} END",
+                        stamp_statements("dip_switches", &format!("id = {row}.dip_switch_id"))
+                    ),
+                ] {
+                    sqlx::query(&ddl).execute(&mut *tx).await?;
+                }
+            }
+        }
+        tx.commit().await
+    }
+
+    /// Upserts one switch and replaces its variant defaults. Returns true when the row was newly inserted.
+    async fn upsert_dipswitch(conn: &mut sqlx::SqliteConnection, model: &str, item: &DipSwitchImport) -> DbResult<bool> {
+        let existing: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM dip_switches WHERE model_name = ? AND switch_number = ? AND bit_number = ?",
+        )
+        .bind(model)
+        .bind(item.switch_number)
+        .bind(item.bit_number)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let switch_id = existing.unwrap_or_else(Uuid::new_v4);
+
+        sqlx::query(r#"
+            INSERT INTO dip_switches (
+                id, model_name, switch_number, bit_number, function_name, setting_0, setting_1, default_val, end_bit,
+                risk_level, temporary_only, risk_note
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (model_name, switch_number, bit_number) DO UPDATE SET
+                function_name = excluded.function_name,
+                setting_0 = excluded.setting_0,
+                setting_1 = excluded.setting_1,
+                default_val = excluded.default_val,
+                end_bit = excluded.end_bit,
+                risk_level = excluded.risk_level,
+                temporary_only = excluded.temporary_only,
+                risk_note = excluded.risk_note
+        "#)
+        .bind(switch_id)
+        .bind(model)
+        .bind(item.switch_number)
+        .bind(item.bit_number)
+        .bind(&item.function_name)
+        .bind(&item.setting_0)
+        .bind(&item.setting_1)
+        .bind(item.primary_default())
+        .bind(item.end_bit.filter(|&e| e > item.bit_number))
+        .bind(item.risk_level.as_deref().unwrap_or("none"))
+        .bind(item.temporary_only)
+        .bind(&item.risk_note)
+        .execute(&mut *conn)
+        .await?;
+
+        // A field replaces the single-bit half-entries stored earlier for the bits it now covers
+        if item.last_bit() > item.bit_number {
+            sqlx::query("DELETE FROM dip_switches WHERE model_name = ? AND switch_number = ? AND bit_number > ? AND bit_number <= ?")
+                .bind(model)
+                .bind(item.switch_number)
+                .bind(item.bit_number)
+                .bind(item.last_bit())
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_values WHERE dip_switch_id = ?")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+        for value in &item.values {
+            sqlx::query("INSERT INTO dip_switch_values (dip_switch_id, value, meaning) VALUES (?, ?, ?)")
+                .bind(switch_id)
+                .bind(value.value)
+                .bind(&value.meaning)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_defaults WHERE dip_switch_id = ?")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+        for (position, default) in item.variant_defaults().iter().enumerate() {
+            sqlx::query(r#"
+                INSERT INTO dip_switch_defaults (dip_switch_id, variant, value, position)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT (dip_switch_id, variant) DO UPDATE SET value = excluded.value
+            "#)
+            .bind(switch_id)
+            .bind(default.variant.trim())
+            .bind(&default.value)
+            .bind(position as i32)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM dip_switch_rules WHERE dip_switch_id = ?")
+            .bind(switch_id)
+            .execute(&mut *conn)
+            .await?;
+        for rule in &item.rules {
+            sqlx::query(r#"
+                INSERT INTO dip_switch_rules (id, dip_switch_id, when_value, kind, target_switch, target_bit, target_value, message)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#)
+            .bind(Uuid::new_v4())
+            .bind(switch_id)
+            .bind(rule.when_value)
+            .bind(&rule.kind)
+            .bind(rule.target_switch)
+            .bind(rule.target_bit)
+            .bind(rule.target_value)
+            .bind(&rule.message)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(existing.is_none())
+    }
+
+    async fn printer_id(conn: &mut sqlx::SqliteConnection, model: &str) -> DbResult<Uuid> {
+        let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM printers WHERE model_name = ?")
+            .bind(model)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO printers (id, model_name) VALUES (?, ?)")
+            .bind(id)
+            .bind(model)
+            .execute(&mut *conn)
+            .await?;
+        Ok(id)
+    }
+
+    /// Upserts import rows with a single statement. Rows that leave the stored texts as they are aren't
+    /// written, so they keep their batch and provenance.
+    async fn upsert_error_codes(
+        conn: &mut sqlx::SqliteConnection,
+        batch_id: Uuid,
+        printers: &HashMap<&str, Uuid>,
+        rows: &[ErrorCodeImportRow<'_>],
+    ) -> DbResult<()> {
+        let rows: Vec<&ErrorCodeImportRow> = rows.iter().filter(|row| row.revision.is_some()).collect();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "INSERT INTO error_codes (id, printer_id, code, classification, cause, measures, solution, \
+             estimated_abnormal_parts, correction, faulty_part_isolation, note, field_sources, import_batch_id, \
+             source_kind, source_document, source_sheet, source_row, source_page, imported_at) ",
+        );
+        qb.push_values(rows, |mut b, row| {
+            let fields = &row.fields;
+            b.push_bind(row.id)
+                .push_bind(printers[row.model])
+                .push_bind(fields.code)
+                .push_bind(fields.classification)
+                .push_bind(fields.cause)
+                .push_bind(fields.measures)
+                .push_bind(fields.solution)
+                .push_bind(fields.estimated_abnormal_parts)
+                .push_bind(fields.correction)
+                .push_bind(fields.faulty_part_isolation)
+                .push_bind(fields.note)
+                .push_bind(sqlx::types::Json(&row.field_sources))
+                .push_bind(batch_id)
+                .push_bind(&row.provenance.source_kind)
+                .push_bind(&row.provenance.source_document)
+                .push_bind(&row.provenance.source_sheet)
+                .push_bind(row.provenance.source_row)
+                .push_bind(row.provenance.source_page)
+                .push_bind(row.provenance.imported_at);
+        });
+        qb.push(r#"
+            ON CONFLICT (printer_id, code) DO UPDATE SET
+                classification = excluded.classification,
+                cause = excluded.cause,
+                measures = excluded.measures,
+                solution = excluded.solution,
+                estimated_abnormal_parts = excluded.estimated_abnormal_parts,
+                correction = excluded.correction,
+                faulty_part_isolation = excluded.faulty_part_isolation,
+                note = excluded.note,
+                field_sources = excluded.field_sources,
+                import_batch_id = excluded.import_batch_id,
+                source_kind = excluded.source_kind,
+                source_document = excluded.source_document,
+                source_sheet = excluded.source_sheet,
+                source_row = excluded.source_row,
+                source_page = excluded.source_page,
+                imported_at = excluded.imported_at,
+                obsolete_at = NULL,
+                obsolete_batch_id = NULL
+            WHERE error_codes.classification IS NOT excluded.classification
+                OR error_codes.cause IS NOT excluded.cause
+                OR error_codes.measures IS NOT excluded.measures
+                OR error_codes.solution IS NOT excluded.solution
+                OR error_codes.estimated_abnormal_parts IS NOT excluded.estimated_abnormal_parts
+                OR error_codes.correction IS NOT excluded.correction
+                OR error_codes.faulty_part_isolation IS NOT excluded.faulty_part_isolation
+                OR error_codes.note IS NOT excluded.note
+                OR error_codes.obsolete_at IS NOT NULL
+        "#);
+        qb.build().execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Marks error codes obsolete by a batch; codes already obsolete keep their date. Returns how many were marked.
+    async fn mark_obsolete(conn: &mut sqlx::SqliteConnection, batch: &ImportBatch, ids: &[Uuid]) -> DbResult<u64> {
+        let mut marked = 0;
+        for chunk in ids.chunks(IMPORT_CHUNK_ROWS) {
+            let mut qb = QueryBuilder::<Sqlite>::new("UPDATE error_codes SET obsolete_at = ");
+            qb.push_bind(batch.created_at).push(", obsolete_batch_id = ").push_bind(batch.id);
+            qb.push(" WHERE obsolete_at IS NULL AND id IN ");
+            push_in_list(&mut qb, chunk);
+            marked += qb.build().execute(&mut *conn).await?.rows_affected();
+        }
+        Ok(marked)
+    }
+
+    async fn insert_revisions(conn: &mut sqlx::SqliteConnection, revisions: &[&ErrorCodeRevision]) -> DbResult<()> {
+        for chunk in revisions.chunks(IMPORT_CHUNK_ROWS) {
+            // SQLite names the VALUES columns column1..column18, in the order bound below
+            let mut qb = QueryBuilder::<Sqlite>::new(r#"
+                INSERT INTO error_code_revisions (
+                    id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note,
+                    previous_obsolete_at, previous_obsolete_batch_id
+                )
+                SELECT v.column1, v.column2, COALESCE((SELECT MAX(r.revision) FROM error_code_revisions r WHERE r.error_id = v.column2), 0) + 1,
+                    v.column3, v.column4, v.column5, v.column6, v.column7, v.column8, v.column9, v.column10, v.column11, v.column12, v.column13, v.column14, v.column15, v.column16,
+                    v.column17, v.column18
+                FROM (
+            "#);
+            qb.push_values(chunk, |mut b, revision| {
+                b.push_bind(revision.id)
+                    .push_bind(revision.error_id)
+                    .push_bind(&revision.action)
+                    .push_bind(&revision.user_name)
+                    .push_bind(&revision.source_file)
+                    .push_bind(revision.batch_id)
+                    .push_bind(revision.created_at)
+                    .push_bind(&revision.code)
+                    .push_bind(&revision.classification)
+                    .push_bind(&revision.cause)
+                    .push_bind(&revision.measures)
+                    .push_bind(&revision.solution)
+                    .push_bind(&revision.estimated_abnormal_parts)
+                    .push_bind(&revision.correction)
+                    .push_bind(&revision.faulty_part_isolation)
+                    .push_bind(&revision.note)
+                    .push_bind(revision.previous_obsolete_at)
+                    .push_bind(revision.previous_obsolete_batch_id);
+            });
+            qb.push(") AS v");
+            qb.build().execute(&mut *conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_audit(conn: &mut sqlx::SqliteConnection, entries: &[&AuditEntry]) -> DbResult<()> {
+        for chunk in entries.chunks(IMPORT_CHUNK_ROWS) {
+            let mut qb = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO audit_log (id, changed_at, user_name, action, entity, entity_id, model_name, entity_key, field, old_value, new_value, source_file) ",
+            );
+            qb.push_values(chunk, |mut b, entry| {
+                b.push_bind(entry.id)
+                    .push_bind(entry.changed_at)
+                    .push_bind(&entry.user_name)
+                    .push_bind(&entry.action)
+                    .push_bind(&entry.entity)
+                    .push_bind(entry.entity_id)
+                    .push_bind(&entry.model_name)
+                    .push_bind(&entry.entity_key)
+                    .push_bind(&entry.field)
+                    .push_bind(&entry.old_value)
+                    .push_bind(&entry.new_value)
+                    .push_bind(&entry.source_file);
+            });
+            qb.build().execute(&mut *conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_import_batch(conn: &mut sqlx::SqliteConnection, batch: &ImportBatch) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO import_batches (id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, rolled_back_at, rolled_back_by)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#)
+        .bind(batch.id)
+        .bind(&batch.source_file)
+        .bind(&batch.content_hash)
+        .bind(&batch.user_name)
+        .bind(batch.created_at)
+        .bind(batch.total_rows)
+        .bind(batch.created_count)
+        .bind(batch.updated_count)
+        .bind(batch.unchanged_count)
+        .bind(batch.failed_count)
+        .bind(batch.rolled_back_at)
+        .bind(&batch.rolled_back_by)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_import_batch_counts(conn: &mut sqlx::SqliteConnection, batch: &ImportBatch) -> DbResult<()> {
+        sqlx::query(r#"
+            UPDATE import_batches SET
+                total_rows = ?2,
+                created_count = ?3,
+                updated_count = ?4,
+                unchanged_count = ?5,
+                failed_count = ?6,
+                obsolete_count = ?7
+            WHERE id = ?1
+        "#)
+        .bind(batch.id)
+        .bind(batch.total_rows)
+        .bind(batch.created_count)
+        .bind(batch.updated_count)
+        .bind(batch.unchanged_count)
+        .bind(batch.failed_count)
+        .bind(batch.obsolete_count)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn printers(&self) -> DbResult<Vec<Printer>> {
+        sqlx::query_as::<_, Printer>("SELECT * FROM printers ORDER BY model_name")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_or_create_printer(&self, model: &str) -> DbResult<Uuid> {
+        let mut conn = self.pool.acquire().await?;
+        Self::printer_id(&mut conn, model).await
+    }
+
+    async fn printers_for_models(&self, models: &[String], since: i64) -> DbResult<Vec<Printer>> {
+        let mut qb = QueryBuilder::<Sqlite>::new("SELECT * FROM printers WHERE change_xid >= ");
+        qb.push_bind(since).push(" AND model_name IN ");
+        push_in_list(&mut qb, models);
+        qb.push(" ORDER BY model_name");
+        qb.build_query_as::<Printer>().fetch_all(&self.pool).await
+    }
+
+    async fn search_error_codes(&self, model: &str, code: Option<&CodeQuery>, limit: Option<i32>, include_obsolete: bool) -> DbResult<Vec<ErrorCode>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE p.model_name = ",
+        );
+        qb.push_bind(model);
+        if !include_obsolete {
+            qb.push(" AND e.obsolete_at IS NULL");
+        }
+        // LIKE is case-insensitive in SQLite; the digits-only match has no SQL equivalent and is done below
+        if let Some(CodeQuery::Compact(prefix)) = code {
+            qb.push(" AND REPLACE(REPLACE(e.code, '-', ''), ' ', '') LIKE ").push_bind(format!("{}%", prefix));
+        }
+        let digits = match code {
+            Some(CodeQuery::Digits(prefix)) => Some(prefix),
+            _ => None,
+        };
+        if let (Some(l), None) = (limit, digits) {
+            qb.push(" LIMIT ").push_bind(l);
+        }
+        let mut errors = qb.build_query_as::<ErrorCode>().fetch_all(&self.pool).await?;
+
+        if let Some(prefix) = digits {
+            errors.retain(|e| e.code.chars().filter(|c| c.is_ascii_digit()).collect::<String>().starts_with(prefix.as_str()));
+            if let Some(l) = limit {
+                errors.truncate(l.max(0) as usize);
+            }
+        }
+        Ok(errors)
+    }
+
+    async fn import_error_codes(
+        &self,
+        batch: &mut ImportBatch,
+        rows: &[ErrorCodeImportRow<'_>],
+        obsolete: &[ObsoleteCode],
+        atomic: bool,
+    ) -> DbResult<Vec<ImportFailure>> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_import_batch(&mut tx, batch).await?;
+        let mut printers: HashMap<&str, Uuid> = HashMap::new();
+        for row in rows {
+            if !printers.contains_key(row.model) {
+                printers.insert(row.model, Self::printer_id(&mut tx, row.model).await?);
+            }
+        }
+
+        let mut failures: Vec<ImportFailure> = Vec::new();
+        for (chunk_index, chunk) in rows.chunks(IMPORT_CHUNK_ROWS).enumerate() {
+            let mut savepoint = tx.begin().await?;
+            if Self::upsert_error_codes(&mut savepoint, batch.id, &printers, chunk).await.is_ok() {
+                savepoint.commit().await?;
+                continue;
+            }
+            savepoint.rollback().await?;
+            // Write the rows of the failed statement one by one to find the ones refused
+            for (i, row) in chunk.iter().enumerate() {
+                let mut savepoint = tx.begin().await?;
+                match Self::upsert_error_codes(&mut savepoint, batch.id, &printers, std::slice::from_ref(row)).await {
+                    Ok(()) => savepoint.commit().await?,
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        failures.push(ImportFailure { row: chunk_index * IMPORT_CHUNK_ROWS + i, message: e.to_string() });
+                    }
+                }
+            }
+            if atomic && !failures.is_empty() {
+                break;
+            }
+        }
+        if atomic && !failures.is_empty() {
+            tx.rollback().await?;
+            batch.failed_count += failures.len() as i32;
+            return Ok(failures);
+        }
+
+        let written: Vec<&ErrorCodeImportRow> = rows
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !failures.iter().any(|f| f.row == *i))
+            .map(|(_, row)| row)
+            .collect();
+        let revisions: Vec<&ErrorCodeRevision> = written.iter().filter_map(|row| row.revision.as_ref()).collect();
+        let entries: Vec<&AuditEntry> = written.iter().flat_map(|row| &row.audit).chain(obsolete.iter().flat_map(|o| &o.audit)).collect();
+        let obsolete_ids: Vec<Uuid> = obsolete.iter().map(|o| o.id).collect();
+        batch.obsolete_count = Self::mark_obsolete(&mut tx, batch, &obsolete_ids).await? as i32;
+        Self::insert_revisions(&mut tx, &revisions).await?;
+        Self::insert_audit(&mut tx, &entries).await?;
+        tally_import(batch, rows, &failures);
+        Self::update_import_batch_counts(&mut tx, batch).await?;
+        tx.commit().await?;
+        Ok(failures)
+    }
+
+    async fn delete_error_code(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM error_codes WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_error_code(
+        &self,
+        id: Uuid,
+        fields: &ErrorCodeFields<'_>,
+        field_sources: &BTreeMap<String, String>,
+        source_kind: Option<&str>,
+    ) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE error_codes SET
+                classification = ?2,
+                cause = ?3,
+                measures = ?4,
+                solution = ?5,
+                estimated_abnormal_parts = ?6,
+                correction = ?7,
+                faulty_part_isolation = ?8,
+                note = ?9,
+                field_sources = ?10,
+                source_kind = COALESCE(?11, source_kind)
+            WHERE id = ?1
+        "#)
+        .bind(id)
+        .bind(fields.classification)
+        .bind(fields.cause)
+        .bind(fields.measures)
+        .bind(fields.solution)
+        .bind(fields.estimated_abnormal_parts)
+        .bind(fields.correction)
+        .bind(fields.faulty_part_isolation)
+        .bind(fields.note)
+        .bind(sqlx::types::Json(field_sources))
+        .bind(source_kind)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn error_code(&self, id: Uuid) -> DbResult<Option<ModelErrorCode>> {
+        sqlx::query_as::<_, ModelErrorCode>(
+            "SELECT p.model_name, e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE e.id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn export_error_codes(&self, models: &[String]) -> DbResult<Vec<ModelErrorCode>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT p.model_name, e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE p.model_name IN ",
+        );
+        push_in_list(&mut qb, models);
+        qb.push(" AND e.obsolete_at IS NULL ORDER BY p.model_name ASC, e.code ASC");
+        qb.build_query_as::<ModelErrorCode>().fetch_all(&self.pool).await
+    }
+
+    async fn changed_error_codes(&self, models: &[String], since: i64) -> DbResult<Vec<ErrorCode>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT e.* FROM error_codes e JOIN printers p ON p.id = e.printer_id WHERE p.model_name IN ",
+        );
+        push_in_list(&mut qb, models);
+        qb.push(" AND (e.change_xid >= ").push_bind(since);
+        qb.push(
+            " OR EXISTS (SELECT 1 FROM error_parts ep JOIN spare_parts sp ON sp.id = ep.part_id \
+             WHERE ep.error_id = e.id AND (ep.change_xid >= ",
+        )
+        .push_bind(since)
+        .push(" OR sp.change_xid >= ")
+        .push_bind(since)
+        .push("))");
+        qb.push(" OR EXISTS (SELECT 1 FROM tombstones t WHERE t.entity = 'error_parts' AND t.change_xid >= ")
+            .push_bind(since)
+            .push(format!(" AND json_extract(t.entity_key, '$.error_id') = {}))", uuid_text("e.id")));
+        qb.push(" ORDER BY e.printer_id ASC, e.code ASC");
+        qb.build_query_as::<ErrorCode>().fetch_all(&self.pool).await
+    }
+
+    async fn error_part_links(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorPartLink>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT ep.error_id, sp.id, sp.oem_code, sp.description, sp.image_url, COALESCE(ep.ranking, 5) AS ranking, \
+             ep.ranking_source, ep.fix_attempts, ep.fix_successes, ep.success_rate \
+             FROM error_parts ep JOIN spare_parts sp ON sp.id = ep.part_id WHERE ep.error_id IN ",
+        );
+        push_in_list(&mut qb, error_ids);
+        qb.push(" ORDER BY ep.error_id ASC, ep.ranking DESC, sp.oem_code ASC");
+        qb.build_query_as::<ErrorPartLink>().fetch_all(&self.pool).await
+    }
+
+    async fn spare_parts(&self, ids: &[Uuid]) -> DbResult<Vec<SparePart>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id, oem_code, description, image_url, COALESCE(ranking, 3) AS ranking FROM spare_parts WHERE id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY oem_code ASC");
+        qb.build_query_as::<SparePart>().fetch_all(&self.pool).await
+    }
+
+    /// One writer at a time: anything written after this read gets a higher clock value.
+    async fn sync_token(&self) -> DbResult<i64> {
+        sqlx::query_scalar("SELECT value + 1 FROM sync_clock WHERE id = 1")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn tombstones(&self, since: i64) -> DbResult<Vec<Tombstone>> {
+        sqlx::query_as::<_, Tombstone>(
+            "SELECT entity, entity_key, deleted_at FROM tombstones WHERE change_xid >= ? ORDER BY id ASC",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn dipswitches(&self, filter: &DipSwitchFilter) -> DbResult<Vec<DipSwitch>> {
+        let mut qb = QueryBuilder::<Sqlite>::new("SELECT * FROM dip_switches WHERE change_xid >= ");
+        qb.push_bind(filter.since);
+        if !filter.models.is_empty() {
+            qb.push(" AND model_name IN ");
+            push_in_list(&mut qb, &filter.models);
+        }
+        if let Some(sw) = filter.switch {
+            qb.push(" AND switch_number = ").push_bind(sw);
+        }
+        if let Some(bit) = filter.bit {
+            qb.push(" AND bit_number <= ").push_bind(bit);
+            qb.push(" AND COALESCE(end_bit, bit_number) >= ").push_bind(bit);
+        }
+        qb.push(" ORDER BY model_name ASC, switch_number ASC, bit_number ASC");
+        qb.build_query_as::<DipSwitch>().fetch_all(&self.pool).await
+    }
+
+    async fn dipswitch_defaults(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchDefault>>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT dip_switch_id, variant, value FROM dip_switch_defaults WHERE dip_switch_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY position ASC");
+        let rows: Vec<(Uuid, String, Option<String>)> = qb.build_query_as().fetch_all(&self.pool).await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchDefault>> = HashMap::new();
+        for (id, variant, value) in rows {
+            by_switch.entry(id).or_default().push(DipSwitchDefault { variant, value });
+        }
+        Ok(by_switch)
+    }
+
+    async fn dipswitch_values(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchValue>>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT dip_switch_id, value, meaning FROM dip_switch_values WHERE dip_switch_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY value ASC");
+        let rows: Vec<(Uuid, i32, Option<String>)> = qb.build_query_as().fetch_all(&self.pool).await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchValue>> = HashMap::new();
+        for (id, value, meaning) in rows {
+            by_switch.entry(id).or_default().push(DipSwitchValue { value, meaning, bits: String::new() });
+        }
+        Ok(by_switch)
+    }
+
+    async fn dipswitch_rules(&self, ids: &[Uuid]) -> DbResult<HashMap<Uuid, Vec<DipSwitchRule>>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT dip_switch_id, when_value, kind, target_switch, target_bit, target_value, message \
+             FROM dip_switch_rules WHERE dip_switch_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY target_switch ASC, target_bit ASC");
+        let rows: Vec<DipSwitchRuleRow> = qb.build_query_as().fetch_all(&self.pool).await?;
+
+        let mut by_switch: HashMap<Uuid, Vec<DipSwitchRule>> = HashMap::new();
+        for row in rows {
+            by_switch.entry(row.dip_switch_id).or_default().push(row.rule);
+        }
+        Ok(by_switch)
+    }
+
+    async fn write_dipswitches(
+        &self,
+        model: &str,
+        items: &[&DipSwitchImport],
+        keep: Option<&[(i32, i32)]>,
+    ) -> DbResult<DipSwitchWrite> {
+        let mut write = DipSwitchWrite::default();
+        let mut tx = self.pool.begin().await?;
+
+        for item in items {
+            let mut savepoint = tx.begin().await?;
+            match Self::upsert_dipswitch(&mut savepoint, model, item).await {
+                Ok(inserted) => {
+                    savepoint.commit().await?;
+                    write.outcomes.push(Ok(inserted));
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    tracing::error!("DIP switch import failed for {} SW {}-{}: {:?}", model, item.switch_number, item.bit_number, e);
+                    write.outcomes.push(Err(e.to_string()));
+                }
+            }
+        }
+
+        if let Some(keep) = keep {
+            let mut qb = QueryBuilder::<Sqlite>::new("DELETE FROM dip_switches WHERE model_name = ");
+            qb.push_bind(model);
+            if !keep.is_empty() {
+                qb.push(" AND (switch_number, bit_number) NOT IN (VALUES ");
+                let mut separated = qb.separated(", ");
+                for (switch, bit) in keep {
+                    separated.push("(").push_bind_unseparated(*switch).push_unseparated(", ").push_bind_unseparated(*bit).push_unseparated(")");
+                }
+                qb.push(")");
+            }
+            write.removed = qb.build().execute(&mut *tx).await?.rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(write)
+    }
+
+    /// No full-text index here: candidates are filtered and ranked in Rust, function words weighing more
+    /// than setting words, which is plenty for one laptop's data.
+    async fn search_dipswitches(&self, words: &[String], model: Option<&str>, limit: i64) -> DbResult<Vec<DipSwitchSearchHit>> {
+        let terms: Vec<String> = words.iter().flat_map(|w| search_words(w)).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows = sqlx::query_as::<_, SearchRow>(r#"
+            SELECT d.*,
+                   (SELECT group_concat(meaning, ' ') FROM (
+                        SELECT meaning FROM dip_switch_values WHERE dip_switch_id = d.id ORDER BY value
+                   )) AS meanings
+            FROM dip_switches d
+            WHERE (?1 IS NULL OR d.model_name = ?1)
+        "#)
+        .bind(model)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut hits = Vec::new();
+        for row in rows {
+            let function = row.switch.function_name.clone().unwrap_or_default();
+            let settings = [row.switch.setting_0.as_deref(), row.switch.setting_1.as_deref(), row.meanings.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let (function_words, settings_words) = (search_words(&function), search_words(&settings));
+            let mut rank = 0.0f32;
+            let mut all_found = true;
+            for term in &terms {
+                if function_words.iter().any(|w| w.starts_with(term.as_str())) {
+                    rank += 1.0;
+                } else if settings_words.iter().any(|w| w.starts_with(term.as_str())) {
+                    rank += 0.4;
+                } else {
+                    all_found = false;
+                    break;
+                }
+            }
+            if !all_found {
+                continue;
+            }
+            hits.push(DipSwitchSearchHit {
+                rank: rank / terms.len() as f32,
+                function_highlight: Some(highlight(&function, &terms)),
+                settings_highlight: Some(highlight(&settings, &terms)),
+                switch: row.switch,
+            });
+        }
+        hits.sort_by(|a, b| {
+            b.rank
+                .total_cmp(&a.rank)
+                .then_with(|| a.switch.model_name.cmp(&b.switch.model_name))
+                .then_with(|| (a.switch.switch_number, a.switch.bit_number).cmp(&(b.switch.switch_number, b.switch.bit_number)))
+        });
+        hits.truncate(limit.max(0) as usize);
+        Ok(hits)
+    }
+
+    async fn dipswitch_profiles(&self, model: Option<&str>) -> DbResult<Vec<DipSwitchProfile>> {
+        sqlx::query_as::<_, DipSwitchProfile>(r#"
+            SELECT id, model_name, name, description, variant, settings FROM dip_switch_profiles
+            WHERE (?1 IS NULL OR model_name = ?1)
+            ORDER BY model_name ASC, name ASC
+        "#)
+        .bind(model)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn dipswitch_profile(&self, id: Uuid) -> DbResult<Option<DipSwitchProfile>> {
+        sqlx::query_as::<_, DipSwitchProfile>(
+            "SELECT id, model_name, name, description, variant, settings FROM dip_switch_profiles WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn save_dipswitch_profile(
+        &self,
+        model: &str,
+        name: &str,
+        description: Option<&str>,
+        variant: Option<&str>,
+        settings: &[DipSwitchSetting],
+    ) -> DbResult<DipSwitchProfile> {
+        sqlx::query_as::<_, DipSwitchProfile>(r#"
+            INSERT INTO dip_switch_profiles (id, model_name, name, description, variant, settings)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (model_name, name) DO UPDATE SET
+                description = excluded.description,
+                variant = excluded.variant,
+                settings = excluded.settings
+            RETURNING id, model_name, name, description, variant, settings
+        "#)
+        .bind(Uuid::new_v4())
+        .bind(model)
+        .bind(name)
+        .bind(description)
+        .bind(variant)
+        .bind(sqlx::types::Json(settings))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn error_code_ids(&self, model: &str, codes: &[String]) -> DbResult<HashMap<String, Uuid>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT e.code, e.id FROM error_codes e JOIN printers p ON p.id = e.printer_id WHERE p.model_name = ",
+        );
+        qb.push_bind(model).push(" AND e.code IN ");
+        push_in_list(&mut qb, codes);
+        let rows: Vec<(String, Uuid)> = qb.build_query_as().fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    async fn spare_parts_by_oem(&self, oem_codes: &[String]) -> DbResult<Vec<SparePart>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id, oem_code, description, image_url, COALESCE(ranking, 3) AS ranking FROM spare_parts WHERE oem_code IN ",
+        );
+        push_in_list(&mut qb, oem_codes);
+        qb.push(" ORDER BY oem_code ASC");
+        qb.build_query_as::<SparePart>().fetch_all(&self.pool).await
+    }
+
+    async fn save_intervention(&self, intervention: &Intervention) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(r#"
+            INSERT INTO interventions (id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#)
+        .bind(intervention.id)
+        .bind(&intervention.serial_number)
+        .bind(&intervention.model_name)
+        .bind(intervention.performed_on)
+        .bind(&intervention.technician)
+        .bind(intervention.minutes_spent)
+        .bind(&intervention.outcome)
+        .bind(&intervention.notes)
+        .execute(&mut *tx)
+        .await?;
+
+        for error in &intervention.error_codes {
+            sqlx::query("INSERT INTO intervention_error_codes (intervention_id, error_id, code) VALUES (?, ?, ?)")
+                .bind(intervention.id)
+                .bind(error.error_id)
+                .bind(&error.code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for part in &intervention.parts {
+            sqlx::query("INSERT INTO intervention_parts (intervention_id, part_id, oem_code, quantity) VALUES (?, ?, ?, ?)")
+                .bind(intervention.id)
+                .bind(part.part_id)
+                .bind(&part.oem_code)
+                .bind(part.quantity)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for switch in &intervention.dip_switches {
+            sqlx::query(r#"
+                INSERT INTO intervention_dip_switches (intervention_id, switch_number, bit_number, previous_value, value)
+                VALUES (?, ?, ?, ?, ?)
+            "#)
+            .bind(intervention.id)
+            .bind(switch.switch_number)
+            .bind(switch.bit_number)
+            .bind(switch.previous_value)
+            .bind(switch.value)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn interventions(&self, filter: &InterventionFilter) -> DbResult<Vec<Intervention>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes FROM interventions i WHERE 1=1",
+        );
+        if let Some(serial) = &filter.serial_number {
+            qb.push(" AND serial_number = ").push_bind(serial);
+        }
+        if let Some(model) = &filter.model {
+            qb.push(" AND model_name = ").push_bind(model);
+        }
+        if let Some(technician) = &filter.technician {
+            qb.push(" AND lower(technician) = lower(").push_bind(technician).push(")");
+        }
+        if let Some(code) = &filter.code {
+            qb.push(" AND EXISTS (SELECT 1 FROM intervention_error_codes x WHERE x.intervention_id = i.id AND x.code = ")
+                .push_bind(code)
+                .push(")");
+        }
+        if let Some(outcome) = &filter.outcome {
+            qb.push(" AND outcome = ").push_bind(outcome);
+        }
+        // Dates are stored as YYYY-MM-DD text, which compares in date order
+        if let Some(from) = filter.from {
+            qb.push(" AND performed_on >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            qb.push(" AND performed_on <= ").push_bind(to);
+        }
+        qb.push(" ORDER BY performed_on DESC, created_at DESC LIMIT ").push_bind(filter.limit);
+        qb.build_query_as::<Intervention>().fetch_all(&self.pool).await
+    }
+
+    async fn intervention(&self, id: Uuid) -> DbResult<Option<Intervention>> {
+        sqlx::query_as::<_, Intervention>(
+            "SELECT id, serial_number, model_name, performed_on, technician, minutes_spent, outcome, notes FROM interventions WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn intervention_details(&self, ids: &[Uuid]) -> DbResult<InterventionDetails> {
+        let mut details = InterventionDetails::default();
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT intervention_id, error_id, code FROM intervention_error_codes WHERE intervention_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY code ASC");
+        let rows: Vec<(Uuid, Option<Uuid>, String)> = qb.build_query_as().fetch_all(&self.pool).await?;
+        for (id, error_id, code) in rows {
+            details.error_codes.entry(id).or_default().push(InterventionErrorCode { error_id, code });
+        }
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT intervention_id, part_id, oem_code, quantity FROM intervention_parts WHERE intervention_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY oem_code ASC");
+        let rows: Vec<(Uuid, Option<Uuid>, String, i32)> = qb.build_query_as().fetch_all(&self.pool).await?;
+        for (id, part_id, oem_code, quantity) in rows {
+            details.parts.entry(id).or_default().push(InterventionPart { part_id, oem_code, quantity });
+        }
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT intervention_id, switch_number, bit_number, previous_value, value FROM intervention_dip_switches WHERE intervention_id IN ",
+        );
+        push_in_list(&mut qb, ids);
+        qb.push(" ORDER BY switch_number ASC, bit_number ASC");
+        let rows: Vec<(Uuid, i32, i32, Option<i32>, i32)> = qb.build_query_as().fetch_all(&self.pool).await?;
+        for (id, switch_number, bit_number, previous_value, value) in rows {
+            details
+                .dip_switches
+                .entry(id)
+                .or_default()
+                .push(InterventionDipSwitch { switch_number, bit_number, previous_value, value });
+        }
+
+        Ok(details)
+    }
+
+    async fn machines(&self, filter: &MachineFilter) -> DbResult<Vec<Machine>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT m.id, m.serial_number, m.printer_id, p.model_name, m.customer, m.site, m.installed_on, m.firmware, m.options, m.notes FROM machines m JOIN printers p ON p.id = m.printer_id WHERE 1=1",
+        );
+        if let Some(query) = &filter.query {
+            let pattern = format!("%{}%", query);
+            qb.push(" AND (m.serial_number LIKE ").push_bind(pattern.clone());
+            qb.push(" OR m.customer LIKE ").push_bind(pattern.clone());
+            qb.push(" OR m.site LIKE ").push_bind(pattern).push(")");
+        }
+        if let Some(model) = &filter.model {
+            qb.push(" AND p.model_name = ").push_bind(model);
+        }
+        qb.push(" ORDER BY m.serial_number ASC LIMIT ").push_bind(filter.limit);
+        qb.build_query_as::<Machine>().fetch_all(&self.pool).await
+    }
+
+    async fn machine(&self, id: Uuid) -> DbResult<Option<Machine>> {
+        sqlx::query_as::<_, Machine>(
+            "SELECT m.id, m.serial_number, m.printer_id, p.model_name, m.customer, m.site, m.installed_on, m.firmware, m.options, m.notes FROM machines m JOIN printers p ON p.id = m.printer_id WHERE m.id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn insert_machine(&self, machine: &Machine) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO machines (id, serial_number, printer_id, customer, site, installed_on, firmware, options, notes)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#)
+        .bind(machine.id)
+        .bind(&machine.serial_number)
+        .bind(machine.printer_id)
+        .bind(&machine.customer)
+        .bind(&machine.site)
+        .bind(machine.installed_on)
+        .bind(&machine.firmware)
+        .bind(&machine.options)
+        .bind(&machine.notes)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_machine(&self, machine: &Machine) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE machines SET
+                serial_number = ?, printer_id = ?, customer = ?, site = ?, installed_on = ?,
+                firmware = ?, options = ?, notes = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+        "#)
+        .bind(&machine.serial_number)
+        .bind(machine.printer_id)
+        .bind(&machine.customer)
+        .bind(&machine.site)
+        .bind(machine.installed_on)
+        .bind(&machine.firmware)
+        .bind(&machine.options)
+        .bind(&machine.notes)
+        .bind(machine.id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_machine(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM machines WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn ranking_inputs(&self, model: Option<&str>, link: Option<(Uuid, Uuid)>) -> DbResult<Vec<RankingInput>> {
+        sqlx::query_as::<_, RankingInput>(r#"
+            SELECT ep.error_id, ep.part_id,
+                   COALESCE(ep.catalog_ranking, ep.ranking, 5) AS catalog_ranking,
+                   ep.manual_ranking,
+                   COALESCE(SUM(CASE WHEN i.outcome IN ('resolved', 'partially_resolved', 'unresolved') THEN 1 ELSE 0 END), 0) AS attempts,
+                   COALESCE(SUM(CASE WHEN i.outcome = 'resolved' THEN 1 ELSE 0 END), 0) AS successes
+            FROM error_parts ep
+            JOIN error_codes e ON e.id = ep.error_id
+            JOIN printers p ON p.id = e.printer_id
+            LEFT JOIN intervention_error_codes iec ON iec.error_id = ep.error_id
+            LEFT JOIN intervention_parts ip ON ip.intervention_id = iec.intervention_id AND ip.part_id = ep.part_id
+            LEFT JOIN interventions i ON i.id = ip.intervention_id
+            WHERE (?1 IS NULL OR p.model_name = ?1)
+            AND (?2 IS NULL OR (ep.error_id = ?2 AND ep.part_id = ?3))
+            GROUP BY ep.error_id, ep.part_id, ep.catalog_ranking, ep.ranking, ep.manual_ranking
+        "#)
+        .bind(model)
+        .bind(link.map(|(error_id, _)| error_id))
+        .bind(link.map(|(_, part_id)| part_id))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn apply_rankings(&self, updates: &[RankingUpdate]) -> DbResult<u64> {
+        let mut changed = 0;
+        let mut tx = self.pool.begin().await?;
+        for update in updates {
+            // Unchanged links are left alone, so a recalculation does not show up in delta sync
+            changed += sqlx::query(r#"
+                UPDATE error_parts SET
+                    ranking = ?3, catalog_ranking = ?4, ranking_source = ?5,
+                    fix_attempts = ?6, fix_successes = ?7, success_rate = ?8
+                WHERE error_id = ?1 AND part_id = ?2
+                AND (ranking IS NOT ?3 OR catalog_ranking IS NOT ?4 OR ranking_source IS NOT ?5
+                     OR fix_attempts IS NOT ?6 OR fix_successes IS NOT ?7 OR success_rate IS NOT ?8)
+            "#)
+            .bind(update.error_id)
+            .bind(update.part_id)
+            .bind(update.ranking)
+            .bind(update.catalog_ranking)
+            .bind(update.source)
+            .bind(update.attempts)
+            .bind(update.successes)
+            .bind(update.success_rate)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+
+    async fn set_manual_ranking(&self, error_id: Uuid, part_id: Uuid, ranking: Option<i32>) -> DbResult<bool> {
+        let result = sqlx::query(r#"
+            UPDATE error_parts SET manual_ranking = ?3, catalog_ranking = COALESCE(catalog_ranking, ranking)
+            WHERE error_id = ?1 AND part_id = ?2
+        "#)
+        .bind(error_id)
+        .bind(part_id)
+        .bind(ranking)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn save_feedback(&self, feedback: &ErrorFeedback) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO error_feedback (id, error_id, part_id, worked, technician, comment, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#)
+        .bind(feedback.id)
+        .bind(feedback.error_id)
+        .bind(feedback.part_id)
+        .bind(feedback.worked)
+        .bind(&feedback.technician)
+        .bind(&feedback.comment)
+        .bind(feedback.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn feedback(&self, error_ids: &[Uuid]) -> DbResult<Vec<ErrorFeedback>> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id, error_id, part_id, worked, technician, comment, created_at FROM error_feedback WHERE error_id IN ",
+        );
+        push_in_list(&mut qb, error_ids);
+        // Timestamps are RFC3339 text with a variable number of fraction digits
+        qb.push(" ORDER BY julianday(created_at) DESC, rowid DESC");
+        qb.build_query_as::<ErrorFeedback>().fetch_all(&self.pool).await
+    }
+
+    async fn api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(
+            "SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn api_key(&self, id: Uuid) -> DbResult<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>("SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys WHERE id = ? AND revoked_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn api_keys(&self) -> DbResult<Vec<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>("SELECT id, name, key_prefix, key_hash, role, created_at, revoked_at FROM api_keys ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> DbResult<()> {
+        sqlx::query(r#"
+            INSERT INTO api_keys (id, name, key_prefix, key_hash, role, created_at, revoked_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#)
+        .bind(key.id)
+        .bind(&key.name)
+        .bind(&key.key_prefix)
+        .bind(&key.key_hash)
+        .bind(&key.role)
+        .bind(key.created_at)
+        .bind(key.revoked_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn revoke_api_key(&self, id: Uuid) -> DbResult<bool> {
+        let result = sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL")
+            .bind(chrono::Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_audit(&self, entries: &[AuditEntry]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_audit(&mut tx, &entries.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter) -> DbResult<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>(r#"
+            SELECT id, changed_at, user_name, action, entity, entity_id, model_name, entity_key, field, old_value, new_value, source_file
+            FROM audit_log
+            WHERE (?1 IS NULL OR model_name = ?1)
+            AND (?2 IS NULL OR entity = ?2)
+            AND (?3 IS NULL OR entity_key = ?3)
+            AND (?4 IS NULL OR entity_id = ?4)
+            AND (?5 IS NULL OR user_name = ?5)
+            ORDER BY julianday(changed_at) DESC, entity_key, field
+            LIMIT ?6
+        "#)
+        .bind(&filter.model)
+        .bind(&filter.entity)
+        .bind(&filter.entity_key)
+        .bind(filter.entity_id)
+        .bind(&filter.user)
+        .bind(filter.limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn add_error_code_revisions(&self, revisions: &[ErrorCodeRevision]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_revisions(&mut tx, &revisions.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await
+    }
+
+    async fn error_code_revisions(&self, error_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(
+            "SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note FROM error_code_revisions WHERE error_id = ? ORDER BY revision DESC",
+        )
+        .bind(error_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn error_code_revisions_at(
+        &self,
+        model: &str,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(r#"
+            SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note FROM (
+                SELECT r.*, ROW_NUMBER() OVER (PARTITION BY r.error_id ORDER BY r.revision DESC) AS rn
+                FROM error_code_revisions r
+                JOIN error_codes e ON e.id = r.error_id
+                JOIN printers p ON p.id = e.printer_id
+                WHERE p.model_name = ?1 AND julianday(r.created_at) <= julianday(?2)
+            ) latest
+            WHERE rn = 1
+            ORDER BY code
+        "#)
+        .bind(model)
+        .bind(at)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn import_batches(&self, limit: i64) -> DbResult<Vec<ImportBatch>> {
+        sqlx::query_as::<_, ImportBatch>("SELECT id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, obsolete_count, rolled_back_at, rolled_back_by FROM import_batches ORDER BY created_at DESC LIMIT ?")
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn import_batch(&self, id: Uuid) -> DbResult<Option<ImportBatch>> {
+        sqlx::query_as::<_, ImportBatch>("SELECT id, source_file, content_hash, user_name, created_at, total_rows, created_count, updated_count, unchanged_count, failed_count, obsolete_count, rolled_back_at, rolled_back_by FROM import_batches WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn batch_revisions(&self, batch_id: Uuid) -> DbResult<Vec<ErrorCodeRevision>> {
+        sqlx::query_as::<_, ErrorCodeRevision>(
+            "SELECT id, error_id, revision, action, user_name, source_file, batch_id, created_at, code, classification, cause, measures, solution, estimated_abnormal_parts, correction, faulty_part_isolation, note, previous_obsolete_at, previous_obsolete_batch_id FROM error_code_revisions WHERE batch_id = ? ORDER BY error_id, revision",
+        )
+        .bind(batch_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn column_mappings(&self) -> DbResult<Vec<ColumnMapping>> {
+        sqlx::query_as::<_, ColumnMapping>("SELECT id, name, description, columns FROM column_mappings ORDER BY name")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn save_column_mapping(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        columns: &BTreeMap<String, Vec<String>>,
+    ) -> DbResult<ColumnMapping> {
+        sqlx::query_as::<_, ColumnMapping>(r#"
+            INSERT INTO column_mappings (id, name, description, columns)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (name) DO UPDATE SET
+                description = excluded.description,
+                columns = excluded.columns
+            RETURNING id, name, description, columns
+        "#)
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(description)
+        .bind(sqlx::types::Json(columns))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn delete_column_mapping(&self, name: &str) -> DbResult<bool> {
+        let result = sqlx::query("DELETE FROM column_mappings WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn batch_obsolete_codes(&self, batch_id: Uuid) -> DbResult<Vec<ModelErrorCode>> {
+        sqlx::query_as::<_, ModelErrorCode>(
+            "SELECT p.model_name, e.* FROM error_codes e JOIN printers p ON e.printer_id = p.id WHERE e.obsolete_batch_id = ? ORDER BY p.model_name, e.code",
+        )
+        .bind(batch_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn rollback_import_batch(&self, rollback: &BatchRollback) -> DbResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let claimed = sqlx::query(
+            "UPDATE import_batches SET rolled_back_at = ?, rolled_back_by = ? WHERE id = ? AND rolled_back_at IS NULL",
+        )
+        .bind(chrono::Utc::now())
+        .bind(&rollback.user)
+        .bind(rollback.batch_id)
+        .execute(&mut *tx)
+        .await?;
+        if claimed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        for id in &rollback.deletes {
+            sqlx::query("DELETE FROM error_codes WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for restore in &rollback.restores {
+            let revision = &restore.revision;
+            sqlx::query(r#"
+                UPDATE error_codes SET
+                    classification = ?2,
+                    cause = ?3,
+                    measures = ?4,
+                    solution = ?5,
+                    estimated_abnormal_parts = ?6,
+                    correction = ?7,
+                    faulty_part_isolation = ?8,
+                    note = ?9,
+                    field_sources = ?10
+                WHERE id = ?1
+            "#)
+            .bind(revision.error_id)
+            .bind(&revision.classification)
+            .bind(&revision.cause)
+            .bind(&revision.measures)
+            .bind(&revision.solution)
+            .bind(&revision.estimated_abnormal_parts)
+            .bind(&revision.correction)
+            .bind(&revision.faulty_part_isolation)
+            .bind(&revision.note)
+            .bind(sqlx::types::Json(&restore.field_sources))
+            .execute(&mut *tx)
+            .await?;
+            if restore.obsolete_at.is_some() {
+                sqlx::query("UPDATE error_codes SET obsolete_at = ?, obsolete_batch_id = ? WHERE id = ? AND obsolete_at IS NULL")
+                    .bind(restore.obsolete_at)
+                    .bind(restore.obsolete_batch_id)
+                    .bind(revision.error_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        Self::insert_revisions(&mut tx, &rollback.restores.iter().map(|r| &r.revision).collect::<Vec<_>>()).await?;
+        for id in &rollback.revives {
+            sqlx::query("UPDATE error_codes SET obsolete_at = NULL, obsolete_batch_id = NULL WHERE id = ? AND obsolete_batch_id = ?")
+                .bind(id)
+                .bind(rollback.batch_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Self::insert_audit(&mut tx, &rollback.audit.iter().collect::<Vec<_>>()).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{self, AuditContext};
+    use crate::auth::{Caller, Role};
+    use crate::models::Provenance;
+    use crate::repository::ErrorCodeRestore;
+
+    async fn repository() -> SqliteRepository {
+        let path = std::env::temp_dir().join(format!("rollback-{}.db", Uuid::new_v4()));
+        let repo = SqliteRepository::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        repo.migrate().await.unwrap();
+        repo
+    }
+
+    fn caller() -> Caller {
+        Caller { name: "tester".to_string(), role: Role::Editor, key_id: None }
+    }
+
+    fn batch() -> ImportBatch {
+        ImportBatch {
+            id: Uuid::new_v4(),
+            source_file: Some("codes.csv".to_string()),
+            content_hash: String::new(),
+            user_name: "tester".to_string(),
+            created_at: chrono::Utc::now(),
+            total_rows: 0,
+            created_count: 0,
+            updated_count: 0,
+            unchanged_count: 0,
+            failed_count: 0,
+            obsolete_count: 0,
+            rolled_back_at: None,
+            rolled_back_by: None,
+        }
+    }
+
+    /// Imports `(id, code, cause)` rows for model "M" as one batch.
+    async fn import(repo: &SqliteRepository, rows: &[(Uuid, &str, &str)]) -> ImportBatch {
+        let mut batch = batch();
+        let mut context = AuditContext::new(&caller(), batch.source_file.as_deref());
+        context.batch_id = Some(batch.id);
+        let rows: Vec<ErrorCodeImportRow> = rows
+            .iter()
+            .map(|&(id, code, cause)| {
+                let fields = ErrorCodeFields { code, cause: Some(cause), ..Default::default() };
+                ErrorCodeImportRow {
+                    id,
+                    model: "M",
+                    fields,
+                    field_sources: BTreeMap::new(),
+                    provenance: Provenance { import_batch_id: Some(batch.id), ..Default::default() },
+                    revision: Some(context.revision(id, audit::ACTION_UPDATE, &fields)),
+                    audit: Vec::new(),
+                }
+            })
+            .collect();
+        assert!(repo.import_error_codes(&mut batch, &rows, &[], true).await.unwrap().is_empty());
+        batch
+    }
+
+    async fn cause(repo: &SqliteRepository, id: Uuid) -> Option<String> {
+        repo.error_code(id).await.unwrap().and_then(|code| code.error.cause)
+    }
+
+    #[test]
+    fn highlights_are_escaped_html() {
+        let terms = vec!["toner".to_string()];
+        assert_eq!(
+            highlight("<img src=x> Toner & \"save\"", &terms),
+            "&lt;img src=x&gt; <mark>Toner</mark> &amp; &quot;save&quot;"
+        );
+        assert_eq!(
+            crate::repository::marked_html("<b>\u{1}Toner\u{2}</b> 'low'"),
+            "&lt;b&gt;<mark>Toner</mark>&lt;/b&gt; &#39;low&#39;"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_rollback_writes_nothing_and_can_be_retried() {
+        let repo = repository().await;
+        let (changed, created) = (Uuid::new_v4(), Uuid::new_v4());
+        import(&repo, &[(changed, "C-1", "first")]).await;
+        let batch = import(&repo, &[(changed, "C-1", "second"), (created, "C-2", "new")]).await;
+
+        let previous = repo.error_code_revisions(changed).await.unwrap().pop().unwrap();
+        let context = AuditContext::new(&caller(), None);
+        let rollback = BatchRollback {
+            batch_id: batch.id,
+            user: "tester".to_string(),
+            deletes: vec![created],
+            restores: vec![ErrorCodeRestore {
+                revision: context.revision(changed, audit::ACTION_ROLLBACK, &audit::revision_fields(&previous)),
+                field_sources: BTreeMap::new(),
+                obsolete_at: None,
+                obsolete_batch_id: None,
+            }],
+            revives: Vec::new(),
+            audit: Vec::new(),
+        };
+
+        // The delete and the restore go through, then writing the rollback revision fails
+        sqlx::query("CREATE TRIGGER refuse_revision BEFORE INSERT ON error_code_revisions BEGIN SELECT RAISE(ABORT, 'refused'); END")
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        assert!(repo.rollback_import_batch(&rollback).await.is_err());
+        assert!(repo.import_batch(batch.id).await.unwrap().unwrap().rolled_back_at.is_none());
+        assert_eq!(cause(&repo, changed).await.as_deref(), Some("second"));
+        assert_eq!(cause(&repo, created).await.as_deref(), Some("new"));
+        assert_eq!(repo.error_code_revisions(changed).await.unwrap().len(), 2);
+
+        sqlx::query("DROP TRIGGER refuse_revision").execute(&repo.pool).await.unwrap();
+        assert!(repo.rollback_import_batch(&rollback).await.unwrap());
+        assert!(repo.import_batch(batch.id).await.unwrap().unwrap().rolled_back_at.is_some());
+        assert_eq!(cause(&repo, changed).await.as_deref(), Some("first"));
+        assert!(repo.error_code(created).await.unwrap().is_none());
+
+        assert!(!repo.rollback_import_batch(&rollback).await.unwrap());
+    }
+}
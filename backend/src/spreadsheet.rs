@@ -0,0 +1,584 @@
+use calamine::{Reader, Xlsx};
+use encoding_rs::Encoding;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+
+/// Column headers of the error-code sheets, as `import_data` reads them and `export_errors` writes them.
+pub const COL_CODE: &str = "Code";
+pub const COL_CLASSIFICATION: &str = "Classification";
+pub const COL_CAUSE: &str = "Cause";
+pub const COL_MEASURES: &str = "Measures to take when an alert occurs";
+pub const COL_SOLUTION: &str = "Solution";
+pub const COL_ESTIMATED_PARTS: &str = "Estimated abnormal parts";
+pub const COL_CORRECTION: &str = "Correction";
+pub const COL_FAULTY_PART_ISOLATION: &str = "Faulty part isolation DIPSW";
+pub const COL_NOTE: &str = "Note";
+/// Only present in multi-model files; overrides the model given with the upload.
+pub const COL_MODEL: &str = "Model";
+/// Page of the service manual a row was extracted from (PDF extractions only).
+pub const COL_PAGE: &str = "Page";
+
+pub const ERROR_CODE_COLUMNS: [&str; 9] = [
+    COL_CODE,
+    COL_CLASSIFICATION,
+    COL_CAUSE,
+    COL_MEASURES,
+    COL_SOLUTION,
+    COL_ESTIMATED_PARTS,
+    COL_CORRECTION,
+    COL_FAULTY_PART_ISOLATION,
+    COL_NOTE,
+];
+
+/// Import fields a column mapping can fill, with the header each is read under once mapped.
+pub const MAPPED_FIELDS: [(&str, &str); 11] = [
+    ("model", COL_MODEL),
+    ("page", COL_PAGE),
+    ("code", COL_CODE),
+    ("classification", COL_CLASSIFICATION),
+    ("cause", COL_CAUSE),
+    ("measures", COL_MEASURES),
+    ("solution", COL_SOLUTION),
+    ("estimated_abnormal_parts", COL_ESTIMATED_PARTS),
+    ("correction", COL_CORRECTION),
+    ("faulty_part_isolation", COL_FAULTY_PART_ISOLATION),
+    ("note", COL_NOTE),
+];
+
+/// Fields a file must have a column for.
+pub const REQUIRED_FIELDS: [&str; 1] = ["code"];
+
+/// Name of the built-in mapping, which can't be saved over.
+pub const DEFAULT_MAPPING: &str = "default";
+
+/// The built-in mapping: the headers `export_errors` writes, and an older spelling of the isolation column.
+pub fn default_mapping() -> BTreeMap<String, Vec<String>> {
+    let mut columns: BTreeMap<String, Vec<String>> =
+        MAPPED_FIELDS.iter().map(|(field, header)| (field.to_string(), vec![header.to_string()])).collect();
+    if let Some(names) = columns.get_mut("faulty_part_isolation") {
+        names.push("Faulty part isolation".to_string());
+    }
+    columns
+}
+
+/// Checks a mapping before it is saved: known fields only, each with a column name, required fields present.
+pub fn validate_mapping(columns: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+    for (field, names) in columns {
+        if !MAPPED_FIELDS.iter().any(|(f, _)| f == field) {
+            let known: Vec<&str> = MAPPED_FIELDS.iter().map(|(f, _)| *f).collect();
+            return Err(format!("Unknown field {}, expected one of {}", field, known.join(", ")));
+        }
+        if names.iter().all(|n| n.trim().is_empty()) {
+            return Err(format!("No column name given for {}", field));
+        }
+    }
+    match REQUIRED_FIELDS.iter().find(|f| !columns.contains_key(**f)) {
+        Some(field) => Err(format!("The mapping must map {}", field)),
+        None => Ok(()),
+    }
+}
+
+/// Headers compare case-insensitively and ignoring repeated or trailing spaces.
+fn header_key(header: &str) -> String {
+    header.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// How a mapping reads a given file.
+#[derive(Debug, Default)]
+pub struct ColumnMatch {
+    /// Field → the file column it is read from
+    pub fields: BTreeMap<String, String>,
+    /// File columns no field reads
+    pub unmapped: Vec<String>,
+    /// Required fields the file has no column for
+    pub missing: Vec<&'static str>,
+}
+
+impl ColumnMatch {
+    /// Matches the file headers against a mapping; the first listed name found wins for each field.
+    pub fn new(columns: &BTreeMap<String, Vec<String>>, headers: &[String]) -> ColumnMatch {
+        let by_key: HashMap<String, &String> = headers.iter().map(|h| (header_key(h), h)).collect();
+        let fields: BTreeMap<String, String> = columns
+            .iter()
+            .filter_map(|(field, names)| {
+                let header = names.iter().find_map(|n| by_key.get(&header_key(n)))?;
+                Some((field.clone(), header.to_string()))
+            })
+            .collect();
+        let unmapped = headers.iter().filter(|h| !fields.values().any(|m| m == *h)).cloned().collect();
+        let missing = REQUIRED_FIELDS.iter().filter(|f| !fields.contains_key(**f)).copied().collect();
+        ColumnMatch { fields, unmapped, missing }
+    }
+
+    /// The record keyed by the `COL_*` headers, as `import_data` reads it.
+    pub fn apply(&self, record: &Record) -> Record {
+        MAPPED_FIELDS
+            .iter()
+            .filter_map(|(field, header)| {
+                let value = record.get(self.fields.get(*field)?)?;
+                Some((header.to_string(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Every header found in the records, sorted (JSON rows need not share their keys).
+pub fn record_headers(records: &[Record]) -> Vec<String> {
+    let mut headers: Vec<String> = records.iter().flat_map(|r| r.keys().cloned()).collect();
+    headers.sort();
+    headers.dedup();
+    headers
+}
+
+/// Column headers of DIP switch sheets: the `DipSwitchImport` fields. `defaults`, `values` and `rules`
+/// hold the JSON arrays of the import payload.
+pub const DIPSW_COLUMNS: [&str; 14] = [
+    "model_name",
+    "switch_number",
+    "bit_number",
+    "end_bit",
+    "function_name",
+    "setting_0",
+    "setting_1",
+    "default_val",
+    "risk_level",
+    "temporary_only",
+    "risk_note",
+    "defaults",
+    "values",
+    "rules",
+];
+
+/// One data row, keyed by header.
+pub type Record = HashMap<String, String>;
+
+/// Upload form fields that override what is detected in a CSV file.
+pub const CSV_OPTION_FIELDS: [&str; 3] = ["delimiter", "quote", "encoding"];
+
+/// Delimiters tried on CSV files, preferred in this order on a tie.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+/// Rows looked at to detect the delimiter.
+const SNIFF_ROWS: usize = 20;
+
+/// How to read a CSV upload; `None` fields are detected.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CsvOptions {
+    pub delimiter: Option<u8>,
+    pub quote: Option<u8>,
+    pub encoding: Option<&'static Encoding>,
+}
+
+impl CsvOptions {
+    /// From the `CSV_OPTION_FIELDS` of an upload. The delimiter may be given as `tab`; the encoding
+    /// takes the usual labels (`utf-8`, `windows-1252`, `latin1`, `utf-16le`, ...).
+    pub fn from_fields(fields: &HashMap<String, String>) -> Result<CsvOptions, String> {
+        let character = |name: &str| match fields.get(name).map(String::as_str) {
+            Some("tab" | "\\t" | "\t") => Ok(Some(b'\t')),
+            Some(v) if v.trim().is_empty() => Ok(None),
+            Some(v) if v.trim().len() == 1 && v.trim().is_ascii() => Ok(Some(v.trim().as_bytes()[0])),
+            Some(v) => Err(format!("{} must be a single character, got {:?}", name, v)),
+            None => Ok(None),
+        };
+        let encoding = match fields.get("encoding").map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("Unknown encoding {}", label))?),
+            None => None,
+        };
+        Ok(CsvOptions { delimiter: character("delimiter")?, quote: character("quote")?, encoding })
+    }
+}
+
+/// How a CSV upload was read, reported back with the import.
+#[derive(Serialize, Debug, Clone)]
+pub struct CsvDialect {
+    pub encoding: &'static str,
+    pub bom: bool,
+    pub delimiter: char,
+    pub quote: char,
+    /// Bytes that were not valid in the encoding and were replaced
+    pub replaced_characters: bool,
+}
+
+/// UTF-16 shows up as a zero byte next to every ASCII character; valid UTF-8 is taken as such and
+/// anything else is Windows-1252, which Excel writes on Italian Windows.
+fn sniff_encoding(bytes: &[u8]) -> &'static Encoding {
+    let sample = &bytes[..bytes.len().min(4096)];
+    let pairs = sample.len() / 2;
+    let zeros_at = |offset: usize| sample.iter().skip(offset).step_by(2).filter(|b| **b == 0).count();
+    if pairs > 0 && zeros_at(1) * 3 > pairs {
+        encoding_rs::UTF_16LE
+    } else if pairs > 0 && zeros_at(0) * 3 > pairs {
+        encoding_rs::UTF_16BE
+    } else if std::str::from_utf8(bytes).is_ok() {
+        encoding_rs::UTF_8
+    } else {
+        encoding_rs::WINDOWS_1252
+    }
+}
+
+/// Double quotes, unless fields are only ever quoted with single ones: some field starts with one, and
+/// every line where one does closes it too (Excel's text marker, as in `'80`, is never closed).
+fn sniff_quote(text: &str) -> u8 {
+    let lines: Vec<&str> = text.lines().take(SNIFF_ROWS).collect();
+    let opening = |quote: char| -> Vec<&str> {
+        lines
+            .iter()
+            .filter(|line| line.starts_with(quote) || DELIMITERS.iter().any(|d| line.contains(&format!("{}{}", *d as char, quote))))
+            .copied()
+            .collect()
+    };
+    let single = opening('\'');
+    if opening('"').is_empty() && !single.is_empty() && single.iter().all(|line| line.matches('\'').count() % 2 == 0) {
+        b'\''
+    } else {
+        b'"'
+    }
+}
+
+/// The delimiter that splits the most of the first rows into as many fields as the header, with
+/// more than one field; comma when none does.
+fn sniff_delimiter(text: &str, quote: u8) -> u8 {
+    let score = |delimiter: u8| {
+        let widths: Vec<usize> = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .quote(quote)
+            .from_reader(text.as_bytes())
+            .records()
+            .take(SNIFF_ROWS)
+            .filter_map(Result::ok)
+            .map(|r| r.len())
+            .collect();
+        match widths.first() {
+            Some(&header) if header > 1 => (widths.iter().filter(|w| **w == header).count(), header),
+            _ => (0, 0),
+        }
+    };
+    DELIMITERS
+        .iter()
+        .rev()
+        .map(|d| (score(*d), *d))
+        .max_by_key(|(score, _)| *score)
+        .filter(|(score, _)| score.0 > 0)
+        .map(|(_, d)| d)
+        .unwrap_or(b',')
+}
+
+/// Decodes a CSV upload and works out its delimiter and quote, honouring the overrides. A BOM picks
+/// the encoding unless one is given, and is dropped.
+fn decode_csv(bytes: &[u8], options: &CsvOptions) -> (String, CsvDialect) {
+    let bom = Encoding::for_bom(bytes);
+    let encoding = options.encoding.or(bom.map(|(e, _)| e)).unwrap_or_else(|| sniff_encoding(bytes));
+    let skip = match bom {
+        Some((e, len)) if e == encoding => len,
+        _ => 0,
+    };
+    let (text, replaced_characters) = encoding.decode_without_bom_handling(&bytes[skip..]);
+    let text = text.into_owned();
+    let quote = options.quote.unwrap_or_else(|| sniff_quote(&text));
+    let delimiter = options.delimiter.unwrap_or_else(|| sniff_delimiter(&text, quote));
+    let dialect = CsvDialect {
+        encoding: encoding.name(),
+        bom: skip > 0,
+        delimiter: delimiter as char,
+        quote: quote as char,
+        replaced_characters,
+    };
+    (text, dialect)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetFormat {
+    Csv,
+    Xlsx,
+    Json,
+}
+
+impl SheetFormat {
+    pub fn from_param(value: Option<&str>) -> Option<SheetFormat> {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("csv") => Some(SheetFormat::Csv),
+            Some("xlsx") => Some(SheetFormat::Xlsx),
+            Some("json") => Some(SheetFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of an upload from its content, falling back to the file extension.
+    pub fn detect(bytes: &[u8], filename: Option<&str>) -> SheetFormat {
+        if bytes.starts_with(b"PK\x03\x04") {
+            return SheetFormat::Xlsx;
+        }
+        if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+            return SheetFormat::Json;
+        }
+        match filename.and_then(|f| f.rsplit('.').next()).map(|e| e.to_lowercase()).as_deref() {
+            Some("xlsx") => SheetFormat::Xlsx,
+            Some("json") => SheetFormat::Json,
+            _ => SheetFormat::Csv,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            SheetFormat::Csv => "csv",
+            SheetFormat::Xlsx => "xlsx",
+            SheetFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SheetFormat::Csv => "text/csv; charset=utf-8",
+            SheetFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            SheetFormat::Json => "application/json",
+        }
+    }
+}
+
+/// The data rows of an uploaded sheet.
+pub struct Sheet {
+    pub records: Vec<Record>,
+    /// Worksheet the rows were read from (XLSX)
+    pub name: Option<String>,
+    /// How a CSV file was read
+    pub dialect: Option<CsvDialect>,
+}
+
+/// Reads every data row of an uploaded sheet. CSV rows that fail to parse are logged and skipped; for
+/// XLSX only the first worksheet is read.
+pub fn read_records(bytes: &[u8], format: SheetFormat, options: &CsvOptions) -> Result<Sheet, String> {
+    match format {
+        SheetFormat::Csv => {
+            let (text, dialect) = decode_csv(bytes, options);
+            if dialect.replaced_characters {
+                tracing::warn!("CSV upload is not valid {}: undecodable bytes were replaced", dialect.encoding);
+            }
+            let mut rdr = csv::ReaderBuilder::new()
+                .has_headers(true)
+                .flexible(true) // Allow variable fields just in case
+                .delimiter(dialect.delimiter as u8)
+                .quote(dialect.quote as u8)
+                .from_reader(text.as_bytes());
+            let mut records = Vec::new();
+            for result in rdr.deserialize::<Record>() {
+                match result {
+                    Ok(r) => records.push(r),
+                    Err(e) => tracing::error!("CSV Parse Error: {:?}", e),
+                }
+            }
+            Ok(Sheet { records, name: None, dialect: Some(dialect) })
+        }
+        SheetFormat::Xlsx => {
+            let mut workbook: Xlsx<_> = calamine::open_workbook_from_rs(Cursor::new(bytes))
+                .map_err(|e| format!("Invalid XLSX file: {}", e))?;
+            let name = workbook.sheet_names().first().cloned();
+            let range = workbook
+                .worksheet_range_at(0)
+                .ok_or_else(|| "The XLSX file has no worksheet".to_string())?
+                .map_err(|e| format!("Invalid XLSX worksheet: {}", e))?;
+            let mut rows = range.rows();
+            let headers: Vec<String> = match rows.next() {
+                Some(h) => h.iter().map(|c| c.to_string()).collect(),
+                None => return Ok(Sheet { records: Vec::new(), name, dialect: None }),
+            };
+            let records = rows
+                .map(|row| {
+                    headers
+                        .iter()
+                        .zip(row.iter())
+                        .map(|(h, c)| (h.clone(), c.to_string()))
+                        .collect()
+                })
+                .collect();
+            Ok(Sheet { records, name, dialect: None })
+        }
+        SheetFormat::Json => {
+            let rows: Vec<HashMap<String, serde_json::Value>> =
+                serde_json::from_slice(bytes).map_err(|e| format!("Invalid JSON file: {}", e))?;
+            let records = rows
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .filter_map(|(k, v)| match v {
+                            serde_json::Value::Null => None,
+                            serde_json::Value::String(s) => Some((k, s)),
+                            other => Some((k, other.to_string())),
+                        })
+                        .collect()
+                })
+                .collect();
+            Ok(Sheet { records, name: None, dialect: None })
+        }
+    }
+}
+
+/// Writes a sheet with the given headers. `None` cells are left empty (CSV, XLSX) or written as `null` (JSON).
+pub fn write_records(headers: &[&str], rows: &[Vec<Option<String>>], format: SheetFormat) -> Result<Vec<u8>, String> {
+    match format {
+        SheetFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(Vec::new());
+            wtr.write_record(headers).map_err(|e| e.to_string())?;
+            for row in rows {
+                wtr.write_record(row.iter().map(|c| c.as_deref().unwrap_or("")))
+                    .map_err(|e| e.to_string())?;
+            }
+            wtr.into_inner().map_err(|e| e.to_string())
+        }
+        SheetFormat::Xlsx => {
+            let mut workbook = rust_xlsxwriter::Workbook::new();
+            let bold = rust_xlsxwriter::Format::new().set_bold();
+            let sheet = workbook.add_worksheet();
+            for (col, header) in headers.iter().enumerate() {
+                sheet
+                    .write_string_with_format(0, col as u16, *header, &bold)
+                    .map_err(|e| e.to_string())?;
+            }
+            for (r, row) in rows.iter().enumerate() {
+                for (col, cell) in row.iter().enumerate() {
+                    if let Some(value) = cell {
+                        // Always strings, so codes like "0101" keep their leading zeros
+                        sheet
+                            .write_string((r + 1) as u32, col as u16, value)
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+            workbook.save_to_buffer().map_err(|e| e.to_string())
+        }
+        SheetFormat::Json => {
+            let objects: Vec<serde_json::Map<String, serde_json::Value>> = rows
+                .iter()
+                .map(|row| {
+                    headers
+                        .iter()
+                        .zip(row.iter())
+                        .map(|(h, c)| (h.to_string(), c.clone().map(serde_json::Value::String).unwrap_or(serde_json::Value::Null)))
+                        .collect()
+                })
+                .collect();
+            serde_json::to_vec_pretty(&objects).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Sheet {
+        read_records(bytes, SheetFormat::Csv, &CsvOptions::default()).unwrap()
+    }
+
+    fn cell<'a>(sheet: &'a Sheet, row: usize, header: &str) -> &'a str {
+        sheet.records[row].get(header).map(String::as_str).unwrap_or_else(|| panic!("no {} in {:?}", header, sheet.records[row]))
+    }
+
+    fn utf16(text: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let units = bom.then_some(0xFEFF).into_iter().chain(text.encode_utf16());
+        for unit in units {
+            bytes.extend(if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() });
+        }
+        bytes
+    }
+
+    #[test]
+    fn detects_the_delimiter() {
+        for (text, delimiter) in [
+            ("Code,Cause\nC-1,Toner\nC-2,Fuser\n", ','),
+            ("Code;Cause\nC-1;Toner\nC-2;Fuser\n", ';'),
+            ("Code\tCause\nC-1\tToner\nC-2\tFuser\n", '\t'),
+            ("Code|Cause\nC-1|Toner\nC-2|Fuser\n", '|'),
+            // Decimal commas in a semicolon file
+            ("Code;Value;Note\nC-1;1,5;a, b\nC-2;2,5;c\n", ';'),
+            // Commas in the text, tabs between the fields
+            ("Code\tCause\tNote\nC-1\tToner low, replace\tsee, manual\n", '\t'),
+            // A single column is read whole
+            ("Code\nC-1\nC-2\n", ','),
+        ] {
+            let sheet = read(text.as_bytes());
+            assert_eq!(sheet.dialect.as_ref().unwrap().delimiter, delimiter, "{:?}", text);
+            assert_eq!(cell(&sheet, 0, "Code"), "C-1", "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn keeps_quoted_delimiters() {
+        let sheet = read(b"Code,Cause,Note\nC-1,\"Toner low, replace\",\"say \"\"hi\"\"\"\nC-2,Fuser,\n");
+        let dialect = sheet.dialect.as_ref().unwrap();
+        assert_eq!((dialect.delimiter, dialect.quote), (',', '"'));
+        assert_eq!(cell(&sheet, 0, "Cause"), "Toner low, replace");
+        assert_eq!(cell(&sheet, 0, "Note"), "say \"hi\"");
+
+        let sheet = read(b"Code;Cause\nC-1;\"a;b\"\nC-2;\"c;d\"\n");
+        assert_eq!(sheet.dialect.as_ref().unwrap().delimiter, ';');
+        assert_eq!(cell(&sheet, 1, "Cause"), "c;d");
+
+        let sheet = read(b"Code;Cause\nC-1;'a;b'\nC-2;'c'\n");
+        assert_eq!(sheet.dialect.as_ref().unwrap().quote, '\'');
+        assert_eq!(cell(&sheet, 0, "Cause"), "a;b");
+    }
+
+    #[test]
+    fn apostrophes_are_not_quotes() {
+        let sheet = read("Code;Cause\nC-1;Controllare l'unità\nC-2;Sostituire\n".as_bytes());
+        assert_eq!(sheet.dialect.as_ref().unwrap().quote, '"');
+        assert_eq!(cell(&sheet, 0, "Cause"), "Controllare l'unità");
+
+        // Excel's text marker opens no quoted field
+        let sheet = read(b"Code;Note\nC-1;'80 fogli\nC-2;ok\nC-3;'x\n");
+        assert_eq!(sheet.dialect.as_ref().unwrap().quote, '"');
+        assert_eq!(sheet.records.len(), 3);
+        assert_eq!(cell(&sheet, 0, "Note"), "'80 fogli");
+    }
+
+    #[test]
+    fn detects_the_encoding() {
+        let text = "Code;Cause\nC-1;Perché è caldo\n";
+
+        let mut with_bom = b"\xEF\xBB\xBF".to_vec();
+        with_bom.extend(text.as_bytes());
+        for (bytes, encoding, bom) in [
+            (text.as_bytes().to_vec(), "UTF-8", false),
+            (with_bom, "UTF-8", true),
+            (utf16(text, false, true), "UTF-16LE", true),
+            (utf16(text, false, false), "UTF-16LE", false),
+            (utf16(text, true, true), "UTF-16BE", true),
+            (utf16(text, true, false), "UTF-16BE", false),
+            (encoding_rs::WINDOWS_1252.encode(text).0.into_owned(), "windows-1252", false),
+        ] {
+            let sheet = read(&bytes);
+            let dialect = sheet.dialect.as_ref().unwrap();
+            assert_eq!((dialect.encoding, dialect.bom), (encoding, bom));
+            assert!(!dialect.replaced_characters, "{}", encoding);
+            // The BOM is not part of the first header
+            assert_eq!(cell(&sheet, 0, "Code"), "C-1", "{}", encoding);
+            assert_eq!(cell(&sheet, 0, "Cause"), "Perché è caldo", "{}", encoding);
+        }
+    }
+
+    #[test]
+    fn options_override_detection() {
+        let fields: HashMap<String, String> = [("delimiter", "tab"), ("quote", "'"), ("encoding", "latin1")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let options = CsvOptions::from_fields(&fields).unwrap();
+        let sheet = read_records(b"Code\tCause\nC-1\t'caf\xE9\tbar'\n", SheetFormat::Csv, &options).unwrap();
+        let dialect = sheet.dialect.as_ref().unwrap();
+        assert_eq!((dialect.delimiter, dialect.quote, dialect.encoding), ('\t', '\'', "windows-1252"));
+        assert_eq!(cell(&sheet, 0, "Cause"), "café\tbar");
+
+        // Bytes that are not valid in a forced encoding are replaced, and reported
+        let options = CsvOptions { encoding: Some(encoding_rs::UTF_8), ..Default::default() };
+        let sheet = read_records(b"Code,Cause\nC-1,caf\xE9\n", SheetFormat::Csv, &options).unwrap();
+        assert!(sheet.dialect.as_ref().unwrap().replaced_characters);
+
+        for (name, value) in [("delimiter", ";;"), ("encoding", "klingon")] {
+            let fields = HashMap::from([(name.to_string(), value.to_string())]);
+            assert!(CsvOptions::from_fields(&fields).is_err(), "{}={}", name, value);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::models::DipSwitch;
+use std::fmt::Write;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Setting column of one row: "0: .. / 1: .." for single bits, the value table for multi-bit fields.
+fn settings_html(sw: &DipSwitch) -> String {
+    if sw.width() > 1 {
+        return sw
+            .values
+            .iter()
+            .map(|v| {
+                let bits = format!("{:0width$b}", v.value, width = sw.width() as usize);
+                format!("<div><b>{}</b> {}</div>", bits, escape(v.meaning.as_deref().unwrap_or("")))
+            })
+            .collect();
+    }
+    let mut html = String::new();
+    for (value, text) in [(0, &sw.setting_0), (1, &sw.setting_1)] {
+        if let Some(text) = text {
+            let _ = write!(html, "<div><b>{}</b> {}</div>", value, escape(text));
+        }
+    }
+    html
+}
+
+/// Renders the printable DIP switch worksheet of a model: one table per switch, with the default to compare
+/// against and an empty "Actual" column to fill in by hand. Reserved bits ("-") are left out.
+pub fn render(model: &str, variant: Option<&str>, switches: &[DipSwitch]) -> String {
+    let mut html = String::new();
+    let title = format!("DIP switch worksheet – {}", escape(model));
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+    @page {{ size: A4; margin: 12mm; }}
+    body {{ font-family: Arial, Helvetica, sans-serif; font-size: 10pt; color: #000; }}
+    h1 {{ font-size: 15pt; margin: 0 0 4mm 0; }}
+    h2 {{ font-size: 12pt; margin: 6mm 0 2mm 0; }}
+    .machine td {{ padding: 1mm 3mm 1mm 0; }}
+    .machine .blank {{ border-bottom: 1px solid #000; width: 55mm; }}
+    table.sw {{ width: 100%; border-collapse: collapse; page-break-inside: auto; }}
+    table.sw tr {{ page-break-inside: avoid; }}
+    table.sw th, table.sw td {{ border: 1px solid #555; padding: 1.5mm; vertical-align: top; text-align: left; }}
+    table.sw th {{ background: #e6e6e6; }}
+    .bit {{ width: 12mm; white-space: nowrap; }}
+    .default {{ width: 16mm; text-align: center; }}
+    .actual {{ width: 20mm; }}
+    .risk {{ font-size: 8pt; font-weight: bold; }}
+    .section {{ page-break-inside: avoid; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<table class="machine">
+    <tr><td>Serial number</td><td class="blank"></td><td>Date</td><td class="blank"></td></tr>
+    <tr><td>Customer</td><td class="blank"></td><td>Technician</td><td class="blank"></td></tr>
+    <tr><td>Defaults</td><td>{variant}</td><td></td><td></td></tr>
+</table>
+"#,
+        title = title,
+        variant = escape(variant.unwrap_or("standard")),
+    );
+
+    let mut current_switch = None;
+    for sw in switches {
+        let function = sw.function_name.as_deref().unwrap_or("").trim();
+        if function.is_empty() || function == "-" {
+            continue;
+        }
+        if current_switch != Some(sw.switch_number) {
+            if current_switch.is_some() {
+                html.push_str("</tbody></table></div>\n");
+            }
+            current_switch = Some(sw.switch_number);
+            let _ = write!(
+                html,
+                r#"<div class="section"><h2>Switch {}</h2>
+<table class="sw"><thead><tr><th class="bit">Bit</th><th>Function</th><th>Setting</th><th class="default">Default</th><th class="actual">Actual</th></tr></thead><tbody>
+"#,
+                sw.switch_number
+            );
+        }
+
+        let bits = match sw.end_bit {
+            Some(end) => format!("{}-{} – {}-{}", sw.switch_number, sw.bit_number, sw.switch_number, end),
+            None => format!("{}-{}", sw.switch_number, sw.bit_number),
+        };
+        let mut notes = String::new();
+        if sw.risk_level == "medium" || sw.risk_level == "high" {
+            let _ = write!(notes, r#"<div class="risk">⚠ {} risk</div>"#, escape(&sw.risk_level));
+        }
+        if sw.temporary_only {
+            notes.push_str(r#"<div class="risk">Temporary use only – restore after service</div>"#);
+        }
+        let _ = writeln!(
+            html,
+            "<tr><td class=\"bit\">{}</td><td>{}{}</td><td>{}</td><td class=\"default\">{}</td><td class=\"actual\"></td></tr>",
+            bits,
+            escape(function).replace('\n', "<br>"),
+            notes,
+            settings_html(sw),
+            escape(sw.default_val.as_deref().unwrap_or("")),
+        );
+    }
+    if current_switch.is_some() {
+        html.push_str("</tbody></table></div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
@@ -1,12 +1,15 @@
 'use server';
 
 const API_URL = process.env.API_URL || 'http://127.0.0.1:8080/api';
+// Server-side only: the backend API key (technician role for searches, editor for imports)
+const API_HEADERS: Record<string, string> = process.env.API_KEY ? { Authorization: `Bearer ${process.env.API_KEY}` } : {};
 
 export async function importErrorCodes(formData: FormData) {
     try {
         console.log(`[Import] Sending request to ${API_URL}/import`);
         const res = await fetch(`${API_URL}/import`, {
             method: 'POST',
+            headers: API_HEADERS,
             body: formData,
             // Duplex needed for some node fetch implementations with streams, though usually optional for FormData
             // @ts-expect-error - duplex is not in standard RequestInit type yet
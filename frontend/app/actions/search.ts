@@ -3,10 +3,12 @@
 import { supabase } from '@/lib/supabase';
 
 const API_URL = process.env.API_URL || 'http://127.0.0.1:8080/api';
+// Server-side only: the backend API key (technician role for searches, editor for imports)
+const API_HEADERS: Record<string, string> = process.env.API_KEY ? { Authorization: `Bearer ${process.env.API_KEY}` } : {};
 
 export async function getPrinters() {
     try {
-        const res = await fetch(`${API_URL}/printers`, { cache: 'no-store' });
+        const res = await fetch(`${API_URL}/printers`, { cache: 'no-store', headers: API_HEADERS });
         if (!res.ok) return [];
         return await res.json();
     } catch (e) {
@@ -30,7 +32,7 @@ export async function searchErrors(modelName: string, codeQuery: string, exact:
         // Note: Backend might not support 'exact' yet, so we filter locally too to be safe.
         // if (exact) params.append('exact', 'true'); 
 
-        const res = await fetch(`${API_URL}/errors?${params.toString()}`, { cache: 'no-store' });
+        const res = await fetch(`${API_URL}/errors?${params.toString()}`, { cache: 'no-store', headers: API_HEADERS });
         if (!res.ok) return [];
 
         const data = (await res.json()) as ErrorCode[];
@@ -87,7 +89,7 @@ export async function getAllModelCodes(modelName: string) {
             summary: '1'
         });
 
-        const res = await fetch(`${API_URL}/errors?${params.toString()}`, { cache: 'no-store' });
+        const res = await fetch(`${API_URL}/errors?${params.toString()}`, { cache: 'no-store', headers: API_HEADERS });
         if (!res.ok) return [];
         return await res.json();
     } catch (e) {
@@ -96,6 +98,17 @@ export async function getAllModelCodes(modelName: string) {
     }
 }
 
+export interface DipSwitchDefault {
+    variant: string;
+    value: string | null;
+}
+
+export interface DipSwitchValue {
+    value: number;
+    bits: string;
+    meaning: string | null;
+}
+
 export interface DipSwitch {
     id: string;
     model_name: string;
@@ -105,12 +118,19 @@ export interface DipSwitch {
     setting_0: string;
     setting_1: string;
     default_val: string;
+    end_bit: number | null;
+    risk_level: 'none' | 'low' | 'medium' | 'high';
+    temporary_only: boolean;
+    risk_note: string | null;
+    defaults: DipSwitchDefault[];
+    values: DipSwitchValue[];
 }
 
-export async function getDipSwitches(modelName: string) {
+export async function getDipSwitches(modelName: string, variant?: string) {
     try {
         const params = new URLSearchParams({ model: modelName });
-        const res = await fetch(`${API_URL}/dipswitches?${params.toString()}`, { cache: 'no-store' });
+        if (variant) params.append('variant', variant);
+        const res = await fetch(`${API_URL}/dipswitches?${params.toString()}`, { cache: 'no-store', headers: API_HEADERS });
         if (!res.ok) return [];
         return (await res.json()) as DipSwitch[];
     } catch (e) {
@@ -34,15 +34,84 @@ export default function ImportPage() {
                     />
                 </div>
                 <div>
-                    <label className="block mb-1">File (CSV/XLSX)</label>
+                    <label className="block mb-1">File (CSV/XLSX/JSON)</label>
                     <input
                         name="file"
                         type="file"
-                        accept=".csv, .xlsx"
+                        accept=".csv, .xlsx, .json"
                         required
                         className="w-full"
                     />
                 </div>
+                <div>
+                    <label className="block mb-1">Column mapping (optional)</label>
+                    <input
+                        name="mapping"
+                        type="text"
+                        placeholder="detected from the headers"
+                        className="w-full p-2 rounded bg-gray-800 border border-gray-700"
+                    />
+                </div>
+                <div>
+                    <label className="block mb-1">CSV delimiter and encoding (optional)</label>
+                    <div className="flex gap-2">
+                        <select
+                            name="delimiter"
+                            defaultValue=""
+                            className="w-1/2 p-2 rounded bg-gray-800 border border-gray-700"
+                        >
+                            <option value="">Detect delimiter</option>
+                            <option value=",">Comma</option>
+                            <option value=";">Semicolon</option>
+                            <option value="tab">Tab</option>
+                            <option value="|">Pipe</option>
+                        </select>
+                        <select
+                            name="encoding"
+                            defaultValue=""
+                            className="w-1/2 p-2 rounded bg-gray-800 border border-gray-700"
+                        >
+                            <option value="">Detect encoding</option>
+                            <option value="utf-8">UTF-8</option>
+                            <option value="windows-1252">Windows-1252</option>
+                            <option value="utf-16le">UTF-16</option>
+                        </select>
+                    </div>
+                </div>
+                <div>
+                    <label className="block mb-1">Stored texts</label>
+                    <select
+                        name="merge"
+                        defaultValue="keep-manual"
+                        className="w-full p-2 rounded bg-gray-800 border border-gray-700"
+                    >
+                        <option value="keep-manual">Keep fields edited by hand</option>
+                        <option value="if-non-empty">Replace unless the file cell is empty</option>
+                        <option value="overwrite">Always replace with the file</option>
+                    </select>
+                </div>
+                <div>
+                    <label className="block mb-1">Stored codes missing from the file</label>
+                    <select
+                        name="missing"
+                        defaultValue="report"
+                        className="w-full p-2 rounded bg-gray-800 border border-gray-700"
+                    >
+                        <option value="report">Only report them</option>
+                        <option value="obsolete">Mark them obsolete</option>
+                    </select>
+                </div>
+                <div>
+                    <label className="block mb-1">If a row fails</label>
+                    <select
+                        name="mode"
+                        defaultValue="atomic"
+                        className="w-full p-2 rounded bg-gray-800 border border-gray-700"
+                    >
+                        <option value="atomic">Import nothing</option>
+                        <option value="keep-valid">Import the valid rows</option>
+                    </select>
+                </div>
                 <button
                     type="submit"
                     disabled={loading}
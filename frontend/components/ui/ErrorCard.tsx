@@ -37,6 +37,13 @@ interface ErrorProps {
         faulty_part_isolation?: string;
         note?: string;
         parts?: SparePart[];
+        provenance?: {
+            kind?: string | null;
+            document?: string | null;
+            sheet?: string | null;
+            row?: number | null;
+            page?: number | null;
+        };
     };
     onDipSwitchClick?: (sw: number, bit: number) => void;
 }
@@ -367,6 +374,15 @@ export function ErrorCard({ error, onDipSwitchClick }: ErrorProps) {
                             )}
                         </Box>
                     )}
+
+                    {error.provenance?.document && (
+                        <Typography variant="caption" color="text.secondary">
+                            Source: {error.provenance.document}
+                            {error.provenance.sheet && `, sheet ${error.provenance.sheet}`}
+                            {error.provenance.page ? `, page ${error.provenance.page}` : error.provenance.row && `, row ${error.provenance.row}`}
+                            {error.provenance.kind === 'manual' && ' (edited by hand)'}
+                        </Typography>
+                    )}
                 </Stack>
             </CardContent>
         </Card>
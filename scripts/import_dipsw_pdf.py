@@ -4,6 +4,7 @@ import json
 import requests
 import sys
 import argparse
+import os
 
 # --- Parsing Logic (Adapted from parse_dipsw.py) ---
 
@@ -29,6 +30,16 @@ def parse_settings(text):
         
     return setting0, setting1
 
+def parse_defaults(row, labels):
+    # Default columns start at index 4 (Def1/Def2/Def3, one per destination or sub-model)
+    defaults = []
+    for idx in range(4, min(len(row), 7)):
+        value = clean_text(row[idx]) if row[idx] else None
+        if not value: continue
+        label = labels[idx - 4] if idx - 4 < len(labels) and labels[idx - 4] else f"Def{idx - 3}"
+        defaults.append({"variant": label, "value": value})
+    return defaults
+
 def process_pdf(pdf_path, model_name):
     json_rows = []
     
@@ -36,6 +47,7 @@ def process_pdf(pdf_path, model_name):
     
     with pdfplumber.open(pdf_path) as pdf:
         current_sw = None
+        default_labels = []
         
         for i, page in enumerate(pdf.pages):
             tables = page.extract_tables()
@@ -49,7 +61,10 @@ def process_pdf(pdf_path, model_name):
                     r0 = str(row[0]) if row[0] else ""
                     r1 = str(row[1]) if row[1] else ""
                     
-                    if "Bit" in r1: continue
+                    if "Bit" in r1:
+                        # Header row: remember the default column labels for this table
+                        default_labels = [clean_text(c).replace('\n', ' ') if c else None for c in row[4:7]]
+                        continue
                     if "[1]" in r0 or "[5]" in r0: continue
                     
                     # Parse SW Number
@@ -81,6 +96,7 @@ def process_pdf(pdf_path, model_name):
                         val_def = clean_text(row[6])
                     elif len(row) >= 5 and row[4]:
                          val_def = clean_text(row[4])
+                    defaults = parse_defaults(row, default_labels)
 
                     row_obj = {
                         "model_name": model_name,
@@ -89,29 +105,18 @@ def process_pdf(pdf_path, model_name):
                         "function_name": func_name,
                         "setting_0": set0,
                         "setting_1": set1,
-                        "default_val": val_def
+                        "default_val": val_def,
+                        "defaults": defaults
                     }
                     json_rows.append(row_obj)
                     
-                    # Multi-bit expansion
+                    # Multi-bit field: "1-7=" inside the settings means this bit governs up to 1-7
                     if settings_raw:
-                        # e.g. "1-7=" inside settings
                         range_matches = re.findall(rf'{current_sw}-(\d+)=', settings_raw)
                         if range_matches:
                             max_ref = max(int(m) for m in range_matches)
                             if max_ref > bit_num:
-                                for b in range(bit_num + 1, max_ref + 1):
-                                    ref_text = f"See SW {current_sw}-{bit_num}"
-                                    ex_obj = {
-                                        "model_name": model_name,
-                                        "switch_number": current_sw,
-                                        "bit_number": b,
-                                        "function_name": ref_text,
-                                        "setting_0": ref_text,
-                                        "setting_1": None,
-                                        "default_val": val_def
-                                    }
-                                    json_rows.append(ex_obj)
+                                row_obj["end_bit"] = max_ref
     
     # --- MANUAL PATCHES ---
     # Inject known missing switches that PDF extraction missed
@@ -137,30 +142,30 @@ def process_pdf(pdf_path, model_name):
         # Remove existing partial entries if any
         json_rows = [r for r in json_rows if not (r['switch_number'] == 1 and r['bit_number'] in [5, 6, 7])]
         
-        print(f"Injecting DipSW 1-5, 1-6, 1-7 for {model_name}")
+        print(f"Injecting DipSW 1-5..1-7 field for {model_name}")
         
         full_desc = (
             "Number of the allowed print quantity after the machine reaches the maintenance count\n\n"
-            "Combination Table (Bit 1-7 | 1-6 | 1-5):\n"
-            "0 | 0 | 0  : 1,000 Prints\n"
-            "0 | 0 | 1  : 2,000 Prints\n"
-            "0 | 1 | 0  : 3,000 Prints\n"
-            "0 | 1 | 1  : 4,000 Prints\n"
-            "1 | 0 | 0  : 5,000 Prints\n"
-            "1 | 0 | 1  : 1,000 Prints\n"
             "Note: See service manual for defaults."
         )
         
-        for bit in [5, 6, 7]:
-            json_rows.append({
-                "model_name": model_name,
-                "switch_number": 1,
-                "bit_number": bit,
-                "function_name": full_desc,
-                "setting_0": "See Combination Table in Function Description",
-                "setting_1": "See Combination Table in Function Description",
-                "default_val": "0"
-            })
+        prints = ["1,000", "2,000", "3,000", "4,000", "5,000", "1,000"]
+        json_rows.append({
+            "model_name": model_name,
+            "switch_number": 1,
+            "bit_number": 5,
+            "end_bit": 7,
+            "function_name": full_desc,
+            "setting_0": None,
+            "setting_1": None,
+            "default_val": "0",
+            "values": [{"value": v, "meaning": f"{p} Prints"} for v, p in enumerate(prints)]
+        })
+
+    # Drop single-bit rows already covered by a multi-bit field of the same switch
+    covered = {(r['switch_number'], b) for r in json_rows if r.get('end_bit')
+               for b in range(r['bit_number'] + 1, r['end_bit'] + 1)}
+    json_rows = [r for r in json_rows if (r['switch_number'], r['bit_number']) not in covered]
 
     return json_rows
 
@@ -183,9 +188,18 @@ def main():
         # Upload
         url = "http://localhost:8080/api/import-dipsw"
         try:
-            r = requests.post(url, json=rows)
+            api_key = os.environ.get("API_KEY")
+            headers = {"Authorization": f"Bearer {api_key}"} if api_key else {}
+            r = requests.post(url, json=rows, headers=headers)
             if r.status_code == 200:
-                print(f"SUCCESS: Uploaded for {model}")
+                report = r.json()
+                for m in report.get("models", []):
+                    print(f"{m['model_name']}: {m['inserted']} inserted, {m['updated']} updated, "
+                          f"{m['rejected']} rejected, {m['failed']} failed")
+                for item in report.get("items", []):
+                    if item["status"] in ("rejected", "failed"):
+                        print(f"  SW {item['switch_number']}-{item['bit_number']}: {item['status']} - {item['message']}")
+                print(f"{'SUCCESS' if report.get('success') else 'PARTIAL'}: Uploaded for {model}")
             else:
                 print(f"FAILED: {r.status_code} - {r.text}")
         except Exception as e:
@@ -54,7 +54,9 @@ async function run() {
             const fileParam = absTempPath.replace(/"/g, '\\"');
 
             // Use -F "file=@PATH"
-            const curlCmd = `curl -X POST -F "model=${modelParam}" -F "file=@${fileParam}" "${API_URL}"`;
+            // Editor API key from the environment, if the backend requires one
+            const authHeader = process.env.API_KEY ? `-H "Authorization: Bearer ${process.env.API_KEY}" ` : '';
+            const curlCmd = `curl -X POST ${authHeader}-F "model=${modelParam}" -F "file=@${fileParam}" "${API_URL}"`;
 
             // console.log(`Running: ${curlCmd}`); 
 